@@ -1,10 +1,87 @@
 use std::cell::UnsafeCell;
-use std::mem;
+use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
-use std::sync::atomic::Ordering::{self, Acquire, Release, SeqCst};
-use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use std::thread;
 
 pub struct AtomicCell<T> {
     in_use: AtomicBool,
     data: UnsafeCell<T>,
 }
+
+// Borrows are exclusive, so sharing the cell only ever hands the contents to
+// one thread at a time.
+unsafe impl<T: Send> Sync for AtomicCell<T> {}
+
+// Exclusive access to the contents of an `AtomicCell`, released on drop.
+pub struct AtomicGuard<'a, T> {
+    cell: &'a AtomicCell<T>,
+    // Opts out of the auto traits `&AtomicCell<T>` would give, which would
+    // let a shared guard hand out `&T` on several threads at once.
+    _marker: PhantomData<*mut T>,
+}
+
+// Moving the guard moves the exclusive access, and sharing it shares `&T`.
+unsafe impl<T: Send> Send for AtomicGuard<'_, T> {}
+unsafe impl<T: Sync> Sync for AtomicGuard<'_, T> {}
+
+impl<T> AtomicCell<T> {
+    pub fn new(t: T) -> AtomicCell<T> {
+        AtomicCell {
+            in_use: AtomicBool::new(false),
+            data: UnsafeCell::new(t),
+        }
+    }
+
+    // Returns `None` if the cell is already borrowed.
+    pub fn try_borrow(&self) -> Option<AtomicGuard<'_, T>> {
+        match self.in_use.compare_exchange(false, true, Acquire, Relaxed) {
+            Ok(_) => Some(AtomicGuard {
+                cell: self,
+                _marker: PhantomData,
+            }),
+            Err(_) => None,
+        }
+    }
+
+    // Spins until the cell can be borrowed.
+    pub fn borrow(&self) -> AtomicGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_borrow() {
+                return guard;
+            }
+            thread::yield_now();
+        }
+    }
+
+    // Exclusive access by construction, so neither of these has to check
+    // for a borrow.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T> Deref for AtomicGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.cell.data.get() }
+    }
+}
+
+impl<T> DerefMut for AtomicGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.cell.data.get() }
+    }
+}
+
+impl<T> Drop for AtomicGuard<'_, T> {
+    fn drop(&mut self) {
+        self.cell.in_use.store(false, Release);
+    }
+}
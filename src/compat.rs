@@ -0,0 +1,166 @@
+use std::future;
+use std::pin::Pin;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::SeqCst;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+
+use crate::task::{self, Task};
+use crate::Future;
+
+// A handle for whatever feeds a future's sources, e.g. the sending side of a
+// channel, to report that the future may be able to make progress.
+#[derive(Clone)]
+pub struct ProgressWaker {
+    inner: Arc<Progress>,
+}
+
+struct Progress {
+    made: AtomicBool,
+    waker: Mutex<Option<Waker>>,
+}
+
+impl ProgressWaker {
+    // Wakes the task driving the future, or, if it's mid-poll, has it polled
+    // once more as soon as that poll comes up empty.
+    pub fn notify(&self) {
+        self.inner.made.store(true, SeqCst);
+        if let Some(waker) = self.inner.waker.lock().unwrap().take() {
+            waker.wake();
+        }
+    }
+}
+
+pub struct CompatSmart<A> {
+    future: Option<A>,
+    progress: ProgressWaker,
+}
+
+impl<A> CompatSmart<A> {
+    pub(crate) fn new(future: A) -> CompatSmart<A> {
+        CompatSmart {
+            future: Some(future),
+            progress: ProgressWaker {
+                inner: Arc::new(Progress {
+                    made: AtomicBool::new(false),
+                    waker: Mutex::new(None),
+                }),
+            },
+        }
+    }
+
+    pub fn progress_waker(&self) -> ProgressWaker {
+        self.progress.clone()
+    }
+}
+
+// The inner future is only ever moved, never pinned.
+impl<A> Unpin for CompatSmart<A> {}
+
+impl<A> future::Future for CompatSmart<A>
+where
+    A: Future,
+{
+    type Output = Result<A::Item, A::Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let progress = self.progress.inner.clone();
+        // Register before polling, so a notification arriving mid-poll
+        // isn't lost.
+        *progress.waker.lock().unwrap() = Some(cx.waker().clone());
+        progress.made.store(false, SeqCst);
+        let future = self.future.take().expect("polled after completion");
+        match future.poll() {
+            Ok(result) => Poll::Ready(result),
+            Err(future) => {
+                self.future = Some(future);
+                if progress.made.load(SeqCst) {
+                    cx.waker().wake_by_ref();
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+// Adapts one of this crate's futures to `std::future::Future`. Each poll
+// runs as a task backed by the executor's waker, so a source which parks
+// the current task, such as a `Oneshot` or a `Delay`, wakes the executor
+// when it's ready. A source which doesn't, such as a bare
+// `std::sync::mpsc::Receiver`, needs `CompatSmart` instead.
+pub struct IntoStd<A> {
+    future: Option<A>,
+}
+
+impl<A> IntoStd<A> {
+    pub(crate) fn new(future: A) -> IntoStd<A> {
+        IntoStd {
+            future: Some(future),
+        }
+    }
+}
+
+impl<A> Unpin for IntoStd<A> {}
+
+impl<A> future::Future for IntoStd<A>
+where
+    A: Future,
+{
+    type Output = Result<A::Item, A::Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let future = self.future.take().expect("polled after completion");
+        let task = Task::from_waker(cx.waker().clone());
+        match task.enter(|| future.poll()) {
+            Ok(result) => Poll::Ready(result),
+            Err(future) => {
+                self.future = Some(future);
+                Poll::Pending
+            }
+        }
+    }
+}
+
+// Adapts a `std::future::Future` with a `Result` output to this crate's
+// `Future`, handing itself back while the inner future is pending. The
+// inner future is woken through the task polling it.
+pub struct FromStd<F> {
+    future: Pin<Box<F>>,
+}
+
+impl<F> FromStd<F> {
+    pub fn new(future: F) -> FromStd<F> {
+        FromStd {
+            future: Box::pin(future),
+        }
+    }
+}
+
+struct TaskWaker(Task);
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+impl<F, T, E> Future for FromStd<F>
+where
+    F: future::Future<Output = Result<T, E>>,
+{
+    type Item = T;
+    type Error = E;
+
+    fn poll(mut self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let waker = Waker::from(Arc::new(TaskWaker(task::park())));
+        let mut cx = Context::from_waker(&waker);
+        match self.future.as_mut().poll(&mut cx) {
+            Poll::Ready(result) => Ok(result),
+            Poll::Pending => Err(self),
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::task::{Wake, Waker};
+use std::thread;
+use std::time::Instant;
+
+use crate::oneshot::{oneshot, Canceled, Oneshot};
+use crate::stream::{FuturesUnordered, Stream};
+use crate::task::{Backoff, Task};
+use crate::timer;
+use crate::{BoxFuture, Future, Never};
+
+// One poll of a spawned future, returning whether it's done with.
+type Step = Box<dyn FnMut() -> bool + Send>;
+
+// A fixed set of threads which futures can be handed to. The threads exit
+// once every clone of the pool is gone and the work queued so far is done.
+#[derive(Clone)]
+pub struct CpuPool {
+    tx: Sender<Arc<Spawned>>,
+}
+
+impl CpuPool {
+    pub fn new(threads: usize) -> CpuPool {
+        assert!(threads > 0, "a CpuPool needs at least one thread");
+        let (tx, rx) = channel::<Arc<Spawned>>();
+        let rx = Arc::new(Mutex::new(rx));
+        for _ in 0..threads {
+            let rx = rx.clone();
+            thread::spawn(move || loop {
+                let spawned = match rx.lock().unwrap().recv() {
+                    Ok(spawned) => spawned,
+                    Err(_) => return,
+                };
+                spawned.run();
+            });
+        }
+        CpuPool { tx }
+    }
+
+    // Drives `future` to completion on the pool's threads. The handle
+    // resolves to its outcome, and dropping the handle stops the work at the
+    // next poll. A pending future gives its thread up until it's unparked,
+    // so futures on the pool can wait on each other.
+    pub fn spawn<F>(&self, future: F) -> CpuFuture<F::Item, F::Error>
+    where
+        F: Future + Send + 'static,
+        F::Item: Send + 'static,
+        F::Error: Send + 'static,
+    {
+        let (complete, result) = oneshot();
+        let canceled = Arc::new(AtomicBool::new(false));
+        let flag = canceled.clone();
+        let mut future = Some(future);
+        let mut complete = Some(complete);
+        // A panicking future only loses its own result, and the panic goes
+        // on to whoever waits on the `CpuFuture`.
+        let step = move || {
+            if flag.load(Ordering::SeqCst) {
+                return true;
+            }
+            let f = future.take().expect("polled a finished CpuPool future");
+            let outcome = match panic::catch_unwind(AssertUnwindSafe(|| f.poll())) {
+                Ok(Ok(outcome)) => Ok(outcome),
+                Ok(Err(f)) => {
+                    future = Some(f);
+                    return false;
+                }
+                Err(payload) => Err(payload),
+            };
+            complete.take().unwrap().complete(outcome);
+            true
+        };
+        let spawned = Arc::new(Spawned {
+            slot: Mutex::new(Slot {
+                step: Some(Box::new(step)),
+                queued: true,
+                notified: false,
+                backoff: Backoff::new(),
+            }),
+            tx: self.tx.clone(),
+        });
+        self.tx
+            .send(spawned)
+            .expect("CpuPool threads have all exited");
+        CpuFuture {
+            result: Some(result),
+            canceled,
+        }
+    }
+}
+
+// A future on a `CpuPool`, which sits in the queue while it can move on and
+// is put back there when its task is unparked.
+struct Spawned {
+    slot: Mutex<Slot>,
+    tx: Sender<Arc<Spawned>>,
+}
+
+struct Slot {
+    // Taken by the thread polling it, and gone once it's finished.
+    step: Option<Step>,
+    queued: bool,
+    // Unparked while being polled, so it has to be polled again.
+    notified: bool,
+    // For sources which never unpark, the timer re-polls it after this.
+    backoff: Backoff,
+}
+
+impl Spawned {
+    fn run(self: Arc<Self>) {
+        let mut step = {
+            let mut slot = self.slot.lock().unwrap();
+            slot.queued = false;
+            slot.notified = false;
+            match slot.step.take() {
+                Some(step) => step,
+                None => return,
+            }
+        };
+        let task = Task::from_waker(Waker::from(self.clone()));
+        if task.enter(&mut step) {
+            return;
+        }
+        let mut slot = self.slot.lock().unwrap();
+        slot.step = Some(step);
+        if slot.notified {
+            slot.backoff.reset();
+            slot.queued = true;
+            drop(slot);
+            let _ = self.tx.send(self.clone());
+        } else {
+            timer::register(Instant::now() + slot.backoff.next_timeout(), task);
+        }
+    }
+}
+
+impl Wake for Spawned {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        let mut slot = self.slot.lock().unwrap();
+        if slot.step.is_none() {
+            slot.notified = true;
+            return;
+        }
+        if !slot.queued {
+            slot.queued = true;
+            drop(slot);
+            let _ = self.tx.send(self.clone());
+        }
+    }
+}
+
+// The outcome of a future running on a `CpuPool`. Waiting on it resumes the
+// panic if the future panicked.
+pub struct CpuFuture<T, E> {
+    // Only `None` while being polled.
+    result: Option<Oneshot<thread::Result<Result<T, E>>>>,
+    canceled: Arc<AtomicBool>,
+}
+
+impl<T, E> Future for CpuFuture<T, E> {
+    type Item = T;
+    type Error = E;
+
+    fn poll(mut self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let result = self.result.take().expect("CpuFuture lost its result");
+        match result.poll() {
+            Ok(Ok(Ok(outcome))) => Ok(outcome),
+            Ok(Ok(Err(payload))) => panic::resume_unwind(payload),
+            Ok(Err(Canceled)) => panic!("CpuPool dropped the future spawned on it"),
+            Err(result) => {
+                self.result = Some(result);
+                Err(self)
+            }
+        }
+    }
+}
+
+impl<T, E> Drop for CpuFuture<T, E> {
+    fn drop(&mut self) {
+        self.canceled.store(true, Ordering::SeqCst);
+    }
+}
+
+type LocalJob = BoxFuture<'static, (), Never>;
+
+// Drives futures on the current thread, which needn't be `Send`. `run`
+// polls the future it's given along with every future spawned so far, each
+// spawned one as a task of its own, and parks the thread while none of
+// them can move on. A spawned future is polled again once its task is
+// unparked, or, in case its source can't unpark anything, whenever the
+// thread's park times out. Clones share the same queue, so a running future
+// can hold one to spawn more.
+#[derive(Clone, Default)]
+pub struct LocalRunner {
+    inner: Rc<LocalInner>,
+}
+
+#[derive(Default)]
+struct LocalInner {
+    // Spawned since the running set was last polled.
+    incoming: RefCell<Vec<LocalJob>>,
+    running: RefCell<Option<FuturesUnordered<LocalJob>>>,
+}
+
+impl LocalRunner {
+    pub fn new() -> LocalRunner {
+        LocalRunner::default()
+    }
+
+    // Queues `future` to be driven by `run` alongside everything else,
+    // throwing its outcome away.
+    pub fn spawn_local<F>(&self, future: F)
+    where
+        F: Future + 'static,
+    {
+        let job = future.then(|_| Ok::<(), Never>(())).boxed();
+        self.inner.incoming.borrow_mut().push(job);
+    }
+
+    // Blocks until `future` resolves, running spawned futures meanwhile.
+    // Those still pending when it does stay queued for the next `run`.
+    pub fn run<F>(&self, future: F) -> Result<F::Item, F::Error>
+    where
+        F: Future,
+    {
+        let task = Task::new();
+        let mut backoff = Backoff::new();
+        let mut future = future;
+        let mut sweep = false;
+        loop {
+            match task.enter(|| future.poll()) {
+                Ok(result) => return result,
+                Err(f) => future = f,
+            }
+            let finished = task.enter(|| self.poll_spawned(sweep));
+            if finished || !self.inner.incoming.borrow().is_empty() {
+                backoff.reset();
+                sweep = false;
+                continue;
+            }
+            sweep = !backoff.park(&task);
+        }
+    }
+
+    // Polls the spawned futures until none is ready, returning whether any
+    // of them finished. With `sweep`, each of them is polled at least once.
+    fn poll_spawned(&self, sweep: bool) -> bool {
+        let mut running = self
+            .inner
+            .running
+            .take()
+            .unwrap_or_else(FuturesUnordered::unparked_only);
+        if sweep {
+            running.unpark_all();
+        }
+        for job in self.inner.incoming.take() {
+            running.push(job);
+        }
+        let mut finished = false;
+        loop {
+            match running.poll() {
+                Ok(Ok(Some(((), rest)))) => {
+                    running = rest;
+                    finished = true;
+                }
+                Ok(Ok(None)) => {
+                    running = FuturesUnordered::unparked_only();
+                    break;
+                }
+                Ok(Err(never)) => match never {},
+                Err(rest) => {
+                    running = rest;
+                    break;
+                }
+            }
+        }
+        *self.inner.running.borrow_mut() = Some(running);
+        finished
+    }
+}
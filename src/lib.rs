@@ -1,5 +1,32 @@
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::marker;
-use std::sync::mpsc::{Receiver, RecvError, TryRecvError};
+use std::mem;
+use std::panic;
+use std::rc::Rc;
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::mpsc::{Receiver, RecvError, Sender, TryRecvError};
+use std::sync::{Arc, Mutex, Once};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::cell::AtomicCell;
+
+pub mod cell;
+pub mod compat;
+pub mod executor;
+pub mod oneshot;
+pub mod sink;
+pub mod stream;
+pub mod sync;
+pub mod task;
+pub mod timer;
+
+pub use oneshot::{oneshot, Complete, Oneshot};
+pub use sink::Sink;
+
+use stream::Stream;
 
 pub trait IntoFuture {
     type Future: Future<Item = Self::Item, Error = Self::Error>;
@@ -26,14 +53,79 @@ pub trait Future {
     where
         Self: Sized;
 
+    // Blocks the current thread until the future resolves. Between polls the
+    // thread parks until a source unparks its task; sources which can't, like
+    // an mpsc `Receiver`, are looked at again after a short timeout which
+    // grows while nothing happens. A future which can never resolve, like
+    // `Empty`, blocks forever.
+    fn wait(self) -> Result<Self::Item, Self::Error>
+    where
+        Self: Sized,
+    {
+        let task = task::Task::new();
+        let mut backoff = task::Backoff::new();
+        let mut future = self;
+        loop {
+            match task.enter(|| future.poll()) {
+                Ok(result) => return result,
+                Err(f) => future = f,
+            }
+            backoff.park(&task);
+        }
+    }
+
+    // Like `wait`, but gives up after `max` polls, handing back the
+    // unresolved future.
+    fn wait_spins(self, max: usize) -> Result<Result<Self::Item, Self::Error>, Self>
+    where
+        Self: Sized,
+    {
+        let mut future = self;
+        for _ in 0..max {
+            match future.poll() {
+                Ok(result) => return Ok(result),
+                Err(f) => future = f,
+            }
+        }
+        Err(future)
+    }
+
+    // The name of the state the future is in, for `trace` to report.
+    // Combinators which move through stages, like `AndThen` or `Join`, say
+    // which stage is active; anything else is just "running".
+    fn state_name(&self) -> &'static str {
+        "running"
+    }
+
+    // Does one unit of work: the same as `poll`, but spelled as a `Step`.
+    fn step(self) -> Step<Self::Item, Self::Error, Self>
+    where
+        Self: Sized,
+    {
+        self.poll().into()
+    }
+
     // dyn https://doc.rust-lang.org/book/ch17-02-trait-objects.html
-    fn boxed<'a>(self) -> Box<dyn Future<Item = Self::Item, Error = Self::Error> + 'a>
+    // `poll` takes `self` by value, so a `dyn Future` can't be polled; the
+    // box holds a `PollBoxed` instead, which can.
+    fn boxed<'a>(self) -> BoxFuture<'a, Self::Item, Self::Error>
     where
         Self: Sized + 'a,
     {
         Box::new(self)
     }
 
+    // Applies `f` to the whole future, so reusable stacks of adapters can be
+    // written as functions and still be chained fluently.
+    fn pipe<F, R>(self, f: F) -> R
+    where
+        F: FnOnce(Self) -> R,
+        Self: Sized,
+    {
+        f(self)
+    }
+
+    #[allow(clippy::redundant_field_names)]
     fn map<F, U>(self, f: F) -> Map<Self, F>
     where
         F: FnOnce(Self::Item) -> U,
@@ -42,6 +134,17 @@ pub trait Future {
         Map { future: self, f: f }
     }
 
+    // Calls `f` with a reference to the item once the future succeeds,
+    // passing the item on unchanged. `f` isn't called on error.
+    fn inspect<F>(self, f: F) -> Inspect<Self, F>
+    where
+        F: FnOnce(&Self::Item),
+        Self: Sized,
+    {
+        Inspect { future: self, f }
+    }
+
+    #[allow(clippy::redundant_field_names)]
     fn map_err<F, E>(self, f: F) -> MapErr<Self, F>
     where
         F: FnOnce(Self::Error) -> E,
@@ -50,6 +153,31 @@ pub trait Future {
         MapErr { future: self, f: f }
     }
 
+    // Transforms the whole outcome once the future is ready, changing both
+    // its item and error types in one step. Unlike `then`, `f` can't start
+    // another future, so readiness is unaffected.
+    fn map_poll<F, T2, E2>(self, f: F) -> MapPoll<Self, F>
+    where
+        F: FnMut(Result<Self::Item, Self::Error>) -> Result<T2, E2>,
+        Self: Sized,
+    {
+        MapPoll { future: self, f }
+    }
+
+    // Converts any error into `E` with `From`, so futures with different
+    // error types can be combined without a closure at every call site.
+    #[allow(clippy::wrong_self_convention)]
+    fn from_err<E>(self) -> FromErr<Self, E>
+    where
+        E: From<Self::Error>,
+        Self: Sized,
+    {
+        FromErr {
+            future: self,
+            _marker: marker::PhantomData,
+        }
+    }
+
     fn and_then<F, B>(self, f: F) -> AndThen<Self, B, F>
     // where https://doc.rust-lang.org/book/ch10-02-traits.html#clearer-trait-bounds-with-where-clauses
     where
@@ -62,6 +190,65 @@ pub trait Future {
         }
     }
 
+    // Continues with `f` only if `pred` accepts the item; otherwise the item
+    // is passed through as is.
+    fn and_then_if<P, F, B>(self, pred: P, f: F) -> AndThenIf<Self, B, P, F>
+    where
+        P: FnOnce(&Self::Item) -> bool,
+        F: FnOnce(Self::Item) -> B,
+        B: IntoFuture<Item = Self::Item, Error = Self::Error>,
+        Self: Sized,
+    {
+        AndThenIf {
+            future: _AndThenIf::First(self, pred, f),
+        }
+    }
+
+    // Like `and_then`, but if the second stage fails its error is paired with
+    // a clone of the first stage's item. Errors from the first stage itself
+    // come with `None`.
+    fn and_then_keep<F, B>(self, f: F) -> AndThenKeep<Self, B, F>
+    where
+        F: FnOnce(Self::Item) -> B,
+        B: IntoFuture<Error = Self::Error>,
+        Self::Item: Clone,
+        Self: Sized,
+    {
+        AndThenKeep {
+            future: _AndThenKeep::First(self, f),
+        }
+    }
+
+    // Like `and_then`, but if the second stage fails it's rebuilt from a
+    // clone of the first stage's item and run again, up to `max` more times.
+    // The first stage only ever runs once.
+    fn and_then_retry<F, B>(self, max: usize, f: F) -> AndThenRetry<Self, B, F>
+    where
+        F: FnMut(Self::Item) -> B,
+        B: IntoFuture<Error = Self::Error>,
+        Self::Item: Clone,
+        Self: Sized,
+    {
+        AndThenRetry {
+            state: _AndThenRetry::First(self),
+            f,
+            remaining: max,
+        }
+    }
+
+    // Builds a second future from a reference to this one's item, resolving
+    // to both items. The first item is held on to while the second runs.
+    fn zip_with_dependent<F, B>(self, f: F) -> ZipDependent<Self, B, F>
+    where
+        F: FnOnce(&Self::Item) -> B,
+        B: IntoFuture<Error = Self::Error>,
+        Self: Sized,
+    {
+        ZipDependent {
+            state: _ZipDependent::First(self, f),
+        }
+    }
+
     fn or_else<F, B>(self, f: F) -> OrElse<Self, B, F>
     where
         F: FnOnce(Self::Error) -> B,
@@ -73,14 +260,149 @@ pub trait Future {
         }
     }
 
+    // Resolves to `fallback` instead of failing when `p` accepts the error,
+    // e.g. to treat "not found" as empty; other errors pass through.
+    fn ignore_err_if<P>(self, p: P, fallback: Self::Item) -> IgnoreErrIf<Self, P>
+    where
+        P: FnOnce(&Self::Error) -> bool,
+        Self: Sized,
+    {
+        IgnoreErrIf {
+            future: self,
+            p,
+            fallback,
+        }
+    }
+
+    // Continues with the future built by `f` from the whole outcome of this
+    // one, whether it succeeded or failed.
+    fn then<F, B>(self, f: F) -> Then<Self, B, F>
+    where
+        F: FnOnce(Result<Self::Item, Self::Error>) -> B,
+        B: IntoFuture,
+        Self: Sized,
+    {
+        Then {
+            future: _Then::First(self, f),
+        }
+    }
+
+    // Continues with `f`'s future on success or `g`'s on failure. Whatever
+    // `g`'s future resolves to becomes the error, so both paths share `BF`'s
+    // error type.
+    fn map_both_async<F, G, BF, BG>(self, f: F, g: G) -> MapBothAsync<Self, BF, BG, F, G>
+    where
+        F: FnOnce(Self::Item) -> BF,
+        G: FnOnce(Self::Error) -> BG,
+        BF: Future,
+        BG: Future<Item = BF::Error, Error = BF::Error>,
+        Self: Sized,
+    {
+        MapBothAsync {
+            state: _MapBothAsync::First(self, f, g),
+        }
+    }
+
+    // For a future resolving to another future, resolves to that one's
+    // outcome. An error from this future is lifted into the inner future's
+    // error type.
+    fn flatten(self) -> Flatten<Self>
+    where
+        Self::Item: IntoFuture,
+        <Self::Item as IntoFuture>::Error: From<Self::Error>,
+        Self: Sized,
+    {
+        Flatten {
+            state: _Flatten::First(self),
+        }
+    }
+
+    // For a future resolving to a `Result`, resolves to what's inside it.
+    // Since the inner outcome is already there, nothing is left to poll once
+    // this future is done.
+    fn flatten_result<T, E>(self) -> FlattenResult<Self>
+    where
+        Self: Future<Item = Result<T, E>> + Sized,
+        E: From<Self::Error>,
+    {
+        FlattenResult { future: self }
+    }
+
+    // Wraps this future so a poll past its outcome is well defined: see
+    // `Fuse`.
+    fn fuse(self) -> Fuse<Self>
+    where
+        Self: Sized,
+    {
+        Fuse { future: Some(self) }
+    }
+
     fn select<B>(self, other: B) -> Select<Self, B::Future>
     where
         B: IntoFuture<Item = Self::Item, Error = Self::Error>,
         Self: Sized,
     {
         Select {
+            a: self.fuse(),
+            b: other.into_future().fuse(),
+        }
+    }
+
+    // Like `select`, but the winner's outcome comes with the other future,
+    // which can still be driven to get its own outcome.
+    fn select_then<B>(self, other: B) -> SelectThen<Self, B::Future>
+    where
+        B: IntoFuture<Item = Self::Item, Error = Self::Error>,
+        Self: Sized,
+    {
+        SelectThen {
+            a: self,
+            b: other.into_future(),
+        }
+    }
+
+    // Races two futures which needn't share any types, resolving to the
+    // whole outcome of whichever finished first: `Left` for this one and
+    // `Right` for `other`. The loser is dropped.
+    fn select_either<B>(self, other: B) -> SelectEither<Self, B::Future>
+    where
+        B: IntoFuture,
+        Self: Sized,
+    {
+        SelectEither {
+            a: self,
+            b: other.into_future(),
+        }
+    }
+
+    // Races two futures which needn't share any types, like `select_either`,
+    // but hands the loser back alongside the winner's outcome so it can be
+    // polled further: `Left` if this one finished first, `Right` for `other`.
+    fn select2<B>(self, other: B) -> Select2<Self, B::Future>
+    where
+        B: IntoFuture,
+        Self: Sized,
+    {
+        Select2 {
+            a: self,
+            b: other.into_future(),
+        }
+    }
+
+    // Races two futures with different item types, mapping the winner's item
+    // through `f` or `g` depending on which side finished first.
+    fn select_map<B, F, G, U>(self, other: B, f: F, g: G) -> SelectMap<Self, B::Future, F, G>
+    where
+        B: IntoFuture<Error = Self::Error>,
+        F: FnOnce(Self::Item) -> U,
+        G: FnOnce(B::Item) -> U,
+        Self: Sized,
+    {
+        SelectMap {
             a: self,
             b: other.into_future(),
+            f,
+            g,
         }
     }
 
@@ -90,271 +412,3656 @@ pub trait Future {
         Self: Sized,
     {
         Join {
+            a: self.fuse(),
+            b: other.into_future().fuse(),
+            a_item: None,
+            b_item: None,
+        }
+    }
+
+    // Like `join`, for three, four or five futures sharing an error type,
+    // resolving to a flat tuple of their items.
+    fn join3<B, C>(self, b: B, c: C) -> Join3<Self, B::Future, C::Future>
+    where
+        B: IntoFuture<Error = Self::Error>,
+        C: IntoFuture<Error = Self::Error>,
+        Self: Sized,
+    {
+        Join3 {
+            inner: self.join(b).join(c),
+        }
+    }
+
+    fn join4<B, C, D>(self, b: B, c: C, d: D) -> Join4<Self, B::Future, C::Future, D::Future>
+    where
+        B: IntoFuture<Error = Self::Error>,
+        C: IntoFuture<Error = Self::Error>,
+        D: IntoFuture<Error = Self::Error>,
+        Self: Sized,
+    {
+        Join4 {
+            inner: self.join3(b, c).join(d),
+        }
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn join5<B, C, D, E>(
+        self,
+        b: B,
+        c: C,
+        d: D,
+        e: E,
+    ) -> Join5<Self, B::Future, C::Future, D::Future, E::Future>
+    where
+        B: IntoFuture<Error = Self::Error>,
+        C: IntoFuture<Error = Self::Error>,
+        D: IntoFuture<Error = Self::Error>,
+        E: IntoFuture<Error = Self::Error>,
+        Self: Sized,
+    {
+        Join5 {
+            inner: self.join4(b, c, d).join(e),
+        }
+    }
+
+    fn then_stream<F, S>(self, f: F) -> stream::ThenStream<Self, S, F>
+    where
+        F: FnOnce(Result<Self::Item, Self::Error>) -> S,
+        S: Stream,
+        Self: Sized,
+    {
+        stream::ThenStream::new(self, f)
+    }
+
+    // Gives up once the inner future has been polled `max` times without
+    // resolving, handing it back alongside the `TimeoutError` so the caller can
+    // keep driving it. The inner future's own outcome becomes the item.
+    fn timeout_return(self, max: usize) -> TimeoutReturn<Self>
+    where
+        Self: Sized,
+    {
+        TimeoutReturn {
+            future: self,
+            remaining: max,
+        }
+    }
+
+    // On failure hands the error back together with a spent `KeepError`
+    // holding a copy of it, which `last_error` can inspect and which fails
+    // the same way again if polled.
+    fn keep_error(self) -> KeepError<Self>
+    where
+        Self::Error: Clone,
+        Self: Sized,
+    {
+        KeepError {
+            future: Some(self),
+            error: None,
+        }
+    }
+
+    // Fails with `TimeoutError` once `TIMEOUT_TICKS` ticks have been pulled
+    // from `ticks` without this future resolving, so the timeout can be
+    // driven by any event source. If `ticks` runs dry first the timeout
+    // never fires; an error from it fails the future.
+    fn timeout_stream<S>(self, ticks: S) -> TimeoutStream<Self, S>
+    where
+        S: Stream<Item = (), Error = Self::Error>,
+        Self::Error: From<TimeoutError>,
+        Self: Sized,
+    {
+        TimeoutStream {
+            future: self,
+            ticks: Some(ticks),
+            remaining: TIMEOUT_TICKS,
+        }
+    }
+
+    // Fails with `TimeoutError` if this future hasn't resolved once
+    // `duration` has passed, as measured by the background timer thread.
+    fn timeout(self, duration: Duration) -> timer::Timeout<Self>
+    where
+        Self::Error: From<TimeoutError>,
+        Self: Sized,
+    {
+        timer::Timeout::new(self, duration)
+    }
+
+    // Turns a panic while polling this future into a value: the item is the
+    // future's own outcome, or the panic's payload if it panicked. Lets
+    // whatever drives it carry on with other work.
+    fn catch_unwind(self) -> CatchUnwind<Self>
+    where
+        Self: panic::UnwindSafe + Sized,
+    {
+        CatchUnwind { future: self }
+    }
+
+    // An iterator whose first `next()` blocks until this future resolves and
+    // yields its outcome; every later call returns `None`.
+    fn into_blocking_iter(self) -> BlockingOnce<Self>
+    where
+        Self: Sized,
+    {
+        BlockingOnce { future: Some(self) }
+    }
+
+    // Adapts the future to `std::future::Future`, woken by whichever source
+    // parks the task polling it. See `compat::IntoStd`.
+    fn into_std(self) -> compat::IntoStd<Self>
+    where
+        Self: Sized,
+    {
+        compat::IntoStd::new(self)
+    }
+
+    // Adapts the future to `std::future::Future`. Rather than asking to be
+    // polled again straight away whenever it's pending, it waits for a
+    // `ProgressWaker` from `CompatSmart::progress_waker` to signal that the
+    // inner future may be able to move on.
+    fn to_std_smart(self) -> compat::CompatSmart<Self>
+    where
+        Self: Sized,
+    {
+        compat::CompatSmart::new(self)
+    }
+
+    // Pairs the future with a handle reporting how many times it has been
+    // polled so far, e.g. for progress displays.
+    fn observable(self) -> (Observable<Self>, ProgressHandle)
+    where
+        Self: Sized,
+    {
+        let polls = Rc::new(Cell::new(0));
+        (
+            Observable {
+                future: self,
+                polls: polls.clone(),
+            },
+            ProgressHandle { polls },
+        )
+    }
+
+    // Calls `f` with the running poll count, starting at 1, before each poll
+    // of the inner future.
+    fn on_poll<F>(self, f: F) -> OnPoll<Self, F>
+    where
+        F: FnMut(usize),
+        Self: Sized,
+    {
+        OnPoll {
+            future: self,
+            f,
+            polls: 0,
+        }
+    }
+
+    // Like `join`, but each poll drives `self` before `other` when
+    // `self_first` is set and `other` before `self` otherwise. The ordering is
+    // deterministic: the side polled first always makes progress first, and
+    // if both sides fail on the same poll it's that side's error which is
+    // returned.
+    fn join_priority<B>(self, other: B, self_first: bool) -> JoinPriority<Self, B::Future>
+    where
+        B: IntoFuture<Error = Self::Error>,
+        Self: Sized,
+    {
+        JoinPriority {
             state: _Join::Both(self, other.into_future()),
+            self_first,
         }
     }
+
+    // A stream of what happens as this future is driven: `Polled` for every
+    // poll that comes up empty, then `Ready` with the outcome as the last item.
+    fn poll_trace(self) -> stream::PollTrace<Self>
+    where
+        Self: Sized,
+    {
+        stream::PollTrace::new(self)
+    }
+
+    // A stream with the outcome as its only item, `OnNext` for success and
+    // `OnError` for failure, so the stream itself never fails.
+    fn materialize(self) -> stream::Materialize<Self>
+    where
+        Self: Sized,
+    {
+        stream::Materialize::new(self)
+    }
+
+    // Fails with `Canceled` on the first poll to find `flag` set, which can be
+    // flipped by whoever else holds it. While the flag is borrowed the poll
+    // comes up empty without touching the inner future.
+    fn until_flag(self, flag: Rc<AtomicCell<bool>>) -> UntilFlag<Self>
+    where
+        Self::Error: From<Canceled>,
+        Self: Sized,
+    {
+        UntilFlag { future: self, flag }
+    }
+
+    // Pairs this future with a handle which cancels it from anywhere, even
+    // another thread: the next poll after `cancel` fails with `Canceled` and
+    // drops nothing but the inner future's chance to finish. A task parked on
+    // the future is unparked so that poll comes promptly.
+    fn cancellable(self) -> (Cancellable<Self>, CancellationHandle)
+    where
+        Self::Error: From<Canceled>,
+        Self: Sized,
+    {
+        let handle = CancellationHandle {
+            inner: Arc::new(CancelInner {
+                canceled: AtomicBool::new(false),
+                task: AtomicCell::new(None),
+            }),
+        };
+        let future = Cancellable {
+            future: self,
+            handle: handle.clone(),
+        };
+        (future, handle)
+    }
+
+    // Sends the outcome over `tx` once the future resolves, then resolves to
+    // `()`. A receiver which has gone away is not an error.
+    fn send_result(self, tx: Sender<Result<Self::Item, Self::Error>>) -> SendResult<Self>
+    where
+        Self: Sized,
+    {
+        SendResult { future: self, tx }
+    }
+
+    // Reports every poll to stderr: the poll count, the state the future
+    // was in, whether it stayed pending and the time since the first poll.
+    fn trace(self, label: &'static str) -> Trace<Self, StderrSink>
+    where
+        Self: Sized,
+    {
+        self.trace_with(label, StderrSink)
+    }
+
+    // Like `trace`, reporting to `sink` instead.
+    fn trace_with<K>(self, label: &'static str, sink: K) -> Trace<Self, K>
+    where
+        K: TraceSink,
+        Self: Sized,
+    {
+        Trace {
+            future: self,
+            label,
+            sink,
+            polls: 0,
+            started: None,
+        }
+    }
+
+    // Appends `"poll"` to `log` on every poll, then `"ready_ok"` or
+    // `"ready_err"` once the future resolves.
+    fn trace_into(self, log: Rc<RefCell<Vec<&'static str>>>) -> TraceInto<Self>
+    where
+        Self: Sized,
+    {
+        TraceInto { future: self, log }
+    }
+
+    // Records `{name}.polls` for every poll, and `{name}.completions` or
+    // `{name}.errors` once the future succeeds or fails, into `metrics`.
+    fn with_metrics(self, name: &'static str, metrics: Metrics) -> WithMetrics<Self>
+    where
+        Self: Sized,
+    {
+        WithMetrics {
+            future: self,
+            name,
+            metrics,
+        }
+    }
+
+    // Hands the outcome to a callback once the future resolves; the returned
+    // future resolves to `()` and can't fail itself.
+    fn for_result<F>(self, f: F) -> ForResult<Self, F>
+    where
+        F: FnOnce(Result<Self::Item, Self::Error>),
+        Self: Sized,
+    {
+        ForResult { future: self, f }
+    }
+
+    // Bounds how many pending polls each stage of a chain may take. Stages
+    // are marked by calling `StageCounter::advance` on `stages`, typically at
+    // the start of an `and_then` closure; stage `i` may then stay pending for
+    // at most `budgets[i]` polls (stages past the end of `budgets` are
+    // unbounded) before the chain fails with `StageTimeout { stage: i }`. A
+    // pending poll counts towards the stage that's current when it returns.
+    fn with_stage_timeouts(self, budgets: Vec<usize>, stages: StageCounter) -> StageTimeouts<Self>
+    where
+        Self::Error: From<StageTimeout>,
+        Self: Sized,
+    {
+        StageTimeouts {
+            future: self,
+            budgets,
+            stage: stages.current(),
+            stages,
+            spins: 0,
+        }
+    }
+
+    // Withholds the inner future's outcome for `polls` more polls once it's
+    // ready, counting the poll on which it became ready, to simulate latency
+    // in delivering an already-computed result.
+    fn hold(self, polls: usize) -> Hold<Self>
+    where
+        Self: Sized,
+    {
+        Hold {
+            state: _Hold::Running(self, polls),
+        }
+    }
+
+    // A handle which can be cloned, with every clone resolving to the same
+    // reference-counted outcome. Whichever clone is polled drives the inner
+    // future, which runs to completion exactly once; unlike `shared_sync`,
+    // other clones stay pending meanwhile rather than blocking.
+    fn shared(self) -> Shared<Self>
+    where
+        Self: Sized,
+    {
+        Shared {
+            inner: Arc::new(AtomicCell::new(SharedState {
+                future: Some(self),
+                result: None,
+                waiters: Vec::new(),
+                poisoned: false,
+            })),
+        }
+    }
+
+    // A handle which can be cloned and sent to other threads, all of which
+    // see the same outcome. The first clone to be polled drives the inner
+    // future to completion on its own thread; polling any other clone
+    // meanwhile blocks until the outcome is ready.
+    fn shared_sync(self) -> SharedSync<Self>
+    where
+        Self::Item: Clone + Send,
+        Self::Error: Clone + Send,
+        Self: Send + Sized,
+    {
+        SharedSync {
+            inner: Arc::new(SharedSyncInner {
+                once: Once::new(),
+                future: Mutex::new(Some(self)),
+                result: Mutex::new(None),
+            }),
+        }
+    }
+
+    // Drives both futures to completion, resolving to both outcomes whether
+    // they succeeded or not. The joined future itself never fails.
+    fn join_both_results<B>(self, other: B) -> JoinBothResults<Self, B::Future>
+    where
+        B: IntoFuture,
+        Self: Sized,
+    {
+        JoinBothResults {
+            a: _JoinCollect::Pending(self),
+            b: _JoinCollect::Pending(other.into_future()),
+        }
+    }
+
+    // Unlike `join`, both futures are always driven to completion so that
+    // neither side's error is lost.
+    fn join_collect<B>(self, other: B) -> JoinCollect<Self, B::Future>
+    where
+        B: IntoFuture<Error = Self::Error>,
+        Self: Sized,
+    {
+        JoinCollect {
+            a: _JoinCollect::Pending(self),
+            b: _JoinCollect::Pending(other.into_future()),
+        }
+    }
+}
+
+// An error type with no values, for futures which can't fail.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Never {}
+
+// The object-safe counterpart of `Future`, polled through a `Box` and
+// handing back a `BoxFuture` while pending. Every future implements it.
+pub trait PollBoxed<'a> {
+    type Item;
+    type Error;
+
+    #[allow(clippy::type_complexity)]
+    fn poll_boxed(
+        self: Box<Self>,
+    ) -> Result<Result<Self::Item, Self::Error>, BoxFuture<'a, Self::Item, Self::Error>>;
+}
+
+// A future of any type, so different futures can share a collection.
+pub type BoxFuture<'a, T, E> = Box<dyn PollBoxed<'a, Item = T, Error = E> + 'a>;
+
+impl<'a, F> PollBoxed<'a> for F
+where
+    F: Future + 'a,
+{
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll_boxed(
+        self: Box<Self>,
+    ) -> Result<Result<Self::Item, Self::Error>, BoxFuture<'a, Self::Item, Self::Error>> {
+        (*self)
+            .poll()
+            .map_err(|f| Box::new(f) as BoxFuture<'a, _, _>)
+    }
+}
+
+impl<'a, T, E> Future for BoxFuture<'a, T, E> {
+    type Item = T;
+    type Error = E;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        self.poll_boxed()
+    }
+}
+
+// The outcome of one `step` of a future `F`: either it's `Done`, or it
+// yields itself back to be stepped again later.
+#[derive(Debug, PartialEq)]
+pub enum Step<T, E, F> {
+    Done(Result<T, E>),
+    Yield(F),
+}
+
+impl<T, E, F> From<Result<Result<T, E>, F>> for Step<T, E, F> {
+    fn from(poll: Result<Result<T, E>, F>) -> Step<T, E, F> {
+        match poll {
+            Ok(result) => Step::Done(result),
+            Err(f) => Step::Yield(f),
+        }
+    }
+}
+
+impl<T, E, F> From<Step<T, E, F>> for Result<Result<T, E>, F> {
+    fn from(step: Step<T, E, F>) -> Result<Result<T, E>, F> {
+        match step {
+            Step::Done(result) => Ok(result),
+            Step::Yield(f) => Err(f),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct FutureResult<T, E> {
+    inner: Result<T, E>,
+}
+
+impl<T, E> IntoFuture for Result<T, E> {
+    type Future = FutureResult<T, E>;
+    type Item = T;
+    type Error = E;
+
+    fn into_future(self) -> FutureResult<T, E> {
+        FutureResult { inner: self }
+    }
+}
+
+impl<T, E> Future for FutureResult<T, E> {
+    type Item = T;
+    type Error = E;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        Ok(self.inner)
+    }
+}
+
+// A future which is immediately ready with `t`.
+pub fn ok<T, E>(t: T) -> FutureResult<T, E> {
+    Ok(t).into_future()
+}
+
+// A future which immediately fails with `e`.
+pub fn err<T, E>(e: E) -> FutureResult<T, E> {
+    Err(e).into_future()
+}
+
+// The same as `ok` and `err`, under the names the rest of the ecosystem
+// uses.
+pub fn finished<T, E>(t: T) -> FutureResult<T, E> {
+    ok(t)
+}
+
+pub fn failed<T, E>(e: E) -> FutureResult<T, E> {
+    err(e)
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Map<A, F> {
+    future: A,
+    f: F,
+}
+
+impl<U, A, F> Future for Map<A, F>
+where
+    A: Future,
+    F: FnOnce(A::Item) -> U,
+{
+    type Item = U;
+    type Error = A::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        match self.future.poll() {
+            Ok(result) => Ok(result.map(self.f)),
+            Err(f) => Err(Map {
+                future: f,
+                f: self.f,
+            }),
+        }
+    }
+}
+
+pub struct Inspect<A, F> {
+    future: A,
+    f: F,
+}
+
+impl<A, F> Future for Inspect<A, F>
+where
+    A: Future,
+    F: FnOnce(&A::Item),
+{
+    type Item = A::Item;
+    type Error = A::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        match self.future.poll() {
+            Ok(Ok(item)) => {
+                (self.f)(&item);
+                Ok(Ok(item))
+            }
+            Ok(Err(e)) => Ok(Err(e)),
+            Err(f) => Err(Inspect {
+                future: f,
+                f: self.f,
+            }),
+        }
+    }
+}
+
+pub struct MapErr<A, F> {
+    future: A,
+    f: F,
+}
+
+impl<A, E, F> Future for MapErr<A, F>
+where
+    A: Future,
+    F: FnOnce(A::Error) -> E,
+{
+    type Item = A::Item;
+    type Error = E;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        match self.future.poll() {
+            Ok(result) => Ok(result.map_err(self.f)),
+            Err(f) => Err(MapErr {
+                future: f,
+                f: self.f,
+            }),
+        }
+    }
+}
+
+pub struct IgnoreErrIf<A, P>
+where
+    A: Future,
+{
+    future: A,
+    p: P,
+    fallback: A::Item,
+}
+
+impl<A, P> Future for IgnoreErrIf<A, P>
+where
+    A: Future,
+    P: FnOnce(&A::Error) -> bool,
+{
+    type Item = A::Item;
+    type Error = A::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let IgnoreErrIf {
+            future,
+            p,
+            fallback,
+        } = self;
+        match future.poll() {
+            Ok(Ok(item)) => Ok(Ok(item)),
+            Ok(Err(e)) => {
+                if p(&e) {
+                    Ok(Ok(fallback))
+                } else {
+                    Ok(Err(e))
+                }
+            }
+            Err(future) => Err(IgnoreErrIf {
+                future,
+                p,
+                fallback,
+            }),
+        }
+    }
+}
+
+pub struct MapPoll<A, F> {
+    future: A,
+    f: F,
+}
+
+impl<A, F, T2, E2> Future for MapPoll<A, F>
+where
+    A: Future,
+    F: FnMut(Result<A::Item, A::Error>) -> Result<T2, E2>,
+{
+    type Item = T2;
+    type Error = E2;
+
+    fn poll(mut self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        match self.future.poll() {
+            Ok(result) => Ok((self.f)(result)),
+            Err(f) => Err(MapPoll {
+                future: f,
+                f: self.f,
+            }),
+        }
+    }
+}
+
+pub struct FromErr<A, E> {
+    future: A,
+    _marker: marker::PhantomData<E>,
+}
+
+impl<A, E> Future for FromErr<A, E>
+where
+    A: Future,
+    E: From<A::Error>,
+{
+    type Item = A::Item;
+    type Error = E;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        match self.future.poll() {
+            Ok(result) => Ok(result.map_err(From::from)),
+            Err(future) => Err(FromErr {
+                future,
+                _marker: marker::PhantomData,
+            }),
+        }
+    }
+}
+
+pub struct AndThen<A, B, F>
+where
+    B: IntoFuture,
+{
+    future: _AndThen<A, B::Future, F>,
+}
+
+enum _AndThen<A, B, F> {
+    First(A, F),
+    Second(B),
+}
+
+impl<A, B, F> Future for AndThen<A, B, F>
+where
+    A: Future,
+    B: IntoFuture<Error = A::Error>,
+    F: FnOnce(A::Item) -> B,
+{
+    type Item = B::Item;
+    type Error = B::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let second = match self.future {
+            _AndThen::First(a, f) => match a.step() {
+                Step::Done(Ok(next)) => f(next).into_future(),
+                Step::Done(Err(e)) => return Ok(Err(e)),
+                Step::Yield(a) => {
+                    return Err(AndThen {
+                        future: _AndThen::First(a, f),
+                    })
+                }
+            },
+            _AndThen::Second(b) => b,
+        };
+        match second.step() {
+            Step::Done(result) => Ok(result),
+            Step::Yield(b) => Err(AndThen {
+                future: _AndThen::Second(b),
+            }),
+        }
+    }
+
+    fn state_name(&self) -> &'static str {
+        match self.future {
+            _AndThen::First(..) => "AndThen::First",
+            _AndThen::Second(_) => "AndThen::Second",
+        }
+    }
+}
+
+pub struct AndThenRetry<A, B, F>
+where
+    A: Future,
+    B: IntoFuture,
+{
+    state: _AndThenRetry<A, A::Item, B::Future>,
+    f: F,
+    remaining: usize,
+}
+
+enum _AndThenRetry<A, T, B> {
+    First(A),
+    Second(T, B),
+}
+
+impl<A, B, F> Future for AndThenRetry<A, B, F>
+where
+    A: Future,
+    A::Item: Clone,
+    B: IntoFuture<Error = A::Error>,
+    F: FnMut(A::Item) -> B,
+{
+    type Item = B::Item;
+    type Error = B::Error;
+
+    fn poll(mut self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let (item, mut second) = match self.state {
+            _AndThenRetry::First(a) => match a.poll() {
+                Ok(Ok(item)) => {
+                    let second = (self.f)(item.clone()).into_future();
+                    (item, second)
+                }
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(a) => {
+                    self.state = _AndThenRetry::First(a);
+                    return Err(self);
+                }
+            },
+            _AndThenRetry::Second(item, b) => (item, b),
+        };
+        loop {
+            match second.poll() {
+                Ok(Ok(item)) => return Ok(Ok(item)),
+                Ok(Err(e)) if self.remaining == 0 => return Ok(Err(e)),
+                Ok(Err(_)) => {
+                    self.remaining -= 1;
+                    second = (self.f)(item.clone()).into_future();
+                }
+                Err(b) => {
+                    self.state = _AndThenRetry::Second(item, b);
+                    return Err(self);
+                }
+            }
+        }
+    }
+}
+
+pub struct ZipDependent<A, B, F>
+where
+    A: Future,
+    B: IntoFuture,
+{
+    state: _ZipDependent<A, A::Item, B::Future, F>,
+}
+
+enum _ZipDependent<A, T, B, F> {
+    First(A, F),
+    Second(T, B),
+}
+
+impl<A, B, F> Future for ZipDependent<A, B, F>
+where
+    A: Future,
+    B: IntoFuture<Error = A::Error>,
+    F: FnOnce(&A::Item) -> B,
+{
+    type Item = (A::Item, B::Item);
+    type Error = A::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let (item, second) = match self.state {
+            _ZipDependent::First(a, f) => match a.poll() {
+                Ok(Ok(item)) => {
+                    let second = f(&item).into_future();
+                    (item, second)
+                }
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(a) => {
+                    return Err(ZipDependent {
+                        state: _ZipDependent::First(a, f),
+                    })
+                }
+            },
+            _ZipDependent::Second(item, b) => (item, b),
+        };
+        match second.poll() {
+            Ok(result) => Ok(result.map(|b| (item, b))),
+            Err(b) => Err(ZipDependent {
+                state: _ZipDependent::Second(item, b),
+            }),
+        }
+    }
+}
+
+pub struct AndThenIf<A, B, P, F>
+where
+    B: IntoFuture,
+{
+    future: _AndThenIf<A, B::Future, P, F>,
+}
+
+enum _AndThenIf<A, B, P, F> {
+    First(A, P, F),
+    Second(B),
+}
+
+impl<A, B, P, F> Future for AndThenIf<A, B, P, F>
+where
+    A: Future,
+    B: IntoFuture<Item = A::Item, Error = A::Error>,
+    P: FnOnce(&A::Item) -> bool,
+    F: FnOnce(A::Item) -> B,
+{
+    type Item = A::Item;
+    type Error = A::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let second = match self.future {
+            _AndThenIf::First(a, p, f) => match a.poll() {
+                Ok(Ok(next)) => {
+                    if !p(&next) {
+                        return Ok(Ok(next));
+                    }
+                    f(next).into_future()
+                }
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(a) => {
+                    return Err(AndThenIf {
+                        future: _AndThenIf::First(a, p, f),
+                    })
+                }
+            },
+            _AndThenIf::Second(b) => b,
+        };
+        second.poll().map_err(|b| AndThenIf {
+            future: _AndThenIf::Second(b),
+        })
+    }
+}
+
+pub struct AndThenKeep<A, B, F>
+where
+    A: Future,
+    B: IntoFuture,
+{
+    future: _AndThenKeep<A, A::Item, B::Future, F>,
+}
+
+enum _AndThenKeep<A, T, B, F> {
+    First(A, F),
+    Second(T, B),
+}
+
+impl<A, B, F> Future for AndThenKeep<A, B, F>
+where
+    A: Future,
+    A::Item: Clone,
+    B: IntoFuture<Error = A::Error>,
+    F: FnOnce(A::Item) -> B,
+{
+    type Item = B::Item;
+    type Error = (Option<A::Item>, A::Error);
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let (item, second) = match self.future {
+            _AndThenKeep::First(a, f) => match a.poll() {
+                Ok(Ok(item)) => (item.clone(), f(item).into_future()),
+                Ok(Err(e)) => return Ok(Err((None, e))),
+                Err(a) => {
+                    return Err(AndThenKeep {
+                        future: _AndThenKeep::First(a, f),
+                    })
+                }
+            },
+            _AndThenKeep::Second(item, b) => (item, b),
+        };
+        match second.poll() {
+            Ok(result) => Ok(result.map_err(|e| (Some(item), e))),
+            Err(b) => Err(AndThenKeep {
+                future: _AndThenKeep::Second(item, b),
+            }),
+        }
+    }
+}
+
+pub struct OrElse<A, B, F>
+where
+    B: IntoFuture,
+{
+    future: _OrElse<A, B::Future, F>,
+}
+
+enum _OrElse<A, B, F> {
+    First(A, F),
+    Second(B),
+}
+
+impl<A, B, F> Future for OrElse<A, B, F>
+where
+    A: Future,
+    B: IntoFuture<Item = A::Item>,
+    F: FnOnce(A::Error) -> B,
+{
+    type Item = B::Item;
+    type Error = B::Error;
+
+    // Returns the option if it contains a value, otherwise calls f and returns the result.
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let second = match self.future {
+            _OrElse::First(a, f) => match a.poll() {
+                Ok(Ok(next)) => return Ok(Ok(next)),
+                Ok(Err(e)) => f(e).into_future(),
+                Err(a) => {
+                    return Err(OrElse {
+                        future: _OrElse::First(a, f),
+                    })
+                }
+            },
+            _OrElse::Second(b) => b,
+        };
+        second.poll().map_err(|b| OrElse {
+            future: _OrElse::Second(b),
+        })
+    }
+
+    fn state_name(&self) -> &'static str {
+        match self.future {
+            _OrElse::First(..) => "OrElse::First",
+            _OrElse::Second(_) => "OrElse::Second",
+        }
+    }
+}
+
+pub struct Then<A, B, F>
+where
+    B: IntoFuture,
+{
+    future: _Then<A, B::Future, F>,
+}
+
+enum _Then<A, B, F> {
+    First(A, F),
+    Second(B),
+}
+
+impl<A, B, F> Future for Then<A, B, F>
+where
+    A: Future,
+    B: IntoFuture,
+    F: FnOnce(Result<A::Item, A::Error>) -> B,
+{
+    type Item = B::Item;
+    type Error = B::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let second = match self.future {
+            _Then::First(a, f) => match a.poll() {
+                Ok(result) => f(result).into_future(),
+                Err(a) => {
+                    return Err(Then {
+                        future: _Then::First(a, f),
+                    })
+                }
+            },
+            _Then::Second(b) => b,
+        };
+        second.poll().map_err(|b| Then {
+            future: _Then::Second(b),
+        })
+    }
+
+    fn state_name(&self) -> &'static str {
+        match self.future {
+            _Then::First(..) => "Then::First",
+            _Then::Second(_) => "Then::Second",
+        }
+    }
+}
+
+pub struct MapBothAsync<A, BF, BG, F, G> {
+    state: _MapBothAsync<A, BF, BG, F, G>,
+}
+
+enum _MapBothAsync<A, BF, BG, F, G> {
+    First(A, F, G),
+    Item(BF),
+    Error(BG),
+}
+
+impl<A, BF, BG, F, G> Future for MapBothAsync<A, BF, BG, F, G>
+where
+    A: Future,
+    F: FnOnce(A::Item) -> BF,
+    G: FnOnce(A::Error) -> BG,
+    BF: Future,
+    BG: Future<Item = BF::Error, Error = BF::Error>,
+{
+    type Item = BF::Item;
+    type Error = BF::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let state = match self.state {
+            _MapBothAsync::First(a, f, g) => match a.poll() {
+                Ok(Ok(item)) => _MapBothAsync::Item(f(item)),
+                Ok(Err(e)) => _MapBothAsync::Error(g(e)),
+                Err(a) => {
+                    return Err(MapBothAsync {
+                        state: _MapBothAsync::First(a, f, g),
+                    })
+                }
+            },
+            state => state,
+        };
+        match state {
+            _MapBothAsync::Item(bf) => bf.poll().map_err(|bf| MapBothAsync {
+                state: _MapBothAsync::Item(bf),
+            }),
+            _MapBothAsync::Error(bg) => match bg.poll() {
+                Ok(Ok(e)) | Ok(Err(e)) => Ok(Err(e)),
+                Err(bg) => Err(MapBothAsync {
+                    state: _MapBothAsync::Error(bg),
+                }),
+            },
+            _MapBothAsync::First(..) => unreachable!(),
+        }
+    }
+}
+
+pub struct Flatten<A>
+where
+    A: Future,
+    A::Item: IntoFuture,
+{
+    state: _Flatten<A, <A::Item as IntoFuture>::Future>,
+}
+
+enum _Flatten<A, B> {
+    First(A),
+    Second(B),
+}
+
+impl<A> Future for Flatten<A>
+where
+    A: Future,
+    A::Item: IntoFuture,
+    <A::Item as IntoFuture>::Error: From<A::Error>,
+{
+    type Item = <A::Item as IntoFuture>::Item;
+    type Error = <A::Item as IntoFuture>::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let second = match self.state {
+            _Flatten::First(a) => match a.poll() {
+                Ok(Ok(next)) => next.into_future(),
+                Ok(Err(e)) => return Ok(Err(From::from(e))),
+                Err(a) => {
+                    return Err(Flatten {
+                        state: _Flatten::First(a),
+                    })
+                }
+            },
+            _Flatten::Second(b) => b,
+        };
+        second.poll().map_err(|b| Flatten {
+            state: _Flatten::Second(b),
+        })
+    }
+
+    fn state_name(&self) -> &'static str {
+        match self.state {
+            _Flatten::First(_) => "Flatten::First",
+            _Flatten::Second(_) => "Flatten::Second",
+        }
+    }
+}
+
+pub struct FlattenResult<A> {
+    future: A,
+}
+
+impl<A, T, E> Future for FlattenResult<A>
+where
+    A: Future<Item = Result<T, E>>,
+    E: From<A::Error>,
+{
+    type Item = T;
+    type Error = E;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        match self.future.poll() {
+            Ok(Ok(inner)) => Ok(inner),
+            Ok(Err(e)) => Ok(Err(From::from(e))),
+            Err(future) => Err(FlattenResult { future }),
+        }
+    }
+}
+
+// A future which can be polled in place, and which is terminated once it
+// has handed out its outcome: from then on `poll_fused` comes up empty for
+// good instead of doing whatever the inner future would. `Join` and
+// `Select` keep their children fused, so a finished child is never polled
+// again. Polling a `Fuse` by value keeps a terminated one pending.
+pub struct Fuse<A> {
+    future: Option<A>,
+}
+
+impl<A> Fuse<A> {
+    pub fn terminated() -> Fuse<A> {
+        Fuse { future: None }
+    }
+
+    pub fn is_terminated(&self) -> bool {
+        self.future.is_none()
+    }
+
+    // Polls the inner future, returning its outcome the first time there is
+    // one and `None` while it's pending or once it's terminated.
+    pub fn poll_fused(&mut self) -> Option<Result<A::Item, A::Error>>
+    where
+        A: Future,
+    {
+        match self.future.take()?.poll() {
+            Ok(result) => Some(result),
+            Err(a) => {
+                self.future = Some(a);
+                None
+            }
+        }
+    }
+}
+
+impl<A> Future for Fuse<A>
+where
+    A: Future,
+{
+    type Item = A::Item;
+    type Error = A::Error;
+
+    fn poll(mut self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        match self.poll_fused() {
+            Some(result) => Ok(result),
+            None => Err(self),
+        }
+    }
+}
+
+impl<T> Future for Receiver<T> {
+    type Item = T;
+    type Error = RecvError;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        match self.try_recv() {
+            Ok(msg) => Ok(Ok(msg)),
+            Err(TryRecvError::Empty) => Err(self),
+            Err(TryRecvError::Disconnected) => Ok(Err(RecvError)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Empty<T, E> {
+    _marker: marker::PhantomData<(T, E)>,
+}
+
+impl<T, E> Empty<T, E> {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Empty<T, E> {
+        Empty {
+            _marker: marker::PhantomData,
+        }
+    }
+}
+
+impl<T, E> Future for Empty<T, E> {
+    type Item = T;
+    type Error = E;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        Err(self)
+    }
+}
+
+#[allow(clippy::non_canonical_clone_impl)]
+impl<T, E> Clone for Empty<T, E> {
+    fn clone(&self) -> Empty<T, E> {
+        Empty::new()
+    }
+}
+
+impl<T, E> Copy for Empty<T, E> {}
+
+// A future which never resolves.
+pub fn empty<T, E>() -> Empty<T, E> {
+    Empty::new()
+}
+
+pub struct Lazy<F, R>
+where
+    R: IntoFuture,
+{
+    state: _Lazy<F, R::Future>,
+}
+
+enum _Lazy<F, R> {
+    First(F),
+    Second(R),
+}
+
+// Defers calling `f` until the returned future is first polled, then
+// behaves like the future `f` returned.
+pub fn lazy<F, R>(f: F) -> Lazy<F, R>
+where
+    F: FnOnce() -> R,
+    R: IntoFuture,
+{
+    Lazy {
+        state: _Lazy::First(f),
+    }
+}
+
+impl<F, R> Future for Lazy<F, R>
+where
+    F: FnOnce() -> R,
+    R: IntoFuture,
+{
+    type Item = R::Item;
+    type Error = R::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let future = match self.state {
+            _Lazy::First(f) => f().into_future(),
+            _Lazy::Second(r) => r,
+        };
+        future.poll().map_err(|r| Lazy {
+            state: _Lazy::Second(r),
+        })
+    }
+}
+
+pub struct Select<A, B> {
+    a: Fuse<A>,
+    b: Fuse<B>,
+}
+
+impl<A, B> Future for Select<A, B>
+where
+    A: Future,
+    B: Future<Item = A::Item, Error = A::Error>,
+{
+    type Item = A::Item;
+    type Error = A::Error;
+
+    fn poll(mut self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        match self.a.poll_fused().or_else(|| self.b.poll_fused()) {
+            Some(result) => Ok(result),
+            None => Err(self),
+        }
+    }
+}
+
+pub struct SelectThen<A, B> {
+    a: A,
+    b: B,
+}
+
+// Whichever of two selected futures didn't finish first.
+pub struct SelectNext<A, B> {
+    inner: Either<A, B>,
+}
+
+impl<A, B> Future for SelectThen<A, B>
+where
+    A: Future,
+    B: Future<Item = A::Item, Error = A::Error>,
+{
+    type Item = (A::Item, SelectNext<A, B>);
+    type Error = (A::Error, SelectNext<A, B>);
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let SelectThen { a, b } = self;
+        let (result, inner) = match a.poll() {
+            Ok(result) => (result, Either::Right(b)),
+            Err(a) => match b.poll() {
+                Ok(result) => (result, Either::Left(a)),
+                Err(b) => return Err(SelectThen { a, b }),
+            },
+        };
+        let next = SelectNext { inner };
+        Ok(match result {
+            Ok(item) => Ok((item, next)),
+            Err(e) => Err((e, next)),
+        })
+    }
+}
+
+impl<A, B> Future for SelectNext<A, B>
+where
+    A: Future,
+    B: Future<Item = A::Item, Error = A::Error>,
+{
+    type Item = A::Item;
+    type Error = A::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        self.inner.poll().map_err(|inner| SelectNext { inner })
+    }
+}
+
+pub struct SelectEither<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A, B> Future for SelectEither<A, B>
+where
+    A: Future,
+    B: Future,
+{
+    type Item = Either<Result<A::Item, A::Error>, Result<B::Item, B::Error>>;
+    type Error = Never;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let SelectEither { a, b } = self;
+        match a.poll() {
+            Ok(result) => Ok(Ok(Either::Left(result))),
+            Err(a) => match b.poll() {
+                Ok(result) => Ok(Ok(Either::Right(result))),
+                Err(b) => Err(SelectEither { a, b }),
+            },
+        }
+    }
+}
+
+pub struct Select2<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A, B> Future for Select2<A, B>
+where
+    A: Future,
+    B: Future,
+{
+    type Item = Either<(A::Item, B), (B::Item, A)>;
+    type Error = Either<(A::Error, B), (B::Error, A)>;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let Select2 { a, b } = self;
+        let a = match a.poll() {
+            Ok(Ok(item)) => return Ok(Ok(Either::Left((item, b)))),
+            Ok(Err(e)) => return Ok(Err(Either::Left((e, b)))),
+            Err(a) => a,
+        };
+        match b.poll() {
+            Ok(Ok(item)) => Ok(Ok(Either::Right((item, a)))),
+            Ok(Err(e)) => Ok(Err(Either::Right((e, a)))),
+            Err(b) => Err(Select2 { a, b }),
+        }
+    }
+}
+
+pub struct SelectMap<A, B, F, G> {
+    a: A,
+    b: B,
+    f: F,
+    g: G,
+}
+
+impl<A, B, F, G, U> Future for SelectMap<A, B, F, G>
+where
+    A: Future,
+    B: Future<Error = A::Error>,
+    F: FnOnce(A::Item) -> U,
+    G: FnOnce(B::Item) -> U,
+{
+    type Item = U;
+    type Error = A::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let SelectMap { a, b, f, g } = self;
+        let a = match a.poll() {
+            Ok(result) => return Ok(result.map(f)),
+            Err(a) => a,
+        };
+        match b.poll() {
+            Ok(result) => Ok(result.map(g)),
+            Err(b) => Err(SelectMap { a, b, f, g }),
+        }
+    }
+}
+
+// Each side is fused, so once it has finished it's left alone while its
+// item waits for the other.
+pub struct Join<A, B>
+where
+    A: Future,
+    B: Future<Error = A::Error>,
+{
+    a: Fuse<A>,
+    b: Fuse<B>,
+    a_item: Option<A::Item>,
+    b_item: Option<B::Item>,
+}
+
+enum _Join<A, B>
+where
+    A: Future,
+    B: Future<Error = A::Error>,
+{
+    Both(A, B),
+    First(A, Result<B::Item, A::Error>),
+    Second(Result<A::Item, A::Error>, B),
+}
+
+impl<A, B> Future for Join<A, B>
+where
+    A: Future,
+    B: Future<Error = A::Error>,
+{
+    type Item = (A::Item, B::Item);
+    type Error = A::Error;
+
+    fn poll(mut self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        match self.a.poll_fused() {
+            Some(Ok(a)) => self.a_item = Some(a),
+            Some(Err(e)) => return Ok(Err(e)),
+            None => {}
+        }
+        match self.b.poll_fused() {
+            Some(Ok(b)) => self.b_item = Some(b),
+            Some(Err(e)) => return Ok(Err(e)),
+            None => {}
+        }
+        match (self.a_item.take(), self.b_item.take()) {
+            (Some(a), Some(b)) => Ok(Ok((a, b))),
+            (a, b) => {
+                self.a_item = a;
+                self.b_item = b;
+                Err(self)
+            }
+        }
+    }
+
+    fn state_name(&self) -> &'static str {
+        match (&self.a_item, &self.b_item) {
+            (None, Some(_)) => "Join::First",
+            (Some(_), None) => "Join::Second",
+            _ => "Join::Both",
+        }
+    }
+}
+
+// Each of these is a chain of `Join`s, which already keep finished items
+// aside until the rest are done, with the nested tuples flattened at the end.
+pub struct Join3<A, B, C>
+where
+    A: Future,
+    B: Future<Error = A::Error>,
+    C: Future<Error = A::Error>,
+{
+    inner: Join<Join<A, B>, C>,
+}
+
+impl<A, B, C> Future for Join3<A, B, C>
+where
+    A: Future,
+    B: Future<Error = A::Error>,
+    C: Future<Error = A::Error>,
+{
+    type Item = (A::Item, B::Item, C::Item);
+    type Error = A::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        match self.inner.poll() {
+            Ok(result) => Ok(result.map(|((a, b), c)| (a, b, c))),
+            Err(inner) => Err(Join3 { inner }),
+        }
+    }
+}
+
+pub struct Join4<A, B, C, D>
+where
+    A: Future,
+    B: Future<Error = A::Error>,
+    C: Future<Error = A::Error>,
+    D: Future<Error = A::Error>,
+{
+    inner: Join<Join3<A, B, C>, D>,
+}
+
+impl<A, B, C, D> Future for Join4<A, B, C, D>
+where
+    A: Future,
+    B: Future<Error = A::Error>,
+    C: Future<Error = A::Error>,
+    D: Future<Error = A::Error>,
+{
+    type Item = (A::Item, B::Item, C::Item, D::Item);
+    type Error = A::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        match self.inner.poll() {
+            Ok(result) => Ok(result.map(|((a, b, c), d)| (a, b, c, d))),
+            Err(inner) => Err(Join4 { inner }),
+        }
+    }
+}
+
+pub struct Join5<A, B, C, D, E>
+where
+    A: Future,
+    B: Future<Error = A::Error>,
+    C: Future<Error = A::Error>,
+    D: Future<Error = A::Error>,
+    E: Future<Error = A::Error>,
+{
+    inner: Join<Join4<A, B, C, D>, E>,
+}
+
+impl<A, B, C, D, E> Future for Join5<A, B, C, D, E>
+where
+    A: Future,
+    B: Future<Error = A::Error>,
+    C: Future<Error = A::Error>,
+    D: Future<Error = A::Error>,
+    E: Future<Error = A::Error>,
+{
+    type Item = (A::Item, B::Item, C::Item, D::Item, E::Item);
+    type Error = A::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        match self.inner.poll() {
+            Ok(result) => Ok(result.map(|((a, b, c, d), e)| (a, b, c, d, e))),
+            Err(inner) => Err(Join5 { inner }),
+        }
+    }
+}
+
+pub struct JoinPriority<A, B>
+where
+    A: Future,
+    B: Future<Error = A::Error>,
+{
+    state: _Join<A, B>,
+    self_first: bool,
+}
+
+impl<A, B> Future for JoinPriority<A, B>
+where
+    A: Future,
+    B: Future<Error = A::Error>,
+{
+    type Item = (A::Item, B::Item);
+    type Error = A::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let self_first = self.self_first;
+        let (a, b) = match self.state {
+            _Join::Both(a, b) if self_first => {
+                let a = a.poll();
+                (a, b.poll())
+            }
+            _Join::Both(a, b) => {
+                let b = b.poll();
+                (a.poll(), b)
+            }
+            _Join::First(a, b) => (a.poll(), Ok(b)),
+            _Join::Second(a, b) => (Ok(a), b.poll()),
+        };
+        let state = match (a, b) {
+            (Ok(Err(a)), Ok(Err(b))) => return Ok(Err(if self_first { a } else { b })),
+            (Ok(Err(e)), _) | (_, Ok(Err(e))) => return Ok(Err(e)),
+            (Ok(Ok(a)), Ok(Ok(b))) => return Ok(Ok((a, b))),
+            (Err(a), Ok(b)) => _Join::First(a, b),
+            (Ok(a), Err(b)) => _Join::Second(a, b),
+            (Err(a), Err(b)) => _Join::Both(a, b),
+        };
+        Err(JoinPriority { state, self_first })
+    }
+}
+
+pub struct JoinCollect<A, B>
+where
+    A: Future,
+    B: Future<Error = A::Error>,
+{
+    a: _JoinCollect<A>,
+    b: _JoinCollect<B>,
+}
+
+enum _JoinCollect<A: Future> {
+    Pending(A),
+    Done(Result<A::Item, A::Error>),
+}
+
+impl<A: Future> _JoinCollect<A> {
+    fn poll(self) -> _JoinCollect<A> {
+        match self {
+            _JoinCollect::Pending(a) => match a.poll() {
+                Ok(result) => _JoinCollect::Done(result),
+                Err(a) => _JoinCollect::Pending(a),
+            },
+            done => done,
+        }
+    }
+}
+
+impl<A, B> Future for JoinCollect<A, B>
+where
+    A: Future,
+    B: Future<Error = A::Error>,
+{
+    type Item = (A::Item, B::Item);
+    type Error = Vec<A::Error>;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let (a, b) = match (self.a.poll(), self.b.poll()) {
+            (_JoinCollect::Done(a), _JoinCollect::Done(b)) => (a, b),
+            (a, b) => return Err(JoinCollect { a, b }),
+        };
+        match (a, b) {
+            (Ok(a), Ok(b)) => Ok(Ok((a, b))),
+            (Err(a), Err(b)) => Ok(Err(vec![a, b])),
+            (Err(e), _) | (_, Err(e)) => Ok(Err(vec![e])),
+        }
+    }
+}
+
+pub struct JoinBothResults<A, B>
+where
+    A: Future,
+    B: Future,
+{
+    a: _JoinCollect<A>,
+    b: _JoinCollect<B>,
+}
+
+impl<A, B> Future for JoinBothResults<A, B>
+where
+    A: Future,
+    B: Future,
+{
+    type Item = (Result<A::Item, A::Error>, Result<B::Item, B::Error>);
+    type Error = Never;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        match (self.a.poll(), self.b.poll()) {
+            (_JoinCollect::Done(a), _JoinCollect::Done(b)) => Ok(Ok((a, b))),
+            (a, b) => Err(JoinBothResults { a, b }),
+        }
+    }
+}
+
+pub struct FutureFn<F> {
+    f: F,
+}
+
+// Unlike a future built from a `Result`, the closure isn't run until the
+// first `poll`, at which point the future resolves immediately.
+pub fn future_fn<T, E, F>(f: F) -> FutureFn<F>
+where
+    F: FnOnce() -> Result<T, E>,
+{
+    FutureFn { f }
+}
+
+impl<T, E, F> Future for FutureFn<F>
+where
+    F: FnOnce() -> Result<T, E>,
+{
+    type Item = T;
+    type Error = E;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        Ok((self.f)())
+    }
+}
+
+pub struct MapJoin<A>
+where
+    A: Future,
+{
+    slots: Vec<_JoinSlot<A>>,
+}
+
+enum _JoinSlot<A: Future> {
+    Pending(A),
+    Done(A::Item),
+}
+
+// Maps every element of `iter` to a future and joins them all, resolving to
+// the items in input order or to the first error encountered.
+pub fn map_join<I, F, B>(iter: I, f: F) -> MapJoin<B::Future>
+where
+    I: IntoIterator,
+    F: FnMut(I::Item) -> B,
+    B: IntoFuture,
+{
+    MapJoin {
+        slots: iter
+            .into_iter()
+            .map(f)
+            .map(|b| _JoinSlot::Pending(b.into_future()))
+            .collect(),
+    }
+}
+
+// Drives every future in `iter` to completion, resolving to their items in
+// input order or to the first error encountered.
+pub fn join_all<I>(iter: I) -> JoinAll<<I::Item as IntoFuture>::Future>
+where
+    I: IntoIterator,
+    I::Item: IntoFuture,
+{
+    JoinAll {
+        inner: map_join(iter, IntoFuture::into_future),
+    }
+}
+
+pub struct JoinAll<A>
+where
+    A: Future,
+{
+    inner: MapJoin<A>,
+}
+
+impl<A> Future for JoinAll<A>
+where
+    A: Future,
+{
+    type Item = Vec<A::Item>;
+    type Error = A::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        self.inner.poll().map_err(|inner| JoinAll { inner })
+    }
+}
+
+// Zips the items of every future in `iter` into one positional vector,
+// failing with the first error. See `zip_all_with` to reduce the vector.
+pub fn zip_all<I>(iter: I) -> ZipAll<<I::Item as IntoFuture>::Future>
+where
+    I: IntoIterator,
+    I::Item: IntoFuture,
+{
+    ZipAll {
+        inner: MapJoin {
+            slots: iter
+                .into_iter()
+                .map(|f| _JoinSlot::Pending(f.into_future()))
+                .collect(),
+        },
+    }
+}
+
+pub fn zip_all_with<I, F, U>(iter: I, f: F) -> Map<ZipAll<<I::Item as IntoFuture>::Future>, F>
+where
+    I: IntoIterator,
+    I::Item: IntoFuture,
+    F: FnOnce(Vec<<I::Item as IntoFuture>::Item>) -> U,
+{
+    zip_all(iter).map(f)
+}
+
+pub struct ZipAll<A>
+where
+    A: Future,
+{
+    inner: MapJoin<A>,
+}
+
+impl<A> Future for ZipAll<A>
+where
+    A: Future,
+{
+    type Item = Vec<A::Item>;
+    type Error = A::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        self.inner.poll().map_err(|inner| ZipAll { inner })
+    }
+}
+
+impl<A> Future for MapJoin<A>
+where
+    A: Future,
+{
+    type Item = Vec<A::Item>;
+    type Error = A::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let mut slots = Vec::with_capacity(self.slots.len());
+        let mut done = true;
+        for slot in self.slots {
+            slots.push(match slot {
+                _JoinSlot::Pending(a) => match a.poll() {
+                    Ok(Ok(item)) => _JoinSlot::Done(item),
+                    Ok(Err(e)) => return Ok(Err(e)),
+                    Err(a) => {
+                        done = false;
+                        _JoinSlot::Pending(a)
+                    }
+                },
+                done => done,
+            });
+        }
+        if !done {
+            return Err(MapJoin { slots });
+        }
+        Ok(Ok(slots
+            .into_iter()
+            .map(|slot| match slot {
+                _JoinSlot::Done(item) => item,
+                _JoinSlot::Pending(_) => unreachable!(),
+            })
+            .collect()))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TimeoutError;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Canceled;
+
+pub struct TimeoutReturn<A> {
+    future: A,
+    remaining: usize,
+}
+
+impl<A> Future for TimeoutReturn<A>
+where
+    A: Future,
+{
+    type Item = Result<A::Item, A::Error>;
+    type Error = (TimeoutError, A);
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        if self.remaining == 0 {
+            return Ok(Err((TimeoutError, self.future)));
+        }
+        match self.future.poll() {
+            Ok(result) => Ok(Ok(result)),
+            Err(a) if self.remaining == 1 => Ok(Err((TimeoutError, a))),
+            Err(a) => Err(TimeoutReturn {
+                future: a,
+                remaining: self.remaining - 1,
+            }),
+        }
+    }
+}
+
+pub struct KeepError<A>
+where
+    A: Future,
+{
+    // `None` once the future has failed and `error` is set.
+    future: Option<A>,
+    error: Option<A::Error>,
+}
+
+impl<A> KeepError<A>
+where
+    A: Future,
+{
+    pub fn last_error(&self) -> Option<&A::Error> {
+        self.error.as_ref()
+    }
+}
+
+impl<A> Future for KeepError<A>
+where
+    A: Future,
+    A::Error: Clone,
+{
+    type Item = A::Item;
+    type Error = (A::Error, KeepError<A>);
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let future = match self.future {
+            Some(future) => future,
+            None => {
+                let e = self
+                    .error
+                    .clone()
+                    .expect("KeepError has neither future nor error");
+                return Ok(Err((e, self)));
+            }
+        };
+        match future.poll() {
+            Ok(Ok(item)) => Ok(Ok(item)),
+            Ok(Err(e)) => {
+                let kept = KeepError {
+                    future: None,
+                    error: Some(e.clone()),
+                };
+                Ok(Err((e, kept)))
+            }
+            Err(future) => Err(KeepError {
+                future: Some(future),
+                error: None,
+            }),
+        }
+    }
+}
+
+pub struct CatchUnwind<A> {
+    future: A,
+}
+
+impl<A> Future for CatchUnwind<A>
+where
+    A: Future + panic::UnwindSafe,
+{
+    type Item = Result<Result<A::Item, A::Error>, Box<dyn Any + Send>>;
+    type Error = Never;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let future = self.future;
+        match panic::catch_unwind(move || future.poll()) {
+            Ok(Ok(result)) => Ok(Ok(Ok(result))),
+            Ok(Err(future)) => Err(CatchUnwind { future }),
+            Err(payload) => Ok(Ok(Err(payload))),
+        }
+    }
+}
+
+// How many ticks `timeout_stream` lets pass before the future times out.
+const TIMEOUT_TICKS: usize = 2;
+
+pub struct TimeoutStream<A, S> {
+    future: A,
+    ticks: Option<S>,
+    remaining: usize,
+}
+
+impl<A, S> Future for TimeoutStream<A, S>
+where
+    A: Future,
+    A::Error: From<TimeoutError>,
+    S: Stream<Item = (), Error = A::Error>,
+{
+    type Item = A::Item;
+    type Error = A::Error;
+
+    fn poll(mut self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        self.future = match self.future.poll() {
+            Ok(result) => return Ok(result),
+            Err(a) => a,
+        };
+        while self.remaining > 0 {
+            let ticks = match self.ticks.take() {
+                Some(ticks) => ticks,
+                None => break,
+            };
+            match ticks.poll() {
+                Ok(Ok(Some(((), ticks)))) => {
+                    self.remaining -= 1;
+                    self.ticks = Some(ticks);
+                }
+                Ok(Ok(None)) => {}
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(ticks) => {
+                    self.ticks = Some(ticks);
+                    break;
+                }
+            }
+        }
+        if self.remaining == 0 {
+            return Ok(Err(TimeoutError.into()));
+        }
+        Err(self)
+    }
+}
+
+pub struct FoldFutures<I, F, B>
+where
+    B: IntoFuture,
+{
+    iter: I,
+    f: F,
+    state: _FoldFutures<B::Item, B::Future>,
+}
+
+enum _FoldFutures<St, B> {
+    Idle(St),
+    Running(B),
+}
+
+// Runs `f(acc, item)` for each element of `iter` in turn, waiting for each
+// future to resolve before starting the next and feeding its item back in as
+// the accumulator. Resolves to the final accumulator or the first error.
+pub fn fold_futures<I, St, F, B>(iter: I, init: St, f: F) -> FoldFutures<I::IntoIter, F, B>
+where
+    I: IntoIterator,
+    F: FnMut(St, I::Item) -> B,
+    B: IntoFuture<Item = St>,
+{
+    FoldFutures {
+        iter: iter.into_iter(),
+        f,
+        state: _FoldFutures::Idle(init),
+    }
+}
+
+impl<I, F, B> Future for FoldFutures<I, F, B>
+where
+    I: Iterator,
+    F: FnMut(B::Item, I::Item) -> B,
+    B: IntoFuture,
+{
+    type Item = B::Item;
+    type Error = B::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let FoldFutures {
+            mut iter,
+            mut f,
+            mut state,
+        } = self;
+        loop {
+            let future = match state {
+                _FoldFutures::Idle(acc) => match iter.next() {
+                    Some(item) => f(acc, item).into_future(),
+                    None => return Ok(Ok(acc)),
+                },
+                _FoldFutures::Running(b) => b,
+            };
+            state = match future.poll() {
+                Ok(Ok(acc)) => _FoldFutures::Idle(acc),
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(b) => {
+                    return Err(FoldFutures {
+                        iter,
+                        f,
+                        state: _FoldFutures::Running(b),
+                    })
+                }
+            };
+        }
+    }
+}
+
+pub struct BlockingOnce<A> {
+    future: Option<A>,
+}
+
+impl<A> Iterator for BlockingOnce<A>
+where
+    A: Future,
+{
+    type Item = Result<A::Item, A::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.future.take().map(Future::wait)
+    }
+}
+
+pub struct Observable<A> {
+    future: A,
+    polls: Rc<Cell<usize>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ProgressHandle {
+    polls: Rc<Cell<usize>>,
+}
+
+impl ProgressHandle {
+    pub fn polls(&self) -> usize {
+        self.polls.get()
+    }
+}
+
+impl<A> Future for Observable<A>
+where
+    A: Future,
+{
+    type Item = A::Item;
+    type Error = A::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let Observable { future, polls } = self;
+        polls.set(polls.get() + 1);
+        future.poll().map_err(|future| Observable { future, polls })
+    }
+}
+
+pub struct OnPoll<A, F> {
+    future: A,
+    f: F,
+    polls: usize,
+}
+
+impl<A, F> Future for OnPoll<A, F>
+where
+    A: Future,
+    F: FnMut(usize),
+{
+    type Item = A::Item;
+    type Error = A::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let OnPoll {
+            future,
+            mut f,
+            polls,
+        } = self;
+        let polls = polls + 1;
+        f(polls);
+        future.poll().map_err(|future| OnPoll { future, f, polls })
+    }
+}
+
+// A pool of retries shared by every `WithBudget` future holding a clone.
+#[derive(Clone, Debug)]
+pub struct RetryBudget {
+    remaining: Rc<Cell<usize>>,
+}
+
+impl RetryBudget {
+    pub fn new(retries: usize) -> RetryBudget {
+        RetryBudget {
+            remaining: Rc::new(Cell::new(retries)),
+        }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.remaining.get()
+    }
+
+    fn try_take(&self) -> bool {
+        match self.remaining.get() {
+            0 => false,
+            n => {
+                self.remaining.set(n - 1);
+                true
+            }
+        }
+    }
+}
+
+pub struct WithBudget<F, A>
+where
+    A: IntoFuture,
+{
+    factory: F,
+    budget: RetryBudget,
+    current: Option<A::Future>,
+}
+
+// Runs a future built by `factory`, retrying on failure only while the
+// shared `budget` has retries left. Each retry uses up one for the group.
+pub fn with_budget<F, A>(factory: F, budget: RetryBudget) -> WithBudget<F, A>
+where
+    F: FnMut() -> A,
+    A: IntoFuture,
+{
+    WithBudget {
+        factory,
+        budget,
+        current: None,
+    }
+}
+
+impl<F, A> Future for WithBudget<F, A>
+where
+    F: FnMut() -> A,
+    A: IntoFuture,
+{
+    type Item = A::Item;
+    type Error = A::Error;
+
+    fn poll(mut self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        loop {
+            let attempt = match self.current.take() {
+                Some(attempt) => attempt,
+                None => (self.factory)().into_future(),
+            };
+            match attempt.poll() {
+                Ok(Err(e)) => {
+                    if !self.budget.try_take() {
+                        return Ok(Err(e));
+                    }
+                }
+                Ok(Ok(item)) => return Ok(Ok(item)),
+                Err(attempt) => {
+                    self.current = Some(attempt);
+                    return Err(self);
+                }
+            }
+        }
+    }
+}
+
+// A set of named counters which can be shared between threads.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    counters: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    // Returns zero for counters which were never incremented.
+    pub fn get(&self, key: &str) -> u64 {
+        let counters = self.counters.lock().unwrap();
+        counters.get(key).cloned().unwrap_or(0)
+    }
+
+    fn incr(&self, name: &str, counter: &str) {
+        let mut counters = self.counters.lock().unwrap();
+        *counters.entry(format!("{}.{}", name, counter)).or_insert(0) += 1;
+    }
+}
+
+pub struct WithMetrics<A> {
+    future: A,
+    name: &'static str,
+    metrics: Metrics,
+}
+
+impl<A> Future for WithMetrics<A>
+where
+    A: Future,
+{
+    type Item = A::Item;
+    type Error = A::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let WithMetrics {
+            future,
+            name,
+            metrics,
+        } = self;
+        metrics.incr(name, "polls");
+        match future.poll() {
+            Ok(Ok(item)) => {
+                metrics.incr(name, "completions");
+                Ok(Ok(item))
+            }
+            Ok(Err(e)) => {
+                metrics.incr(name, "errors");
+                Ok(Err(e))
+            }
+            Err(future) => Err(WithMetrics {
+                future,
+                name,
+                metrics,
+            }),
+        }
+    }
+}
+
+pub struct ForResult<A, F> {
+    future: A,
+    f: F,
+}
+
+impl<A, F> Future for ForResult<A, F>
+where
+    A: Future,
+    F: FnOnce(Result<A::Item, A::Error>),
+{
+    type Item = ();
+    type Error = Never;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        match self.future.poll() {
+            Ok(result) => {
+                (self.f)(result);
+                Ok(Ok(()))
+            }
+            Err(future) => Err(ForResult { future, f: self.f }),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct StageTimeout {
+    pub stage: usize,
+}
+
+// A shared marker of which stage of a chain is currently running.
+#[derive(Clone, Debug, Default)]
+pub struct StageCounter {
+    stage: Rc<Cell<usize>>,
+}
+
+impl StageCounter {
+    pub fn new() -> StageCounter {
+        StageCounter::default()
+    }
+
+    pub fn advance(&self) {
+        self.stage.set(self.stage.get() + 1);
+    }
+
+    pub fn current(&self) -> usize {
+        self.stage.get()
+    }
+}
+
+pub struct StageTimeouts<A> {
+    future: A,
+    budgets: Vec<usize>,
+    stages: StageCounter,
+    // The stage seen after the last poll, and how many pending polls it has
+    // taken so far.
+    stage: usize,
+    spins: usize,
+}
+
+impl<A> Future for StageTimeouts<A>
+where
+    A: Future,
+    A::Error: From<StageTimeout>,
+{
+    type Item = A::Item;
+    type Error = A::Error;
+
+    fn poll(mut self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let future = match self.future.poll() {
+            Ok(result) => return Ok(result),
+            Err(future) => future,
+        };
+        let stage = self.stages.current();
+        if stage != self.stage {
+            self.stage = stage;
+            self.spins = 0;
+        }
+        self.spins += 1;
+        match self.budgets.get(stage) {
+            Some(&budget) if self.spins > budget => Ok(Err(From::from(StageTimeout { stage }))),
+            _ => {
+                self.future = future;
+                Err(self)
+            }
+        }
+    }
+}
+
+pub struct Hold<A>
+where
+    A: Future,
+{
+    state: _Hold<A, Result<A::Item, A::Error>>,
+}
+
+enum _Hold<A, R> {
+    Running(A, usize),
+    Held(R, usize),
+}
+
+impl<A> Future for Hold<A>
+where
+    A: Future,
+{
+    type Item = A::Item;
+    type Error = A::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let (result, remaining) = match self.state {
+            _Hold::Running(a, polls) => match a.poll() {
+                Ok(result) => (result, polls),
+                Err(a) => {
+                    return Err(Hold {
+                        state: _Hold::Running(a, polls),
+                    })
+                }
+            },
+            _Hold::Held(result, remaining) => (result, remaining),
+        };
+        if remaining == 0 {
+            return Ok(result);
+        }
+        Err(Hold {
+            state: _Hold::Held(result, remaining - 1),
+        })
+    }
+}
+
+pub struct SelectAll<A> {
+    futures: Vec<A>,
+}
+
+// Resolves as soon as any future in `iter` does, to the winner's index and
+// outcome along with the rest, in order, which can be selected over again.
+// An error comes back the same way, leaving the others just as usable.
+//
+// Panics if `iter` is empty.
+pub fn select_all<I>(iter: I) -> SelectAll<<I::Item as IntoFuture>::Future>
+where
+    I: IntoIterator,
+    I::Item: IntoFuture,
+{
+    let futures: Vec<_> = iter.into_iter().map(IntoFuture::into_future).collect();
+    assert!(!futures.is_empty(), "select_all needs at least one future");
+    SelectAll { futures }
+}
+
+impl<A> Future for SelectAll<A>
+where
+    A: Future,
+{
+    type Item = (usize, A::Item, Vec<A>);
+    type Error = (usize, A::Error, Vec<A>);
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let mut rest = Vec::with_capacity(self.futures.len());
+        let mut futures = self.futures.into_iter().enumerate();
+        while let Some((i, f)) = futures.next() {
+            match f.poll() {
+                Ok(result) => {
+                    rest.extend(futures.map(|(_, f)| f));
+                    return Ok(match result {
+                        Ok(item) => Ok((i, item, rest)),
+                        Err(e) => Err((i, e, rest)),
+                    });
+                }
+                Err(f) => rest.push(f),
+            }
+        }
+        Err(SelectAll { futures: rest })
+    }
+}
+
+pub struct SelectOkVerbose<A>
+where
+    A: Future,
+{
+    futures: Vec<A>,
+    errors: Vec<A::Error>,
+}
+
+// Resolves to the first success, along with the errors of every future which
+// failed before it, in the order they failed, and the futures still running.
+// Fails with all of the errors if every future fails.
+//
+// Panics if `iter` is empty.
+pub fn select_ok_verbose<I>(iter: I) -> SelectOkVerbose<<I::Item as IntoFuture>::Future>
+where
+    I: IntoIterator,
+    I::Item: IntoFuture,
+{
+    let futures: Vec<_> = iter.into_iter().map(IntoFuture::into_future).collect();
+    assert!(
+        !futures.is_empty(),
+        "select_ok_verbose needs at least one future"
+    );
+    SelectOkVerbose {
+        futures,
+        errors: Vec::new(),
+    }
+}
+
+impl<A> Future for SelectOkVerbose<A>
+where
+    A: Future,
+{
+    type Item = (A::Item, Vec<A::Error>, Vec<A>);
+    type Error = Vec<A::Error>;
+
+    fn poll(mut self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let mut rest = Vec::with_capacity(self.futures.len());
+        let mut futures = self.futures.into_iter();
+        while let Some(f) = futures.next() {
+            match f.poll() {
+                Ok(Ok(item)) => {
+                    rest.extend(futures);
+                    return Ok(Ok((item, self.errors, rest)));
+                }
+                Ok(Err(e)) => self.errors.push(e),
+                Err(f) => rest.push(f),
+            }
+        }
+        if rest.is_empty() {
+            return Ok(Err(self.errors));
+        }
+        self.futures = rest;
+        Err(self)
+    }
+}
+
+pub struct Quorum<A>
+where
+    A: Future,
+{
+    futures: Vec<A>,
+    items: Vec<A::Item>,
+    k: usize,
+}
+
+// Resolves to the items of the first `k` futures to succeed, in the order
+// they did, dropping the rest. Fails with the error which leaves too few
+// futures running to reach `k`.
+//
+// Panics if `iter` has fewer than `k` futures.
+pub fn quorum<I>(iter: I, k: usize) -> Quorum<<I::Item as IntoFuture>::Future>
+where
+    I: IntoIterator,
+    I::Item: IntoFuture,
+{
+    let futures: Vec<_> = iter.into_iter().map(IntoFuture::into_future).collect();
+    assert!(futures.len() >= k, "quorum needs at least k futures");
+    Quorum {
+        futures,
+        items: Vec::with_capacity(k),
+        k,
+    }
+}
+
+impl<A> Future for Quorum<A>
+where
+    A: Future,
+{
+    type Item = Vec<A::Item>;
+    type Error = A::Error;
+
+    fn poll(mut self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        if self.k == 0 {
+            return Ok(Ok(self.items));
+        }
+        let mut rest = Vec::with_capacity(self.futures.len());
+        let mut futures = self.futures.into_iter();
+        while let Some(f) = futures.next() {
+            match f.poll() {
+                Ok(Ok(item)) => {
+                    self.items.push(item);
+                    if self.items.len() == self.k {
+                        return Ok(Ok(self.items));
+                    }
+                }
+                Ok(Err(e)) if self.items.len() + rest.len() + futures.len() < self.k => {
+                    return Ok(Err(e))
+                }
+                Ok(Err(_)) => {}
+                Err(f) => rest.push(f),
+            }
+        }
+        self.futures = rest;
+        Err(self)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Policy {
+    // Every future must succeed, as with `join_all`.
+    All,
+    // The first success wins; fails only once every future has failed.
+    Any,
+    // The first future to finish wins, whatever its outcome.
+    First,
+}
+
+// Drives every future in `iter` according to `policy`, resolving to the
+// successful items paired with their index: all of them in input order for
+// `Policy::All`, otherwise just the winner. Under `Policy::Any` the error is
+// the last one seen.
+//
+// Panics on the first poll if `iter` is empty under `Any` or `First`.
+pub fn combine<I>(iter: I, policy: Policy) -> Combine<<I::Item as IntoFuture>::Future>
+where
+    I: IntoIterator,
+    I::Item: IntoFuture,
+{
+    Combine {
+        policy,
+        slots: iter
+            .into_iter()
+            .enumerate()
+            .map(|(i, f)| (i, _JoinSlot::Pending(f.into_future())))
+            .collect(),
+        error: None,
+    }
+}
+
+pub struct Combine<A>
+where
+    A: Future,
+{
+    policy: Policy,
+    slots: Vec<(usize, _JoinSlot<A>)>,
+    // The latest failure under `Policy::Any`.
+    error: Option<A::Error>,
+}
+
+impl<A> Future for Combine<A>
+where
+    A: Future,
+{
+    type Item = Vec<(usize, A::Item)>;
+    type Error = A::Error;
+
+    fn poll(mut self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let policy = self.policy;
+        if policy != Policy::All {
+            assert!(
+                !self.slots.is_empty() || self.error.is_some(),
+                "combine needs at least one future"
+            );
+        }
+        let mut slots = Vec::with_capacity(self.slots.len());
+        for (i, slot) in self.slots {
+            let f = match slot {
+                _JoinSlot::Pending(f) => f,
+                done => {
+                    slots.push((i, done));
+                    continue;
+                }
+            };
+            match (f.poll(), policy) {
+                (Ok(Ok(item)), Policy::All) => slots.push((i, _JoinSlot::Done(item))),
+                (Ok(Ok(item)), _) => return Ok(Ok(vec![(i, item)])),
+                (Ok(Err(e)), Policy::Any) => self.error = Some(e),
+                (Ok(Err(e)), _) => return Ok(Err(e)),
+                (Err(f), _) => slots.push((i, _JoinSlot::Pending(f))),
+            }
+        }
+        if policy == Policy::Any && slots.is_empty() {
+            if let Some(e) = self.error {
+                return Ok(Err(e));
+            }
+        }
+        if policy == Policy::All
+            && slots
+                .iter()
+                .all(|(_, slot)| matches!(slot, _JoinSlot::Done(_)))
+        {
+            return Ok(Ok(slots
+                .into_iter()
+                .map(|(i, slot)| match slot {
+                    _JoinSlot::Done(item) => (i, item),
+                    _JoinSlot::Pending(_) => unreachable!(),
+                })
+                .collect()));
+        }
+        self.slots = slots;
+        Err(self)
+    }
+}
+
+pub struct RaceKeep<A> {
+    futures: Vec<A>,
+}
+
+// Polls every future in turn until one completes, resolving to its outcome
+// along with all the others, in their original order, still unfinished.
+// Futures after the winner aren't polled on that final pass.
+//
+// Panics if `iter` is empty.
+pub fn race_keep<I>(iter: I) -> RaceKeep<<I::Item as IntoFuture>::Future>
+where
+    I: IntoIterator,
+    I::Item: IntoFuture,
+{
+    let futures: Vec<_> = iter.into_iter().map(IntoFuture::into_future).collect();
+    assert!(!futures.is_empty(), "race_keep needs at least one future");
+    RaceKeep { futures }
+}
+
+impl<A> Future for RaceKeep<A>
+where
+    A: Future,
+{
+    type Item = (Result<A::Item, A::Error>, Vec<A>);
+    type Error = Never;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let mut rest = Vec::with_capacity(self.futures.len());
+        let mut futures = self.futures.into_iter();
+        while let Some(f) = futures.next() {
+            match f.poll() {
+                Ok(result) => {
+                    rest.extend(futures);
+                    return Ok(Ok((result, rest)));
+                }
+                Err(f) => rest.push(f),
+            }
+        }
+        Err(RaceKeep { futures: rest })
+    }
+}
+
+// Configures a future which runs attempts built by `factory`, each bounded
+// by an optional spin timeout, retrying failed or timed-out attempts and
+// finally resolving to the fallback value, if one was given, once every
+// attempt has failed.
+pub struct Orchestrate<F, A>
+where
+    A: IntoFuture,
+{
+    factory: F,
+    timeout_spins: Option<usize>,
+    retries: usize,
+    fallback: Option<A::Item>,
+}
+
+impl<F, A> Orchestrate<F, A>
+where
+    F: FnMut() -> A,
+    A: IntoFuture,
+    A::Error: From<TimeoutError>,
+{
+    pub fn new(factory: F) -> Orchestrate<F, A> {
+        Orchestrate {
+            factory,
+            timeout_spins: None,
+            retries: 0,
+            fallback: None,
+        }
+    }
+
+    // Fails an attempt with `TimeoutError` after `n` pending polls.
+    pub fn timeout_spins(mut self, n: usize) -> Orchestrate<F, A> {
+        self.timeout_spins = Some(n);
+        self
+    }
+
+    // Starts up to `n` further attempts after the first one fails.
+    pub fn retries(mut self, n: usize) -> Orchestrate<F, A> {
+        self.retries = n;
+        self
+    }
+
+    pub fn fallback(mut self, v: A::Item) -> Orchestrate<F, A> {
+        self.fallback = Some(v);
+        self
+    }
+
+    pub fn build(self) -> Orchestrated<F, A> {
+        Orchestrated {
+            factory: self.factory,
+            current: None,
+            spins: 0,
+            timeout_spins: self.timeout_spins,
+            retries: self.retries,
+            fallback: self.fallback,
+        }
+    }
+}
+
+pub struct Orchestrated<F, A>
+where
+    A: IntoFuture,
+{
+    factory: F,
+    current: Option<A::Future>,
+    spins: usize,
+    timeout_spins: Option<usize>,
+    retries: usize,
+    fallback: Option<A::Item>,
+}
+
+impl<F, A> Future for Orchestrated<F, A>
+where
+    F: FnMut() -> A,
+    A: IntoFuture,
+    A::Error: From<TimeoutError>,
+{
+    type Item = A::Item;
+    type Error = A::Error;
+
+    fn poll(mut self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        loop {
+            let attempt = match self.current.take() {
+                Some(attempt) => attempt,
+                None => (self.factory)().into_future(),
+            };
+            let e = match attempt.poll() {
+                Ok(Ok(item)) => return Ok(Ok(item)),
+                Ok(Err(e)) => e,
+                Err(attempt) => {
+                    self.spins += 1;
+                    match self.timeout_spins {
+                        Some(n) if self.spins >= n => TimeoutError.into(),
+                        _ => {
+                            self.current = Some(attempt);
+                            return Err(self);
+                        }
+                    }
+                }
+            };
+            self.spins = 0;
+            if self.retries == 0 {
+                return Ok(self.fallback.take().ok_or(e));
+            }
+            self.retries -= 1;
+        }
+    }
+}
+
+pub struct SharedSync<A>
+where
+    A: Future,
+{
+    inner: Arc<SharedSyncInner<A>>,
+}
+
+struct SharedSyncInner<A>
+where
+    A: Future,
+{
+    once: Once,
+    future: Mutex<Option<A>>,
+    result: Mutex<Option<Result<A::Item, A::Error>>>,
+}
+
+impl<A> Clone for SharedSync<A>
+where
+    A: Future,
+{
+    fn clone(&self) -> SharedSync<A> {
+        SharedSync {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<A> Future for SharedSync<A>
+where
+    A: Future,
+    A::Item: Clone,
+    A::Error: Clone,
+{
+    type Item = A::Item;
+    type Error = A::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let inner = &self.inner;
+        inner.once.call_once(|| {
+            let future = inner.future.lock().unwrap().take().unwrap();
+            let result = future.wait();
+            *inner.result.lock().unwrap() = Some(result);
+        });
+        Ok(inner.result.lock().unwrap().clone().unwrap())
+    }
+}
+
+pub struct Shared<A>
+where
+    A: Future,
+{
+    inner: Arc<AtomicCell<SharedState<A>>>,
+}
+
+// `future` is `None` while a clone has taken it out to poll, or once it's
+// done; the clones which found it gone are in `waiters` until it's back.
+// `poisoned` is set if it panicked while out, and it's never coming back.
+struct SharedState<A>
+where
+    A: Future,
+{
+    future: Option<A>,
+    #[allow(clippy::type_complexity)]
+    result: Option<Result<Arc<A::Item>, Arc<A::Error>>>,
+    waiters: Vec<task::Task>,
+    poisoned: bool,
+}
+
+// Held by the clone polling a `Shared` future, so that if the poll panics the
+// clones waiting for it are woken to find it poisoned.
+struct SharedPoll<'a, A>
+where
+    A: Future,
+{
+    inner: &'a AtomicCell<SharedState<A>>,
+}
+
+impl<A> Drop for SharedPoll<'_, A>
+where
+    A: Future,
+{
+    fn drop(&mut self) {
+        let waiters = {
+            let mut state = self.inner.borrow();
+            state.poisoned = true;
+            state.waiters.split_off(0)
+        };
+        for waiter in waiters {
+            waiter.unpark();
+        }
+    }
+}
+
+impl<A> Clone for Shared<A>
+where
+    A: Future,
+{
+    fn clone(&self) -> Shared<A> {
+        Shared {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<A> Future for Shared<A>
+where
+    A: Future,
+{
+    type Item = Arc<A::Item>;
+    type Error = Arc<A::Error>;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let future = match self.inner.try_borrow() {
+            Some(mut state) => {
+                if let Some(result) = &state.result {
+                    return Ok(result.clone());
+                }
+                if state.poisoned {
+                    panic!("a Shared future panicked while a clone was polling it");
+                }
+                if state.future.is_none() {
+                    state.waiters.push(task::park());
+                }
+                state.future.take()
+            }
+            // Some other clone is mid-update, so try again straight away.
+            None => {
+                task::park().unpark();
+                None
+            }
+        };
+        let future = match future {
+            Some(future) => future,
+            None => return Err(self),
+        };
+        // Poll without holding the cell, so other clones can register.
+        let poisoner = SharedPoll { inner: &self.inner };
+        let outcome = future.poll();
+        mem::forget(poisoner);
+        let (result, waiters) = {
+            let mut state = self.inner.borrow();
+            match outcome {
+                Ok(result) => {
+                    let result = result.map(Arc::new).map_err(Arc::new);
+                    state.result = Some(result.clone());
+                    (Some(result), state.waiters.split_off(0))
+                }
+                // Whoever was waiting for it can take it out again, and
+                // register with whatever it's waiting on.
+                Err(future) => {
+                    state.future = Some(future);
+                    (None, state.waiters.split_off(0))
+                }
+            }
+        };
+        for waiter in waiters {
+            waiter.unpark();
+        }
+        result.ok_or(self)
+    }
+}
+
+// One of two values. As a future it unifies two branches with the same item
+// and error types, e.g. to return different futures from an `if`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Either<A, B> {
+    Left(A),
+    Right(B),
+}
+
+// `Either::Left(a)` if `cond` holds, otherwise `Either::Right(b)`.
+pub fn either<A, B>(cond: bool, a: A, b: B) -> Either<A, B>
+where
+    A: Future,
+    B: Future<Item = A::Item, Error = A::Error>,
+{
+    if cond {
+        Either::Left(a)
+    } else {
+        Either::Right(b)
+    }
+}
+
+impl<A, B> Future for Either<A, B>
+where
+    A: Future,
+    B: Future<Item = A::Item, Error = A::Error>,
+{
+    type Item = A::Item;
+    type Error = A::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        match self {
+            Either::Left(a) => a.poll().map_err(Either::Left),
+            Either::Right(b) => b.poll().map_err(Either::Right),
+        }
+    }
+}
+
+pub struct TraceInto<A> {
+    future: A,
+    log: Rc<RefCell<Vec<&'static str>>>,
+}
+
+impl<A> Future for TraceInto<A>
+where
+    A: Future,
+{
+    type Item = A::Item;
+    type Error = A::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let TraceInto { future, log } = self;
+        log.borrow_mut().push("poll");
+        match future.poll() {
+            Ok(result) => {
+                log.borrow_mut().push(if result.is_ok() {
+                    "ready_ok"
+                } else {
+                    "ready_err"
+                });
+                Ok(result)
+            }
+            Err(future) => Err(TraceInto { future, log }),
+        }
+    }
+}
+
+// How a traced poll went.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TraceOutcome {
+    Pending,
+    Ok,
+    Err,
 }
 
+// One poll of a traced future, as handed to its `TraceSink`.
 #[derive(Copy, Clone, Debug)]
-pub struct FutureResult<T, E> {
-    inner: Result<T, E>,
+pub struct PollEvent {
+    pub label: &'static str,
+    // Counting from 1.
+    pub poll: usize,
+    // The future's `state_name` going into the poll.
+    pub state: &'static str,
+    pub outcome: TraceOutcome,
+    // Since the first poll started.
+    pub elapsed: Duration,
 }
 
-impl<T, E> IntoFuture for Result<T, E> {
-    type Future = FutureResult<T, E>;
-    type Item = T;
-    type Error = E;
+pub trait TraceSink {
+    fn record(&self, event: &PollEvent);
+}
 
-    fn into_future(self) -> FutureResult<T, E> {
-        FutureResult { inner: self }
+// Writes one line per event to stderr.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct StderrSink;
+
+impl TraceSink for StderrSink {
+    fn record(&self, event: &PollEvent) {
+        eprintln!(
+            "[{}] poll {} in {}: {:?} after {:?}",
+            event.label, event.poll, event.state, event.outcome, event.elapsed
+        );
     }
 }
 
-impl<T, E> Future for FutureResult<T, E> {
-    type Item = T;
-    type Error = E;
+pub struct Trace<A, K> {
+    future: A,
+    label: &'static str,
+    sink: K,
+    polls: usize,
+    started: Option<Instant>,
+}
 
-    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
-        Ok(self.inner)
+impl<A, K> Future for Trace<A, K>
+where
+    A: Future,
+    K: TraceSink,
+{
+    type Item = A::Item;
+    type Error = A::Error;
+
+    fn poll(mut self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let started = *self.started.get_or_insert_with(Instant::now);
+        self.polls += 1;
+        let state = self.future.state_name();
+        let Trace {
+            future,
+            label,
+            sink,
+            polls,
+            started: _,
+        } = self;
+        let (outcome, result) = match future.poll() {
+            Ok(Ok(item)) => (TraceOutcome::Ok, Ok(Ok(item))),
+            Ok(Err(e)) => (TraceOutcome::Err, Ok(Err(e))),
+            Err(future) => (TraceOutcome::Pending, Err(future)),
+        };
+        sink.record(&PollEvent {
+            label,
+            poll: polls,
+            state,
+            outcome,
+            elapsed: started.elapsed(),
+        });
+        result.map_err(|future| Trace {
+            future,
+            label,
+            sink,
+            polls,
+            started: Some(started),
+        })
+    }
+
+    fn state_name(&self) -> &'static str {
+        self.future.state_name()
     }
 }
 
-#[derive(Copy, Clone, Debug)]
-pub struct Map<A, F> {
+pub struct ComposeN<A, F> {
     future: A,
+    remaining: usize,
     f: F,
 }
 
-impl<U, A, F> Future for Map<A, F>
+// Drives `init`, then `n` times over builds the next future from the last
+// one's item with `f`, resolving to the final item. The first error ends
+// the chain.
+pub fn compose_n<F, A>(init: A, n: usize, f: F) -> ComposeN<A, F>
 where
+    F: FnMut(A::Item) -> A,
     A: Future,
-    F: FnOnce(A::Item) -> U,
 {
-    type Item = U;
+    ComposeN {
+        future: init,
+        remaining: n,
+        f,
+    }
+}
+
+impl<A, F> Future for ComposeN<A, F>
+where
+    F: FnMut(A::Item) -> A,
+    A: Future,
+{
+    type Item = A::Item;
     type Error = A::Error;
 
-    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
-        match self.future.poll() {
-            Ok(result) => Ok(result.map(self.f)),
-            Err(f) => Err(Map {
-                future: f,
-                f: self.f,
-            }),
+    fn poll(mut self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        loop {
+            match self.future.poll() {
+                Ok(Ok(item)) if self.remaining == 0 => return Ok(Ok(item)),
+                Ok(Ok(item)) => {
+                    self.remaining -= 1;
+                    self.future = (self.f)(item);
+                }
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(a) => {
+                    self.future = a;
+                    return Err(self);
+                }
+            }
         }
     }
 }
 
-pub struct MapErr<A, F> {
+pub struct UntilFlag<A> {
     future: A,
-    f: F,
+    flag: Rc<AtomicCell<bool>>,
 }
 
-impl<A, E, F> Future for MapErr<A, F>
+impl<A> Future for UntilFlag<A>
 where
     A: Future,
-    F: FnOnce(A::Error) -> E,
+    A::Error: From<Canceled>,
 {
     type Item = A::Item;
-    type Error = E;
+    type Error = A::Error;
 
     fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
-        match self.future.poll() {
-            Ok(result) => Ok(result.map_err(self.f)),
-            Err(f) => Err(MapErr {
-                future: f,
-                f: self.f,
-            }),
+        let set = self.flag.try_borrow().map(|flag| *flag);
+        match set {
+            Some(true) => return Ok(Err(Canceled.into())),
+            Some(false) => {}
+            None => return Err(self),
         }
+        let UntilFlag { future, flag } = self;
+        future.poll().map_err(|future| UntilFlag { future, flag })
     }
 }
 
-pub struct AndThen<A, B, F>
-where
-    B: IntoFuture,
-{
-    future: _AndThen<A, B::Future, F>,
+#[derive(Clone)]
+pub struct CancellationHandle {
+    inner: Arc<CancelInner>,
 }
 
-enum _AndThen<A, B, F> {
-    First(A, F),
-    Second(B),
+struct CancelInner {
+    canceled: AtomicBool,
+    task: AtomicCell<Option<task::Task>>,
 }
 
-impl<A, B, F> Future for AndThen<A, B, F>
+impl CancellationHandle {
+    pub fn cancel(&self) {
+        self.inner.canceled.store(true, atomic::Ordering::SeqCst);
+        if let Some(task) = self.inner.task.borrow().take() {
+            task.unpark();
+        }
+    }
+
+    pub fn is_canceled(&self) -> bool {
+        self.inner.canceled.load(atomic::Ordering::SeqCst)
+    }
+}
+
+pub struct Cancellable<A> {
+    future: A,
+    handle: CancellationHandle,
+}
+
+impl<A> Future for Cancellable<A>
 where
     A: Future,
-    B: IntoFuture<Error = A::Error>,
-    F: FnOnce(A::Item) -> B,
+    A::Error: From<Canceled>,
 {
-    type Item = B::Item;
-    type Error = B::Error;
+    type Item = A::Item;
+    type Error = A::Error;
 
     fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
-        let second = match self.future {
-            _AndThen::First(a, f) => match a.poll() {
-                Ok(Ok(next)) => f(next).into_future(),
-                Ok(Err(e)) => return Ok(Err(e)),
-                Err(a) => {
-                    return Err(AndThen {
-                        future: _AndThen::First(a, f),
-                    })
+        if self.handle.is_canceled() {
+            return Ok(Err(Canceled.into()));
+        }
+        let Cancellable { future, handle } = self;
+        match future.poll() {
+            Ok(result) => Ok(result),
+            Err(future) => {
+                *handle.inner.task.borrow() = Some(task::park());
+                // `cancel` may have run before seeing this task.
+                if handle.is_canceled() {
+                    task::park().unpark();
                 }
-            },
-            _AndThen::Second(b) => b,
-        };
-        second.poll().map_err(|b| AndThen {
-            future: _AndThen::Second(b),
-        })
+                Err(Cancellable { future, handle })
+            }
+        }
     }
 }
 
-pub struct OrElse<A, B, F>
+// What a `loop_fn` step resolves to: the loop's outcome, or the state to
+// run the next step from.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Loop<T, S> {
+    Break(T),
+    Continue(S),
+}
+
+pub struct LoopFn<F, B>
 where
     B: IntoFuture,
 {
-    future: _OrElse<A, B::Future, F>,
+    f: F,
+    step: B::Future,
 }
 
-enum _OrElse<A, B, F> {
-    First(A, F),
-    Second(B),
+// Runs the future `f` builds from `initial`, then from each state it
+// continues with, until one breaks with the loop's item. Every step lives in
+// the same `LoopFn`, so iterating doesn't nest types the way recursing
+// through `and_then` would. An error from any step ends the loop.
+pub fn loop_fn<S, T, F, B>(initial: S, mut f: F) -> LoopFn<F, B>
+where
+    F: FnMut(S) -> B,
+    B: IntoFuture<Item = Loop<T, S>>,
+{
+    let step = f(initial).into_future();
+    LoopFn { f, step }
 }
 
-impl<A, B, F> Future for OrElse<A, B, F>
+impl<S, T, F, B> Future for LoopFn<F, B>
 where
-    A: Future,
-    B: IntoFuture<Item = A::Item>,
-    F: FnOnce(A::Error) -> B,
+    F: FnMut(S) -> B,
+    B: IntoFuture<Item = Loop<T, S>>,
 {
-    type Item = B::Item;
+    type Item = T;
     type Error = B::Error;
 
-    // Returns the option if it contains a value, otherwise calls f and returns the result.
     fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
-        let second = match self.future {
-            _OrElse::First(a, f) => match a.poll() {
-                Ok(Ok(next)) => return Ok(Ok(next)),
-                Ok(Err(e)) => f(e).into_future(),
-                Err(a) => {
-                    return Err(OrElse {
-                        future: _OrElse::First(a, f),
-                    })
-                }
-            },
-            _OrElse::Second(b) => b,
-        };
-        second.poll().map_err(|b| OrElse {
-            future: _OrElse::Second(b),
-        })
+        let LoopFn { mut f, mut step } = self;
+        loop {
+            match step.poll() {
+                Ok(Ok(Loop::Break(item))) => return Ok(Ok(item)),
+                Ok(Ok(Loop::Continue(state))) => step = f(state).into_future(),
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(step) => return Err(LoopFn { f, step }),
+            }
+        }
     }
 }
 
-impl<T> Future for Receiver<T> {
-    type Item = T;
-    type Error = RecvError;
+// How `retry` goes about it: at most `attempts` attempts in all, pausing
+// `delay` before the first retry and multiplying the pause by `backoff`
+// before each one after it, up to `max_delay`. A plain attempt count
+// converts into a policy which retries straight away.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    attempts: usize,
+    delay: Duration,
+    backoff: u32,
+    max_delay: Duration,
+}
 
-    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
-        match self.try_recv() {
-            Ok(msg) => Ok(Ok(msg)),
-            Err(TryRecvError::Empty) => Err(self),
-            Err(TryRecvError::Disconnected) => Ok(Err(RecvError)),
+// The longest `retry` pauses for unless told otherwise.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+impl RetryPolicy {
+    pub fn attempts(attempts: usize) -> RetryPolicy {
+        assert!(attempts > 0, "retry needs at least one attempt");
+        RetryPolicy {
+            attempts,
+            delay: Duration::from_secs(0),
+            backoff: 1,
+            max_delay: MAX_RETRY_DELAY,
         }
     }
+
+    pub fn delay(mut self, delay: Duration) -> RetryPolicy {
+        self.delay = delay;
+        self
+    }
+
+    pub fn backoff(mut self, factor: u32) -> RetryPolicy {
+        self.backoff = factor;
+        self
+    }
+
+    pub fn max_delay(mut self, max_delay: Duration) -> RetryPolicy {
+        self.max_delay = max_delay;
+        self
+    }
 }
 
-#[derive(Debug)]
-pub struct Empty<T, E> {
-    _marker: marker::PhantomData<(T, E)>,
+impl From<usize> for RetryPolicy {
+    fn from(attempts: usize) -> RetryPolicy {
+        RetryPolicy::attempts(attempts)
+    }
 }
 
-impl<T, E> Empty<T, E> {
-    pub fn new() -> Empty<T, E> {
-        Empty {
-            _marker: marker::PhantomData,
+pub struct Retry<F, B>
+where
+    B: IntoFuture,
+{
+    factory: F,
+    current: Option<B::Future>,
+    // Attempts which may still be started after the current one.
+    remaining: usize,
+    // Waited out before the next attempt is built.
+    pause: Option<timer::Delay>,
+    delay: Duration,
+    backoff: u32,
+    max_delay: Duration,
+}
+
+// Runs a future built by `f`, building and running a fresh one each time it
+// fails, as often as `policy` allows. The first attempt is built on the
+// first poll, and after the last one its error is returned.
+pub fn retry<P, F, B>(policy: P, f: F) -> Retry<F, B>
+where
+    P: Into<RetryPolicy>,
+    F: FnMut() -> B,
+    B: IntoFuture,
+{
+    let policy = policy.into();
+    Retry {
+        factory: f,
+        current: None,
+        remaining: policy.attempts - 1,
+        pause: None,
+        delay: policy.delay.min(policy.max_delay),
+        backoff: policy.backoff,
+        max_delay: policy.max_delay,
+    }
+}
+
+impl<F, B> Future for Retry<F, B>
+where
+    F: FnMut() -> B,
+    B: IntoFuture,
+{
+    type Item = B::Item;
+    type Error = B::Error;
+
+    fn poll(mut self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        loop {
+            if let Some(pause) = self.pause.take() {
+                if let Err(pause) = pause.poll() {
+                    self.pause = Some(pause);
+                    return Err(self);
+                }
+            }
+            let attempt = match self.current.take() {
+                Some(attempt) => attempt,
+                None => (self.factory)().into_future(),
+            };
+            match attempt.poll() {
+                Ok(Ok(item)) => return Ok(Ok(item)),
+                Ok(Err(e)) if self.remaining == 0 => return Ok(Err(e)),
+                Ok(Err(_)) => {
+                    self.remaining -= 1;
+                    if self.delay > Duration::from_secs(0) {
+                        self.pause = Some(timer::Delay::new(self.delay));
+                        self.delay = match self.delay.checked_mul(self.backoff) {
+                            Some(delay) => delay.min(self.max_delay),
+                            None => self.max_delay,
+                        };
+                    }
+                }
+                Err(attempt) => {
+                    self.current = Some(attempt);
+                    return Err(self);
+                }
+            }
         }
     }
 }
 
-impl<T, E> Future for Empty<T, E> {
-    type Item = T;
-    type Error = E;
+// The pending polls `retry_jitter` waits before each retry: `base` plus an
+// offset of up to `base` drawn from a linear congruential generator seeded
+// with `seed`, so the same seed always gives the same delays.
+pub fn jitter_delays(base: usize, seed: u64) -> JitterDelays {
+    JitterDelays { base, state: seed }
+}
 
-    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
-        Err(self)
+#[derive(Copy, Clone, Debug)]
+pub struct JitterDelays {
+    base: usize,
+    state: u64,
+}
+
+impl Iterator for JitterDelays {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        self.state = self
+            .state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        let offset = (self.state >> 33) as usize % (self.base + 1);
+        Some(self.base + offset)
     }
 }
 
-impl<T, E> Clone for Empty<T, E> {
-    fn clone(&self) -> Empty<T, E> {
-        Empty::new()
+pub struct RetryJitter<F, B>
+where
+    B: IntoFuture,
+{
+    factory: F,
+    current: Option<B::Future>,
+    remaining: usize,
+    delays: JitterDelays,
+    // Pending polls left before the next attempt is built.
+    delay: usize,
+}
+
+// Like `retry`, but each retry waits a number of pending polls taken from
+// `jitter_delays(base, seed)`, counting the poll on which the previous
+// attempt failed. After `max` attempts in all the last error is returned.
+pub fn retry_jitter<F, B>(factory: F, base: usize, max: usize, seed: u64) -> RetryJitter<F, B>
+where
+    F: FnMut() -> B,
+    B: IntoFuture,
+{
+    assert!(max > 0, "retry_jitter needs at least one attempt");
+    RetryJitter {
+        factory,
+        current: None,
+        remaining: max - 1,
+        delays: jitter_delays(base, seed),
+        delay: 0,
     }
 }
 
-impl<T, E> Copy for Empty<T, E> {}
+impl<F, B> Future for RetryJitter<F, B>
+where
+    F: FnMut() -> B,
+    B: IntoFuture,
+{
+    type Item = B::Item;
+    type Error = B::Error;
 
-pub struct Select<A, B> {
-    a: A,
-    b: B,
+    fn poll(mut self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        loop {
+            if self.delay > 0 {
+                self.delay -= 1;
+                return Err(self);
+            }
+            let attempt = match self.current.take() {
+                Some(attempt) => attempt,
+                None => (self.factory)().into_future(),
+            };
+            match attempt.poll() {
+                Ok(Ok(item)) => return Ok(Ok(item)),
+                Ok(Err(e)) if self.remaining == 0 => return Ok(Err(e)),
+                Ok(Err(_)) => {
+                    self.remaining -= 1;
+                    self.delay = self.delays.next().unwrap();
+                }
+                Err(attempt) => {
+                    self.current = Some(attempt);
+                    return Err(self);
+                }
+            }
+        }
+    }
 }
 
-impl<A, B> Future for Select<A, B>
+pub struct FallbackChain<I, A>
 where
-    A: Future,
-    B: Future<Item = A::Item, Error = A::Error>,
+    A: IntoFuture,
 {
-    type Item = A::Item;
-    type Error = A::Error;
+    factories: I,
+    current: Option<A::Future>,
+}
 
-    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
-        let Select { a, b } = self;
-        a.poll().or_else(|a| b.poll().map_err(|b| Select { a, b }))
+// Runs the future built by each factory in turn until one succeeds,
+// resolving to its item, or to the last error if they all fail. A factory
+// is only called once the one before it has failed.
+//
+// Panics when polled if `factories` is empty.
+pub fn fallback_chain<I, A>(factories: I) -> FallbackChain<I::IntoIter, A>
+where
+    I: IntoIterator,
+    I::Item: FnOnce() -> A,
+    A: IntoFuture,
+{
+    FallbackChain {
+        factories: factories.into_iter(),
+        current: None,
     }
 }
 
-pub struct Join<A, B>
+impl<I, A> Future for FallbackChain<I, A>
 where
-    A: Future,
-    B: Future<Error = A::Error>,
+    I: Iterator,
+    I::Item: FnOnce() -> A,
+    A: IntoFuture,
 {
-    state: _Join<A, B>,
+    type Item = A::Item;
+    type Error = A::Error;
+
+    fn poll(mut self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let mut current = match self.current.take() {
+            Some(current) => current,
+            None => match self.factories.next() {
+                Some(factory) => factory().into_future(),
+                None => panic!("fallback_chain needs at least one factory"),
+            },
+        };
+        loop {
+            match current.poll() {
+                Ok(Ok(item)) => return Ok(Ok(item)),
+                Ok(Err(e)) => match self.factories.next() {
+                    Some(factory) => current = factory().into_future(),
+                    None => return Ok(Err(e)),
+                },
+                Err(current) => {
+                    self.current = Some(current);
+                    return Err(self);
+                }
+            }
+        }
+    }
 }
 
-enum _Join<A, B>
+pub struct SendResult<A>
 where
     A: Future,
-    B: Future<Error = A::Error>,
 {
-    Both(A, B),
-    First(A, Result<B::Item, A::Error>),
-    Second(Result<A::Item, A::Error>, B),
+    future: A,
+    tx: Sender<Result<A::Item, A::Error>>,
 }
 
-impl<A, B> Future for Join<A, B>
+impl<A> Future for SendResult<A>
 where
     A: Future,
-    B: Future<Error = A::Error>,
 {
-    type Item = (A::Item, B::Item);
-    type Error = A::Error;
+    type Item = ();
+    type Error = Never;
 
     fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
-        let (a, b) = match self.state {
-            _Join::Both(a, b) => (a.poll(), b.poll()),
-            _Join::First(a, b) => (a.poll(), Ok(b)),
-            _Join::Second(a, b) => (Ok(a), b.poll()),
-        };
-        match (a, b) {
-            (Ok(Err(e)), _) | (_, Ok(Err(e))) => Ok(Err(e)),
-            (Ok(Ok(a)), Ok(Ok(b))) => Ok(Ok((a, b))),
-            (Err(a), Ok(b)) => Err(Join {
-                state: _Join::First(a, b),
-            }),
-            (Ok(a), Err(b)) => Err(Join {
-                state: _Join::Second(a, b),
-            }),
-            (Err(a), Err(b)) => Err(Join {
-                state: _Join::Both(a, b),
-            }),
+        let SendResult { future, tx } = self;
+        match future.poll() {
+            Ok(result) => {
+                let _ = tx.send(result);
+                Ok(Ok(()))
+            }
+            Err(future) => Err(SendResult { future, tx }),
         }
     }
 }
+
+// Drives `f` to completion on a thread of its own and throws the outcome
+// away. A panic in `f` only takes down that thread.
+pub fn spawn_detached<F>(f: F)
+where
+    F: Future + Send + 'static,
+{
+    thread::spawn(move || {
+        let _ = f.wait();
+    });
+}
@@ -0,0 +1,191 @@
+use std::sync::Arc;
+
+use crate::cell::AtomicCell;
+use crate::task::{self, Task};
+use crate::Future;
+
+pub use crate::Canceled;
+
+// The value passed to `Complete::complete`, and whether the `Complete` is
+// gone.
+// The task last seen waiting is unparked when either changes. `canceled`
+// records that the `Oneshot` is gone, so nobody wants the value any more.
+struct Inner<T> {
+    value: Option<T>,
+    done: bool,
+    canceled: bool,
+    task: Option<Task>,
+}
+
+pub struct Complete<T> {
+    inner: Arc<AtomicCell<Inner<T>>>,
+}
+
+// A future resolving to the value given to the matching `Complete`, or to
+// `Canceled` if it's dropped without completing.
+pub struct Oneshot<T> {
+    inner: Arc<AtomicCell<Inner<T>>>,
+}
+
+// A channel for sending a single value, e.g. to resolve a future from a
+// callback.
+pub fn oneshot<T>() -> (Complete<T>, Oneshot<T>) {
+    let inner = Arc::new(AtomicCell::new(Inner {
+        value: None,
+        done: false,
+        canceled: false,
+        task: None,
+    }));
+    (
+        Complete {
+            inner: inner.clone(),
+        },
+        Oneshot { inner },
+    )
+}
+
+impl<T> Complete<T> {
+    pub fn complete(self, value: T) {
+        let task = {
+            let mut inner = self.inner.borrow();
+            inner.value = Some(value);
+            inner.task.take()
+        };
+        if let Some(task) = task {
+            task.unpark();
+        }
+    }
+
+    // Whether the matching `Oneshot` has been dropped, in which case the
+    // work to produce a value can be abandoned.
+    pub fn is_canceled(&self) -> bool {
+        self.inner.borrow().canceled
+    }
+}
+
+impl<T> Drop for Complete<T> {
+    fn drop(&mut self) {
+        let task = {
+            let mut inner = self.inner.borrow();
+            inner.done = true;
+            inner.task.take()
+        };
+        if let Some(task) = task {
+            task.unpark();
+        }
+    }
+}
+
+impl<T> Future for Oneshot<T> {
+    type Item = T;
+    type Error = Canceled;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        // The sender holding the cell means it's mid-write and may not see
+        // this task, so ask to be polled again straight away.
+        let result = match self.inner.try_borrow() {
+            Some(mut inner) => match inner.value.take() {
+                Some(value) => Some(Ok(value)),
+                None if inner.done => Some(Err(Canceled)),
+                None => {
+                    inner.task = Some(task::park());
+                    None
+                }
+            },
+            None => {
+                task::park().unpark();
+                None
+            }
+        };
+        result.ok_or(self)
+    }
+}
+
+impl<T> Drop for Oneshot<T> {
+    fn drop(&mut self) {
+        self.inner.borrow().canceled = true;
+    }
+}
+
+// The outcome set by a `SlotWriter`, and whether the writer is gone. Like
+// `Inner`, the reader's task is unparked when either changes.
+struct Slot<T, E> {
+    result: Option<Result<T, E>>,
+    done: bool,
+    task: Option<Task>,
+}
+
+// Sets the outcome read by the matching `SlotReader`.
+pub struct SlotWriter<T, E> {
+    slot: Arc<AtomicCell<Slot<T, E>>>,
+}
+
+// A future resolving to whatever outcome the matching `SlotWriter` sets, or
+// failing with `Canceled` if the writer goes away without setting one.
+pub struct SlotReader<T, E> {
+    slot: Arc<AtomicCell<Slot<T, E>>>,
+}
+
+// A slot for handing a whole outcome from one place to another, e.g. the
+// result of a future driven on another thread.
+pub fn oneshot_slot<T, E>() -> (SlotWriter<T, E>, SlotReader<T, E>) {
+    let slot = Arc::new(AtomicCell::new(Slot {
+        result: None,
+        done: false,
+        task: None,
+    }));
+    (SlotWriter { slot: slot.clone() }, SlotReader { slot })
+}
+
+impl<T, E> SlotWriter<T, E> {
+    pub fn set(self, result: Result<T, E>) {
+        let task = {
+            let mut slot = self.slot.borrow();
+            slot.result = Some(result);
+            slot.task.take()
+        };
+        if let Some(task) = task {
+            task.unpark();
+        }
+    }
+}
+
+impl<T, E> Drop for SlotWriter<T, E> {
+    fn drop(&mut self) {
+        let task = {
+            let mut slot = self.slot.borrow();
+            slot.done = true;
+            slot.task.take()
+        };
+        if let Some(task) = task {
+            task.unpark();
+        }
+    }
+}
+
+impl<T, E> Future for SlotReader<T, E>
+where
+    E: From<Canceled>,
+{
+    type Item = T;
+    type Error = E;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        // As with `Oneshot`, a writer mid-write may not see this task.
+        let result = match self.slot.try_borrow() {
+            Some(mut slot) => match slot.result.take() {
+                Some(result) => Some(result),
+                None if slot.done => Some(Err(From::from(Canceled))),
+                None => {
+                    slot.task = Some(task::park());
+                    None
+                }
+            },
+            None => {
+                task::park().unpark();
+                None
+            }
+        };
+        result.ok_or(self)
+    }
+}
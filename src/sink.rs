@@ -0,0 +1,252 @@
+use std::marker;
+use std::sync::mpsc::{SendError, Sender};
+
+use crate::stream::Stream;
+use crate::{Future, Never};
+
+// The outcome of offering an item to a sink `S`: the sink back once it has
+// taken the item, or the sink and the item if it can't accept it yet.
+pub type StartSend<S> = Result<Result<S, <S as Sink>::SinkError>, (S, <S as Sink>::SinkItem)>;
+
+// The outcome of flushing a sink `S`, which is handed back through `Err`
+// while items are still in flight.
+pub type PollComplete<S> = Result<Result<S, <S as Sink>::SinkError>, S>;
+
+// A sink is somewhere values can be sent asynchronously, the counterpart of
+// a `Stream`. It follows the same by-value convention: every call consumes
+// the sink and hands it back, and a sink which isn't ready hands the item
+// back too so nothing is lost.
+pub trait Sink {
+    type SinkItem;
+    type SinkError;
+
+    fn start_send(self, item: Self::SinkItem) -> StartSend<Self>
+    where
+        Self: Sized;
+
+    fn poll_complete(self) -> PollComplete<Self>
+    where
+        Self: Sized;
+
+    // Sends every item from `stream` and flushes, resolving to the sink once
+    // the stream has ended. An error from either side fails the future.
+    fn send_all<S>(self, stream: S) -> SendAll<Self, S>
+    where
+        S: Stream<Item = Self::SinkItem>,
+        Self::SinkError: From<S::Error>,
+        Self: Sized,
+    {
+        SendAll {
+            sink: self,
+            stream: Some(stream),
+            pending: None,
+        }
+    }
+
+    // A sink taking `U`s, each converted with `f` before being passed on.
+    // An item the inner sink refuses is held and retried before the next.
+    fn with<F, U>(self, f: F) -> With<Self, F, U>
+    where
+        F: FnMut(U) -> Self::SinkItem,
+        Self: Sized,
+    {
+        With::from_parts(self, f, None)
+    }
+}
+
+// Offers `pending` to `sink`, flushing once if it's refused.
+pub(crate) fn send_pending<K: Sink>(
+    sink: K,
+    pending: Option<K::SinkItem>,
+) -> Result<(K, Option<K::SinkItem>), K::SinkError> {
+    let item = match pending {
+        Some(item) => item,
+        None => return Ok((sink, None)),
+    };
+    match sink.start_send(item) {
+        Ok(Ok(sink)) => Ok((sink, None)),
+        Ok(Err(e)) => Err(e),
+        Err((sink, item)) => match sink.poll_complete() {
+            Ok(Ok(sink)) | Err(sink) => Ok((sink, Some(item))),
+            Ok(Err(e)) => Err(e),
+        },
+    }
+}
+
+impl<T> Sink for Vec<T> {
+    type SinkItem = T;
+    type SinkError = Never;
+
+    fn start_send(mut self, item: T) -> StartSend<Self> {
+        self.push(item);
+        Ok(Ok(self))
+    }
+
+    fn poll_complete(self) -> PollComplete<Self> {
+        Ok(Ok(self))
+    }
+}
+
+// Every item goes straight onto the channel, failing once the receiver has
+// hung up.
+impl<T> Sink for Sender<T> {
+    type SinkItem = T;
+    type SinkError = SendError<T>;
+
+    fn start_send(self, item: T) -> StartSend<Self> {
+        match self.send(item) {
+            Ok(()) => Ok(Ok(self)),
+            Err(e) => Ok(Err(e)),
+        }
+    }
+
+    fn poll_complete(self) -> PollComplete<Self> {
+        Ok(Ok(self))
+    }
+}
+
+pub struct SendAll<K, S>
+where
+    K: Sink,
+{
+    sink: K,
+    // `None` once the stream has ended and only flushing is left.
+    stream: Option<S>,
+    pending: Option<K::SinkItem>,
+}
+
+impl<K, S> Future for SendAll<K, S>
+where
+    K: Sink,
+    S: Stream<Item = K::SinkItem>,
+    K::SinkError: From<S::Error>,
+{
+    type Item = K;
+    type Error = K::SinkError;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let SendAll {
+            sink,
+            mut stream,
+            pending,
+        } = self;
+        let (mut sink, mut pending) = match send_pending(sink, pending) {
+            Ok(sent) => sent,
+            Err(e) => return Ok(Err(e)),
+        };
+        while pending.is_none() {
+            let s = match stream.take() {
+                Some(s) => s,
+                None => break,
+            };
+            match s.poll() {
+                Ok(Ok(Some((item, s)))) => {
+                    stream = Some(s);
+                    let sent = match send_pending(sink, Some(item)) {
+                        Ok(sent) => sent,
+                        Err(e) => return Ok(Err(e)),
+                    };
+                    sink = sent.0;
+                    pending = sent.1;
+                }
+                Ok(Ok(None)) => {}
+                Ok(Err(e)) => return Ok(Err(K::SinkError::from(e))),
+                Err(s) => {
+                    stream = Some(s);
+                    break;
+                }
+            }
+        }
+        if stream.is_some() || pending.is_some() {
+            return Err(SendAll {
+                sink,
+                stream,
+                pending,
+            });
+        }
+        match sink.poll_complete() {
+            Ok(result) => Ok(result),
+            Err(sink) => Err(SendAll {
+                sink,
+                stream,
+                pending,
+            }),
+        }
+    }
+}
+
+pub struct With<K, F, U>
+where
+    K: Sink,
+{
+    sink: K,
+    f: F,
+    pending: Option<K::SinkItem>,
+    _marker: marker::PhantomData<fn(U)>,
+}
+
+impl<K, F, U> With<K, F, U>
+where
+    K: Sink,
+{
+    fn from_parts(sink: K, f: F, pending: Option<K::SinkItem>) -> With<K, F, U> {
+        With {
+            sink,
+            f,
+            pending,
+            _marker: marker::PhantomData,
+        }
+    }
+
+    pub fn get_ref(&self) -> &K {
+        &self.sink
+    }
+}
+
+impl<K, F, U> Sink for With<K, F, U>
+where
+    K: Sink,
+    F: FnMut(U) -> K::SinkItem,
+{
+    type SinkItem = U;
+    type SinkError = K::SinkError;
+
+    fn start_send(self, item: U) -> StartSend<Self> {
+        let With {
+            sink,
+            mut f,
+            pending,
+            ..
+        } = self;
+        let (sink, pending) = match send_pending(sink, pending) {
+            Ok(sent) => sent,
+            Err(e) => return Ok(Err(e)),
+        };
+        if pending.is_some() {
+            return Err((With::from_parts(sink, f, pending), item));
+        }
+        let converted = f(item);
+        match send_pending(sink, Some(converted)) {
+            Ok((sink, pending)) => Ok(Ok(With::from_parts(sink, f, pending))),
+            Err(e) => Ok(Err(e)),
+        }
+    }
+
+    fn poll_complete(self) -> PollComplete<Self> {
+        let With {
+            sink, f, pending, ..
+        } = self;
+        let (sink, pending) = match send_pending(sink, pending) {
+            Ok(sent) => sent,
+            Err(e) => return Ok(Err(e)),
+        };
+        if pending.is_some() {
+            return Err(With::from_parts(sink, f, pending));
+        }
+        match sink.poll_complete() {
+            Ok(Ok(sink)) => Ok(Ok(With::from_parts(sink, f, pending))),
+            Ok(Err(e)) => Ok(Err(e)),
+            Err(sink) => Err(With::from_parts(sink, f, pending)),
+        }
+    }
+}
@@ -0,0 +1,3792 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::Hash;
+use std::marker;
+use std::ops::{Add, Sub};
+use std::rc::Rc;
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::task::{Wake, Waker};
+
+use crate::cell::AtomicCell;
+use crate::sink::{send_pending, Sink};
+use crate::task::{self, Backoff, Task};
+use crate::{_JoinSlot, Future, IntoFuture, Never, TimeoutError};
+
+// The outcome of polling a stream `S`, spelled out in full as
+// `Result<Result<Option<(S::Item, S)>, S::Error>, S>`.
+pub type StreamPoll<S> = Result<Result<Option<(<S as Stream>::Item, S)>, <S as Stream>::Error>, S>;
+
+// A stream is the multi-value counterpart of a `Future`. It follows the same
+// by-value convention: `poll` consumes the stream and hands it back through
+// `Err(self)` while nothing is ready. Because a stream keeps going after an
+// item, a ready item is returned together with the stream that produced it,
+// and `Ok(Ok(None))` signals end-of-stream.
+pub trait Stream {
+    type Item;
+    type Error;
+
+    fn poll(self) -> StreamPoll<Self>
+    where
+        Self: Sized;
+
+    // Runs `f` over every item, resolving once the stream ends.
+    fn for_each<F>(self, f: F) -> ForEach<Self, F>
+    where
+        F: FnMut(Self::Item),
+        Self: Sized,
+    {
+        ForEach { stream: self, f }
+    }
+
+    // Like `for_each`, but `f` starts a future per item and up to `n` of them
+    // run at once, in whatever order they finish. Resolves once the stream
+    // has ended and every effect is done; the first error wins.
+    fn for_each_concurrent<F, B>(self, n: usize, f: F) -> ForEachConcurrent<Self, F, B>
+    where
+        F: FnMut(Self::Item) -> B,
+        B: Future<Item = (), Error = Self::Error>,
+        Self: Sized,
+    {
+        assert!(
+            n > 0,
+            "for_each_concurrent needs room for at least one effect"
+        );
+        ForEachConcurrent {
+            stream: Some(self),
+            f,
+            n,
+            running: Vec::new(),
+        }
+    }
+
+    fn map<F, U>(self, f: F) -> Map<Self, F>
+    where
+        F: FnMut(Self::Item) -> U,
+        Self: Sized,
+    {
+        Map { stream: self, f }
+    }
+
+    fn map_err<F, E>(self, f: F) -> MapErr<Self, F>
+    where
+        F: FnOnce(Self::Error) -> E,
+        Self: Sized,
+    {
+        MapErr { stream: self, f }
+    }
+
+    // Yields only the items for which `f` returns true.
+    fn filter<F>(self, f: F) -> Filter<Self, F>
+    where
+        F: FnMut(&Self::Item) -> bool,
+        Self: Sized,
+    {
+        Filter { stream: self, f }
+    }
+
+    // Runs the future built by `f` for each item in turn, yielding its item.
+    // No further item is pulled until the current future has resolved.
+    fn and_then<F, B>(self, f: F) -> AndThen<Self, F, B>
+    where
+        F: FnMut(Self::Item) -> B,
+        B: IntoFuture<Error = Self::Error>,
+        Self: Sized,
+    {
+        AndThen {
+            stream: self,
+            f,
+            current: None,
+        }
+    }
+
+    // Threads an accumulator through the future built by `f` for each item,
+    // resolving to the final value once the stream ends.
+    fn fold<F, T, B>(self, init: T, f: F) -> Fold<Self, F, T, B>
+    where
+        F: FnMut(T, Self::Item) -> B,
+        B: IntoFuture<Item = T, Error = Self::Error>,
+        Self: Sized,
+    {
+        Fold {
+            stream: self,
+            f,
+            state: _Fold::Ready(init),
+        }
+    }
+
+    // Resolves to every item in order, or to the first error.
+    fn collect(self) -> Collect<Self>
+    where
+        Self: Sized,
+    {
+        Collect {
+            inner: self.collect_with_capacity(0),
+        }
+    }
+
+    fn with_latest_from<S>(self, other: S) -> WithLatestFrom<Self, S>
+    where
+        S: Stream<Error = Self::Error>,
+        S::Item: Clone,
+        Self: Sized,
+    {
+        WithLatestFrom {
+            stream: self,
+            other: Some(other),
+            latest: None,
+        }
+    }
+
+    // For streams whose items are themselves results: an `Err` item is a
+    // per-item failure and the pull is retried, up to `max` times in a row,
+    // before it's promoted to a terminal stream error. Errors reported by the
+    // stream itself are always terminal and are never retried.
+    fn retry_each<T, E>(self, max: usize) -> RetryEach<Self>
+    where
+        Self: Stream<Item = Result<T, E>, Error = E> + Sized,
+    {
+        RetryEach {
+            stream: self,
+            max,
+            retries: 0,
+        }
+    }
+
+    fn pairwise(self) -> Pairwise<Self>
+    where
+        Self::Item: Clone,
+        Self: Sized,
+    {
+        Pairwise {
+            stream: self,
+            prev: None,
+        }
+    }
+
+    // Batches items, yielding a batch once `size` items are buffered or once
+    // `max_spins` polls have come up empty while something is buffered.
+    fn chunks_or_spins(self, size: usize, max_spins: usize) -> ChunksOrSpins<Self>
+    where
+        Self: Sized,
+    {
+        assert!(size > 0, "chunk size must be non-zero");
+        ChunksOrSpins {
+            stream: Some(self),
+            buf: Vec::with_capacity(size),
+            size,
+            max_spins,
+            spins: 0,
+        }
+    }
+
+    // Yields the elements of every `Ok` vector one at a time; an `Err` item
+    // ends the stream with that error.
+    fn flatten_ok_vecs<T, E>(self) -> FlattenOkVecs<Self, T>
+    where
+        Self: Stream<Item = Result<Vec<T>, E>> + Sized,
+        Self::Error: From<E>,
+    {
+        FlattenOkVecs {
+            stream: self,
+            current: Vec::new().into_iter(),
+        }
+    }
+
+    // Passes items through while storing a clone of the most recent one in
+    // `cell`, where other handles can read it without consuming the stream.
+    fn latest_into(self, cell: Rc<AtomicCell<Option<Self::Item>>>) -> LatestInto<Self>
+    where
+        Self::Item: Clone,
+        Self: Sized,
+    {
+        LatestInto { stream: self, cell }
+    }
+
+    // Runs up to `n` of the stream's futures at once, yielding each one's
+    // outcome in the original order. A failed future is just another item, so
+    // only an error from the stream itself ends the stream.
+    fn buffered_results(self, n: usize) -> BufferedResults<Self>
+    where
+        Self::Item: IntoFuture,
+        Self: Sized,
+    {
+        assert!(n > 0, "buffer size must be non-zero");
+        BufferedResults {
+            stream: Some(self),
+            queue: VecDeque::with_capacity(n),
+            n,
+        }
+    }
+
+    // Like `buffered_results`, but a failed future's error is turned into a
+    // fallback item by `recover`, which takes its place in the output.
+    fn buffered_recover<F>(self, n: usize, recover: F) -> BufferedRecover<Self, F>
+    where
+        Self::Item: IntoFuture,
+        F: FnMut(<Self::Item as IntoFuture>::Error) -> <Self::Item as IntoFuture>::Item,
+        Self: Sized,
+    {
+        BufferedRecover {
+            inner: self.buffered_results(n),
+            recover,
+        }
+    }
+
+    // Maps each item to a future with `f`, keeping up to `n` of them running
+    // at once and yielding their items in input order. A failing future
+    // ends the stream with its error.
+    fn map_buffered<F, B>(self, n: usize, f: F) -> MapBuffered<Self, B, F>
+    where
+        F: FnMut(Self::Item) -> B,
+        B: IntoFuture<Error = Self::Error>,
+        Self: Sized,
+    {
+        assert!(n > 0, "buffer size must be non-zero");
+        MapBuffered {
+            stream: Some(self),
+            queue: VecDeque::with_capacity(n),
+            n,
+            f,
+        }
+    }
+
+    // Drains the stream while remembering its last `capacity` items, which
+    // subscribers replay before seeing new ones. Subscribers only observe
+    // items: they end when the source does, whether it ended or failed, and
+    // a subscriber lagging more than `capacity` items behind skips ahead.
+    fn replay(self, capacity: usize) -> (ReplayStream<Self>, ReplaySubscriber<Self::Item>)
+    where
+        Self::Item: Clone,
+        Self: Sized,
+    {
+        assert!(capacity > 0, "replay capacity must be non-zero");
+        let stream = ReplayStream {
+            stream: self,
+            shared: Rc::new(RefCell::new(Replay {
+                buffer: VecDeque::with_capacity(capacity),
+                capacity,
+                next_seq: 0,
+                done: false,
+            })),
+        };
+        let subscriber = stream.subscribe();
+        (stream, subscriber)
+    }
+
+    // Yields the sum of the last `window` items for every item from the
+    // `window`th on, keeping a running total rather than re-summing.
+    fn rolling_sum(self, window: usize) -> RollingSum<Self>
+    where
+        Self::Item: Clone + Add<Output = Self::Item> + Sub<Output = Self::Item>,
+        Self: Sized,
+    {
+        assert!(window > 0, "window must be non-zero");
+        RollingSum {
+            stream: self,
+            window,
+            buf: VecDeque::with_capacity(window + 1),
+            total: None,
+        }
+    }
+
+    // Sends a clone of every item to both `a` and `b`, resolving to the two
+    // sinks once the stream has ended and both have been flushed. Each sink
+    // applies backpressure independently: an item one sink refuses is held
+    // for it alone, and the stream isn't pulled again until both have taken
+    // the current item.
+    fn fanout<A, B>(self, a: A, b: B) -> Fanout<Self, A, B>
+    where
+        A: Sink<SinkItem = Self::Item, SinkError = Self::Error>,
+        B: Sink<SinkItem = Self::Item, SinkError = Self::Error>,
+        Self::Item: Clone,
+        Self: Sized,
+    {
+        Fanout {
+            stream: Some(self),
+            a,
+            b,
+            pending_a: None,
+            pending_b: None,
+        }
+    }
+
+    // Yields all but the last `n` items, holding `n` back at all times and
+    // dropping them when the stream ends.
+    fn skip_last(self, n: usize) -> SkipLast<Self>
+    where
+        Self: Sized,
+    {
+        SkipLast {
+            stream: self,
+            n,
+            buf: VecDeque::with_capacity(n + 1),
+        }
+    }
+
+    // Passes items through unchanged, folding a copy of each into the
+    // shared `cell` with `f` so observers can read the running aggregate.
+    // As with `latest_into`, a poll while the cell is borrowed comes up
+    // empty rather than losing the item.
+    fn accumulate_into<T, F>(self, cell: Rc<AtomicCell<T>>, f: F) -> AccumulateInto<Self, T, F>
+    where
+        F: FnMut(&mut T, Self::Item),
+        Self::Item: Clone,
+        Self: Sized,
+    {
+        AccumulateInto {
+            stream: self,
+            cell,
+            f,
+        }
+    }
+
+    // Groups items into batches, starting a new one at every item matching
+    // `p`. The batch in progress is yielded when the stream ends, unless
+    // it's empty.
+    fn split_before<P>(self, p: P) -> SplitBefore<Self, P>
+    where
+        P: FnMut(&Self::Item) -> bool,
+        Self: Sized,
+    {
+        SplitBefore {
+            stream: Some(self),
+            batch: Vec::new(),
+            p,
+        }
+    }
+
+    // Collapses each run of equal consecutive items into the item and the
+    // length of the run.
+    fn run_length_encode(self) -> RunLength<Self>
+    where
+        Self::Item: PartialEq + Clone,
+        Self: Sized,
+    {
+        RunLength {
+            stream: Some(self),
+            run: None,
+        }
+    }
+
+    // Fails with `TimeoutError` on the first poll after `max` polls, however
+    // many items have been yielded, bounding the lifetime of the whole
+    // stream rather than the wait for any one item.
+    fn total_timeout_spins(self, max: usize) -> TotalTimeout<Self>
+    where
+        Self::Error: From<TimeoutError>,
+        Self: Sized,
+    {
+        TotalTimeout {
+            stream: self,
+            polls: 0,
+            max,
+        }
+    }
+
+    // Calls `f` with a reference to each item as it passes through.
+    fn inspect<F>(self, f: F) -> Inspect<Self, F>
+    where
+        F: FnMut(&Self::Item),
+        Self: Sized,
+    {
+        Inspect { stream: self, f }
+    }
+
+    // Pulls and discards every item, resolving once the stream ends. Useful
+    // for streams run purely for their side effects.
+    fn drain(self) -> Drain<Self>
+    where
+        Self: Sized,
+    {
+        Drain { stream: self }
+    }
+
+    // Groups items into bursts, yielding the burst so far once the stream has
+    // come up empty on `idle` polls in a row, and the last one when it ends.
+    fn collect_bursts(self, idle: usize) -> CollectBursts<Self>
+    where
+        Self: Sized,
+    {
+        assert!(idle > 0, "idle threshold must be non-zero");
+        CollectBursts {
+            stream: Some(self),
+            batch: Vec::new(),
+            idle,
+            spins: 0,
+        }
+    }
+
+    // Yields the values `f` maps items to, ending the stream at the first
+    // item it maps to `None`.
+    fn map_while<F, U>(self, f: F) -> MapWhile<Self, F>
+    where
+        F: FnMut(Self::Item) -> Option<U>,
+        Self: Sized,
+    {
+        MapWhile { stream: self, f }
+    }
+
+    // Merges adjacent items with `f`, which either returns the merged item
+    // or hands both back to have the first one yielded.
+    fn coalesce<F>(self, f: F) -> Coalesce<Self, F>
+    where
+        F: FnMut(Self::Item, Self::Item) -> Result<Self::Item, (Self::Item, Self::Item)>,
+        Self: Sized,
+    {
+        Coalesce {
+            stream: Some(self),
+            held: None,
+            f,
+        }
+    }
+
+    // For `(index, value)` items arriving in increasing index order, yields
+    // every index from the first to the last seen, with `default` standing
+    // in for any missing value. An item whose index has already been passed
+    // is yielded as it is.
+    fn fill_gaps<V>(self, default: V) -> FillGaps<Self, V>
+    where
+        V: Clone,
+        Self: Stream<Item = (usize, V)> + Sized,
+    {
+        FillGaps {
+            stream: self,
+            default,
+            next: None,
+            pending: None,
+        }
+    }
+
+    // The inverse of `Future::materialize`: `OnNext` items are yielded and an
+    // `OnError` fails the stream. The stream's own errors are converted into
+    // the notified error type.
+    fn dematerialize<T, E>(self) -> Dematerialize<Self, T, E>
+    where
+        Self: Stream<Item = Notification<T, E>> + Sized,
+        E: From<Self::Error>,
+    {
+        Dematerialize {
+            stream: self,
+            _marker: marker::PhantomData,
+        }
+    }
+
+    // Passes items through, failing with `OrderViolation` in place of the
+    // first item which is less than the one before it.
+    fn assert_increasing(self) -> AssertIncreasing<Self>
+    where
+        Self::Item: PartialOrd + Clone,
+        Self::Error: From<OrderViolation>,
+        Self: Sized,
+    {
+        AssertIncreasing {
+            stream: self,
+            last: None,
+        }
+    }
+
+    // Fails with `TimeoutError` if the stream comes up empty on more than
+    // `max` polls in a row. Every item restarts the count.
+    fn idle_timeout_spins(self, max: usize) -> IdleTimeout<Self>
+    where
+        Self::Error: From<TimeoutError>,
+        Self: Sized,
+    {
+        IdleTimeout {
+            stream: self,
+            idle: 0,
+            max,
+        }
+    }
+
+    // Flattens incoming vectors and regroups their elements into chunks of
+    // `size`, the last of which may be shorter.
+    fn rechunk<T>(self, size: usize) -> Rechunk<Self, T>
+    where
+        Self: Stream<Item = Vec<T>> + Sized,
+    {
+        assert!(size > 0, "chunk size must be non-zero");
+        Rechunk {
+            stream: Some(self),
+            buf: VecDeque::new(),
+            size,
+        }
+    }
+
+    // Passes items through, appending a copy of each to the shared `cell` so
+    // observers can see everything yielded so far. As with `latest_into`, a
+    // poll while the cell is borrowed comes up empty.
+    fn tee_into(self, cell: Rc<AtomicCell<Vec<Self::Item>>>) -> TeeInto<Self>
+    where
+        Self::Item: Clone,
+        Self: Sized,
+    {
+        TeeInto { stream: self, cell }
+    }
+
+    // Comes up empty without touching the source whenever `gauge` reports
+    // that downstream is congested, resuming once it clears.
+    fn backpressure<G>(self, gauge: G) -> Backpressure<Self, G>
+    where
+        G: FnMut() -> bool,
+        Self: Sized,
+    {
+        Backpressure {
+            stream: self,
+            gauge,
+        }
+    }
+
+    // Splits a stream of pairs into a stream of the first halves and one of
+    // the second halves. Whichever side pulls a pair queues the other half
+    // for its sibling. An error goes to the side which pulled it, after
+    // which the sibling ends once its queue is drained.
+    fn unzip<A, B>(self) -> (UnzipLeft<Self, A, B>, UnzipRight<Self, A, B>)
+    where
+        Self: Stream<Item = (A, B)> + Sized,
+    {
+        let shared = Rc::new(RefCell::new(Unzip {
+            stream: Some(self),
+            left: VecDeque::new(),
+            right: VecDeque::new(),
+        }));
+        let right = UnzipRight {
+            shared: shared.clone(),
+        };
+        (UnzipLeft { shared }, right)
+    }
+
+    // Yields the accumulator after folding in each item with `f`, starting
+    // from `init`, so every item produces exactly one output.
+    fn prefix_scan<F, U>(self, init: U, f: F) -> PrefixScan<Self, F, U>
+    where
+        U: Clone,
+        F: FnMut(&U, Self::Item) -> U,
+        Self: Sized,
+    {
+        PrefixScan {
+            stream: self,
+            f,
+            acc: init,
+        }
+    }
+
+    // Yields the first `n` distinct items, skipping repeats, and ends as
+    // soon as the last of them has been yielded.
+    fn take_distinct(self, n: usize) -> TakeDistinct<Self>
+    where
+        Self::Item: Eq + Hash + Clone,
+        Self: Sized,
+    {
+        TakeDistinct {
+            stream: self,
+            seen: HashSet::new(),
+            remaining: n,
+        }
+    }
+
+    // Yields `f` applied to each non-overlapping window of `size` items, and
+    // to a final shorter window if the stream ends part way through one.
+    fn tumbling_reduce<F, U>(self, size: usize, f: F) -> TumblingReduce<Self, F>
+    where
+        F: FnMut(&[Self::Item]) -> U,
+        Self: Sized,
+    {
+        assert!(size > 0, "window size must be non-zero");
+        TumblingReduce {
+            stream: Some(self),
+            f,
+            size,
+            window: Vec::with_capacity(size),
+        }
+    }
+
+    // Comes up empty without touching the source until `gate` resolves, then
+    // behaves just like the source. A failed gate fails the stream.
+    fn gated<Fut>(self, gate: Fut) -> Gated<Self, Fut>
+    where
+        Fut: Future<Item = (), Error = Self::Error>,
+        Self: Sized,
+    {
+        Gated {
+            state: _Gated::Waiting(gate, self),
+        }
+    }
+
+    // Passes items through, sending `f(&item)` on `tx` for each one, e.g. to
+    // feed metrics elsewhere. Nobody listening on `tx` is no reason to stop.
+    fn report_to<F, M>(self, tx: Sender<M>, f: F) -> ReportTo<Self, F, M>
+    where
+        F: FnMut(&Self::Item) -> M,
+        Self: Sized,
+    {
+        ReportTo {
+            stream: self,
+            tx,
+            f,
+        }
+    }
+
+    // Pulls every item but only yields the last of each group of `n`; a
+    // trailing partial group is dropped.
+    fn sample_every(self, n: usize) -> SampleEvery<Self>
+    where
+        Self: Sized,
+    {
+        assert!(n > 0, "sample interval must be non-zero");
+        SampleEvery {
+            stream: self,
+            n,
+            count: 0,
+        }
+    }
+
+    // An iterator which blocks on each `next()` until the stream produces an
+    // item, yielding the thread whenever nothing is ready. A terminal error
+    // is yielded as the final `Err` before the iterator ends.
+    fn into_blocking_iter(self) -> StreamIter<Self>
+    where
+        Self: Sized,
+    {
+        StreamIter { stream: Some(self) }
+    }
+
+    // Buffers items and lets `f` decide when to carve a batch out of the
+    // buffer: it's called after every new item, and a `Some` is yielded.
+    // Once the stream ends `f` is called until it declines, and whatever is
+    // left in the buffer is dropped.
+    fn batching<F, U>(self, f: F) -> Batching<Self, F>
+    where
+        F: FnMut(&mut VecDeque<Self::Item>) -> Option<U>,
+        Self: Sized,
+    {
+        Batching {
+            stream: Some(self),
+            buf: VecDeque::new(),
+            f,
+        }
+    }
+
+    // Yields the first item of every window of `window` polls, dropping any
+    // further items pulled before the window elapses. At most one item is
+    // pulled per poll, so each poll advances the window by one.
+    fn throttle_first(self, window: usize) -> ThrottleFirst<Self>
+    where
+        Self: Sized,
+    {
+        ThrottleFirst {
+            stream: self,
+            window,
+            cooldown: 0,
+        }
+    }
+
+    // After each item, waits out `f(&item)` pending polls before pulling the
+    // next one. Unlike `throttle_first`, no items are dropped.
+    fn throttle_by<F>(self, f: F) -> ThrottleBy<Self, F>
+    where
+        F: FnMut(&Self::Item) -> usize,
+        Self: Sized,
+    {
+        ThrottleBy {
+            stream: self,
+            f,
+            cooldown: 0,
+        }
+    }
+
+    // Like `take_while`, but the first item matching `p` is still yielded
+    // and ends the stream.
+    fn terminate_on<P>(self, p: P) -> TerminateOn<Self, P>
+    where
+        P: FnMut(&Self::Item) -> bool,
+        Self: Sized,
+    {
+        TerminateOn {
+            stream: Some(self),
+            p,
+        }
+    }
+
+    fn enumerate_from(self, start: usize) -> EnumerateFrom<Self>
+    where
+        Self: Sized,
+    {
+        EnumerateFrom {
+            stream: self,
+            next: start,
+        }
+    }
+
+    // Collects items until the stream ends or fails, resolving to everything
+    // collected along with the error, if there was one. The returned future
+    // itself never fails.
+    fn collect_until_error(self) -> CollectUntilError<Self>
+    where
+        Self: Sized,
+    {
+        CollectUntilError {
+            stream: self,
+            items: Vec::new(),
+        }
+    }
+
+    // Collects every item into a `Vec` allocated up front with room for
+    // `cap` of them, failing with the stream's error.
+    fn collect_with_capacity(self, cap: usize) -> CollectWithCapacity<Self>
+    where
+        Self: Sized,
+    {
+        CollectWithCapacity {
+            stream: self,
+            items: Vec::with_capacity(cap),
+        }
+    }
+
+    // Collects every item and resolves to them sorted. Nothing can be
+    // yielded before the stream ends, so this is a future rather than a
+    // stream adapter.
+    fn sorted(self) -> Sorted<Self>
+    where
+        Self::Item: Ord,
+        Self: Sized,
+    {
+        Sorted {
+            inner: self.collect_with_capacity(0),
+        }
+    }
+
+    // Like `sorted`, but ordered by `cmp`.
+    fn sorted_by<F>(self, cmp: F) -> SortedBy<Self, F>
+    where
+        F: FnMut(&Self::Item, &Self::Item) -> Ordering,
+        Self: Sized,
+    {
+        SortedBy {
+            inner: self.collect_with_capacity(0),
+            cmp,
+        }
+    }
+
+    // Collects `(key, value)` items into a map from each key to all of its
+    // values, in the order they arrived.
+    fn collect_grouped<K, V>(self) -> CollectGrouped<Self, K, V>
+    where
+        K: Eq + Hash,
+        Self: Stream<Item = (K, V)> + Sized,
+    {
+        CollectGrouped {
+            stream: self,
+            groups: HashMap::new(),
+        }
+    }
+
+    // Counts how many times each distinct item appears.
+    fn histogram(self) -> Histogram<Self>
+    where
+        Self::Item: Eq + Hash,
+        Self: Sized,
+    {
+        Histogram {
+            stream: self,
+            counts: HashMap::new(),
+        }
+    }
+
+    // Resolves to the final `n` items, or all of them if there are fewer.
+    fn last_n(self, n: usize) -> LastN<Self>
+    where
+        Self::Item: Clone,
+        Self: Sized,
+    {
+        LastN {
+            stream: self,
+            n,
+            buf: VecDeque::with_capacity(n),
+        }
+    }
+
+    // Splits the stream into its successful prefix and the error which ended
+    // it, if any. A stream is consumed by its error, so nothing is left to
+    // hand back as the tail beyond `()`.
+    fn split_on_error(self) -> SplitOnError<Self>
+    where
+        Self: Sized,
+    {
+        SplitOnError {
+            inner: self.collect_until_error(),
+        }
+    }
+}
+
+pub struct ForEach<S, F> {
+    stream: S,
+    f: F,
+}
+
+impl<S, F> Future for ForEach<S, F>
+where
+    S: Stream,
+    F: FnMut(S::Item),
+{
+    type Item = ();
+    type Error = S::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let ForEach { mut stream, mut f } = self;
+        loop {
+            match stream.poll() {
+                Ok(Ok(Some((item, s)))) => {
+                    f(item);
+                    stream = s;
+                }
+                Ok(Ok(None)) => return Ok(Ok(())),
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(stream) => return Err(ForEach { stream, f }),
+            }
+        }
+    }
+}
+
+pub struct ForEachConcurrent<S, F, B> {
+    stream: Option<S>,
+    f: F,
+    n: usize,
+    running: Vec<B>,
+}
+
+impl<S, F, B> Future for ForEachConcurrent<S, F, B>
+where
+    S: Stream,
+    F: FnMut(S::Item) -> B,
+    B: Future<Item = (), Error = S::Error>,
+{
+    type Item = ();
+    type Error = S::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let ForEachConcurrent {
+            mut stream,
+            mut f,
+            n,
+            mut running,
+        } = self;
+        loop {
+            while running.len() < n {
+                let s = match stream.take() {
+                    Some(s) => s,
+                    None => break,
+                };
+                match s.poll() {
+                    Ok(Ok(Some((item, s)))) => {
+                        running.push(f(item));
+                        stream = Some(s);
+                    }
+                    Ok(Ok(None)) => break,
+                    Ok(Err(e)) => return Ok(Err(e)),
+                    Err(s) => {
+                        stream = Some(s);
+                        break;
+                    }
+                }
+            }
+            let before = running.len();
+            let mut still = Vec::with_capacity(before);
+            for effect in running {
+                match effect.poll() {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => return Ok(Err(e)),
+                    Err(effect) => still.push(effect),
+                }
+            }
+            running = still;
+            if stream.is_none() && running.is_empty() {
+                return Ok(Ok(()));
+            }
+            // Keep going while finished effects have made room for more.
+            if running.len() == before || stream.is_none() {
+                return Err(ForEachConcurrent {
+                    stream,
+                    f,
+                    n,
+                    running,
+                });
+            }
+        }
+    }
+}
+
+pub struct Map<S, F> {
+    stream: S,
+    f: F,
+}
+
+impl<S, F, U> Stream for Map<S, F>
+where
+    S: Stream,
+    F: FnMut(S::Item) -> U,
+{
+    type Item = U;
+    type Error = S::Error;
+
+    fn poll(self) -> StreamPoll<Self> {
+        let Map { stream, mut f } = self;
+        match stream.poll() {
+            Ok(Ok(Some((item, stream)))) => Ok(Ok(Some((f(item), Map { stream, f })))),
+            Ok(Ok(None)) => Ok(Ok(None)),
+            Ok(Err(e)) => Ok(Err(e)),
+            Err(stream) => Err(Map { stream, f }),
+        }
+    }
+}
+
+pub struct MapErr<S, F> {
+    stream: S,
+    f: F,
+}
+
+impl<S, F, E> Stream for MapErr<S, F>
+where
+    S: Stream,
+    F: FnOnce(S::Error) -> E,
+{
+    type Item = S::Item;
+    type Error = E;
+
+    fn poll(self) -> StreamPoll<Self> {
+        let MapErr { stream, f } = self;
+        match stream.poll() {
+            Ok(Ok(Some((item, stream)))) => Ok(Ok(Some((item, MapErr { stream, f })))),
+            Ok(Ok(None)) => Ok(Ok(None)),
+            Ok(Err(e)) => Ok(Err(f(e))),
+            Err(stream) => Err(MapErr { stream, f }),
+        }
+    }
+}
+
+pub struct Filter<S, F> {
+    stream: S,
+    f: F,
+}
+
+impl<S, F> Stream for Filter<S, F>
+where
+    S: Stream,
+    F: FnMut(&S::Item) -> bool,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(self) -> StreamPoll<Self> {
+        let Filter { mut stream, mut f } = self;
+        loop {
+            match stream.poll() {
+                Ok(Ok(Some((item, s)))) => {
+                    if f(&item) {
+                        return Ok(Ok(Some((item, Filter { stream: s, f }))));
+                    }
+                    stream = s;
+                }
+                Ok(Ok(None)) => return Ok(Ok(None)),
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(stream) => return Err(Filter { stream, f }),
+            }
+        }
+    }
+}
+
+pub struct AndThen<S, F, B>
+where
+    B: IntoFuture,
+{
+    stream: S,
+    f: F,
+    current: Option<B::Future>,
+}
+
+impl<S, F, B> Stream for AndThen<S, F, B>
+where
+    S: Stream,
+    F: FnMut(S::Item) -> B,
+    B: IntoFuture<Error = S::Error>,
+{
+    type Item = B::Item;
+    type Error = S::Error;
+
+    fn poll(mut self) -> StreamPoll<Self> {
+        let current = match self.current.take() {
+            Some(current) => current,
+            None => match self.stream.poll() {
+                Ok(Ok(Some((item, s)))) => {
+                    self.stream = s;
+                    (self.f)(item).into_future()
+                }
+                Ok(Ok(None)) => return Ok(Ok(None)),
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(s) => {
+                    self.stream = s;
+                    return Err(self);
+                }
+            },
+        };
+        match current.poll() {
+            Ok(Ok(item)) => Ok(Ok(Some((item, self)))),
+            Ok(Err(e)) => Ok(Err(e)),
+            Err(current) => {
+                self.current = Some(current);
+                Err(self)
+            }
+        }
+    }
+}
+
+pub struct Fold<S, F, T, B>
+where
+    B: IntoFuture,
+{
+    stream: S,
+    f: F,
+    state: _Fold<T, B::Future>,
+}
+
+enum _Fold<T, B> {
+    Ready(T),
+    Running(B),
+}
+
+impl<S, F, T, B> Future for Fold<S, F, T, B>
+where
+    S: Stream,
+    F: FnMut(T, S::Item) -> B,
+    B: IntoFuture<Item = T, Error = S::Error>,
+{
+    type Item = T;
+    type Error = S::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let Fold {
+            mut stream,
+            mut f,
+            mut state,
+        } = self;
+        loop {
+            let acc = match state {
+                _Fold::Ready(acc) => acc,
+                _Fold::Running(b) => match b.poll() {
+                    Ok(Ok(acc)) => acc,
+                    Ok(Err(e)) => return Ok(Err(e)),
+                    Err(b) => {
+                        let state = _Fold::Running(b);
+                        return Err(Fold { stream, f, state });
+                    }
+                },
+            };
+            match stream.poll() {
+                Ok(Ok(Some((item, s)))) => {
+                    stream = s;
+                    state = _Fold::Running(f(acc, item).into_future());
+                }
+                Ok(Ok(None)) => return Ok(Ok(acc)),
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(stream) => {
+                    let state = _Fold::Ready(acc);
+                    return Err(Fold { stream, f, state });
+                }
+            }
+        }
+    }
+}
+
+pub struct Collect<S>
+where
+    S: Stream,
+{
+    inner: CollectWithCapacity<S>,
+}
+
+impl<S> Future for Collect<S>
+where
+    S: Stream,
+{
+    type Item = Vec<S::Item>;
+    type Error = S::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        self.inner.poll().map_err(|inner| Collect { inner })
+    }
+}
+
+// Every message sent on the channel, ending once all senders have hung up.
+// A `Receiver` is also a `Future` for its first message, so with both
+// traits in scope a method they share, like `map`, needs spelling out.
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+    type Error = Never;
+
+    fn poll(self) -> StreamPoll<Self> {
+        match self.try_recv() {
+            Ok(msg) => Ok(Ok(Some((msg, self)))),
+            Err(TryRecvError::Empty) => Err(self),
+            Err(TryRecvError::Disconnected) => Ok(Ok(None)),
+        }
+    }
+}
+
+// The same stream as a type of its own, which is only a `Stream`, for use
+// where a `Receiver`'s shared method names would be ambiguous.
+pub struct ReceiverStream<T> {
+    rx: Receiver<T>,
+}
+
+pub fn receiver<T>(rx: Receiver<T>) -> ReceiverStream<T> {
+    ReceiverStream { rx }
+}
+
+impl<T> ReceiverStream<T> {
+    pub fn into_inner(self) -> Receiver<T> {
+        self.rx
+    }
+}
+
+impl<T> Stream for ReceiverStream<T> {
+    type Item = T;
+    type Error = Never;
+
+    fn poll(self) -> StreamPoll<Self> {
+        match Stream::poll(self.rx) {
+            Ok(Ok(Some((msg, rx)))) => Ok(Ok(Some((msg, ReceiverStream { rx })))),
+            Ok(Ok(None)) => Ok(Ok(None)),
+            Ok(Err(never)) => match never {},
+            Err(rx) => Err(ReceiverStream { rx }),
+        }
+    }
+}
+
+pub struct WithLatestFrom<A, S>
+where
+    S: Stream,
+{
+    stream: A,
+    // `None` once `other` has ended; the last value it produced is kept.
+    other: Option<S>,
+    latest: Option<S::Item>,
+}
+
+impl<A, S> Stream for WithLatestFrom<A, S>
+where
+    A: Stream,
+    S: Stream<Error = A::Error>,
+    S::Item: Clone,
+{
+    type Item = (A::Item, Option<S::Item>);
+    type Error = A::Error;
+
+    fn poll(self) -> StreamPoll<Self> {
+        let WithLatestFrom {
+            stream,
+            mut other,
+            mut latest,
+        } = self;
+        // Pull everything `other` has ready so `latest` is as fresh as possible.
+        while let Some(s) = other.take() {
+            match s.poll() {
+                Ok(Ok(Some((item, s)))) => {
+                    latest = Some(item);
+                    other = Some(s);
+                }
+                Ok(Ok(None)) => {}
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(s) => {
+                    other = Some(s);
+                    break;
+                }
+            }
+        }
+        match stream.poll() {
+            Ok(Ok(Some((item, stream)))) => {
+                let pair = (item, latest.clone());
+                Ok(Ok(Some((
+                    pair,
+                    WithLatestFrom {
+                        stream,
+                        other,
+                        latest,
+                    },
+                ))))
+            }
+            Ok(Ok(None)) => Ok(Ok(None)),
+            Ok(Err(e)) => Ok(Err(e)),
+            Err(stream) => Err(WithLatestFrom {
+                stream,
+                other,
+                latest,
+            }),
+        }
+    }
+}
+
+pub struct Iter<I, E> {
+    iter: I,
+    _marker: marker::PhantomData<E>,
+}
+
+// Turns an iterator into a stream which is always ready and never fails.
+pub fn iter<I, E>(i: I) -> Iter<I::IntoIter, E>
+where
+    I: IntoIterator,
+{
+    Iter {
+        iter: i.into_iter(),
+        _marker: marker::PhantomData,
+    }
+}
+
+impl<I, E> Stream for Iter<I, E>
+where
+    I: Iterator,
+{
+    type Item = I::Item;
+    type Error = E;
+
+    fn poll(mut self) -> StreamPoll<Self> {
+        match self.iter.next() {
+            Some(item) => Ok(Ok(Some((item, self)))),
+            None => Ok(Ok(None)),
+        }
+    }
+}
+
+pub struct ThenStream<A, S, F> {
+    state: _ThenStream<A, S, F>,
+}
+
+enum _ThenStream<A, S, F> {
+    First(A, F),
+    Second(S),
+}
+
+impl<A, S, F> ThenStream<A, S, F> {
+    pub(crate) fn new(future: A, f: F) -> ThenStream<A, S, F> {
+        ThenStream {
+            state: _ThenStream::First(future, f),
+        }
+    }
+}
+
+impl<A, S, F> Stream for ThenStream<A, S, F>
+where
+    A: Future,
+    S: Stream,
+    F: FnOnce(Result<A::Item, A::Error>) -> S,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(self) -> StreamPoll<Self> {
+        let stream = match self.state {
+            _ThenStream::First(a, f) => match a.poll() {
+                Ok(result) => f(result),
+                Err(a) => {
+                    return Err(ThenStream {
+                        state: _ThenStream::First(a, f),
+                    })
+                }
+            },
+            _ThenStream::Second(s) => s,
+        };
+        match stream.poll() {
+            Ok(Ok(Some((item, s)))) => Ok(Ok(Some((
+                item,
+                ThenStream {
+                    state: _ThenStream::Second(s),
+                },
+            )))),
+            Ok(Ok(None)) => Ok(Ok(None)),
+            Ok(Err(e)) => Ok(Err(e)),
+            Err(s) => Err(ThenStream {
+                state: _ThenStream::Second(s),
+            }),
+        }
+    }
+}
+
+pub struct RetryEach<S> {
+    stream: S,
+    max: usize,
+    retries: usize,
+}
+
+impl<S, T, E> Stream for RetryEach<S>
+where
+    S: Stream<Item = Result<T, E>, Error = E>,
+{
+    type Item = T;
+    type Error = E;
+
+    fn poll(self) -> StreamPoll<Self> {
+        let RetryEach {
+            mut stream,
+            max,
+            mut retries,
+        } = self;
+        loop {
+            match stream.poll() {
+                Ok(Ok(Some((Ok(item), stream)))) => {
+                    return Ok(Ok(Some((
+                        item,
+                        RetryEach {
+                            stream,
+                            max,
+                            retries: 0,
+                        },
+                    ))))
+                }
+                Ok(Ok(Some((Err(e), s)))) => {
+                    if retries == max {
+                        return Ok(Err(e));
+                    }
+                    retries += 1;
+                    stream = s;
+                }
+                Ok(Ok(None)) => return Ok(Ok(None)),
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(stream) => {
+                    return Err(RetryEach {
+                        stream,
+                        max,
+                        retries,
+                    })
+                }
+            }
+        }
+    }
+}
+
+pub struct Pairwise<S>
+where
+    S: Stream,
+{
+    stream: S,
+    prev: Option<S::Item>,
+}
+
+impl<S> Stream for Pairwise<S>
+where
+    S: Stream,
+    S::Item: Clone,
+{
+    type Item = (S::Item, S::Item);
+    type Error = S::Error;
+
+    fn poll(self) -> StreamPoll<Self> {
+        let Pairwise {
+            mut stream,
+            mut prev,
+        } = self;
+        loop {
+            match stream.poll() {
+                Ok(Ok(Some((item, s)))) => match prev.replace(item.clone()) {
+                    Some(p) => return Ok(Ok(Some(((p, item), Pairwise { stream: s, prev })))),
+                    None => stream = s,
+                },
+                Ok(Ok(None)) => return Ok(Ok(None)),
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(stream) => return Err(Pairwise { stream, prev }),
+            }
+        }
+    }
+}
+
+pub struct ChunksOrSpins<S>
+where
+    S: Stream,
+{
+    // `None` once the underlying stream has ended.
+    stream: Option<S>,
+    buf: Vec<S::Item>,
+    size: usize,
+    max_spins: usize,
+    spins: usize,
+}
+
+impl<S> ChunksOrSpins<S>
+where
+    S: Stream,
+{
+    fn flush(mut self) -> StreamPoll<Self> {
+        let batch = std::mem::replace(&mut self.buf, Vec::with_capacity(self.size));
+        self.spins = 0;
+        Ok(Ok(Some((batch, self))))
+    }
+}
+
+impl<S> Stream for ChunksOrSpins<S>
+where
+    S: Stream,
+{
+    type Item = Vec<S::Item>;
+    type Error = S::Error;
+
+    fn poll(mut self) -> StreamPoll<Self> {
+        while let Some(stream) = self.stream.take() {
+            match stream.poll() {
+                Ok(Ok(Some((item, s)))) => {
+                    self.stream = Some(s);
+                    self.buf.push(item);
+                    if self.buf.len() == self.size {
+                        return self.flush();
+                    }
+                }
+                Ok(Ok(None)) => {}
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(s) => {
+                    self.stream = Some(s);
+                    if self.buf.is_empty() {
+                        return Err(self);
+                    }
+                    self.spins += 1;
+                    if self.spins >= self.max_spins {
+                        return self.flush();
+                    }
+                    return Err(self);
+                }
+            }
+        }
+        if self.buf.is_empty() {
+            Ok(Ok(None))
+        } else {
+            self.flush()
+        }
+    }
+}
+
+pub struct FlattenOkVecs<S, T> {
+    stream: S,
+    current: std::vec::IntoIter<T>,
+}
+
+impl<S, T, E> Stream for FlattenOkVecs<S, T>
+where
+    S: Stream<Item = Result<Vec<T>, E>>,
+    S::Error: From<E>,
+{
+    type Item = T;
+    type Error = S::Error;
+
+    fn poll(self) -> StreamPoll<Self> {
+        let FlattenOkVecs {
+            mut stream,
+            mut current,
+        } = self;
+        loop {
+            if let Some(item) = current.next() {
+                return Ok(Ok(Some((item, FlattenOkVecs { stream, current }))));
+            }
+            match stream.poll() {
+                Ok(Ok(Some((Ok(vec), s)))) => {
+                    stream = s;
+                    current = vec.into_iter();
+                }
+                Ok(Ok(Some((Err(e), _)))) => return Ok(Err(From::from(e))),
+                Ok(Ok(None)) => return Ok(Ok(None)),
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(stream) => return Err(FlattenOkVecs { stream, current }),
+            }
+        }
+    }
+}
+
+pub struct LatestInto<S>
+where
+    S: Stream,
+{
+    stream: S,
+    cell: Rc<AtomicCell<Option<S::Item>>>,
+}
+
+impl<S> Stream for LatestInto<S>
+where
+    S: Stream,
+    S::Item: Clone,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(self) -> StreamPoll<Self> {
+        let LatestInto { stream, cell } = self;
+        // Don't pull an item unless it can be recorded; an observer holding
+        // the cell just makes this poll come up empty.
+        let polled = match cell.try_borrow() {
+            Some(mut latest) => {
+                let polled = stream.poll();
+                if let Ok(Ok(Some((ref item, _)))) = polled {
+                    latest.replace(item.clone());
+                }
+                polled
+            }
+            None => Err(stream),
+        };
+        match polled {
+            Ok(Ok(Some((item, stream)))) => Ok(Ok(Some((item, LatestInto { stream, cell })))),
+            Ok(Ok(None)) => Ok(Ok(None)),
+            Ok(Err(e)) => Ok(Err(e)),
+            Err(stream) => Err(LatestInto { stream, cell }),
+        }
+    }
+}
+
+enum _BufferedSlot<A: Future> {
+    Pending(A),
+    Done(Result<A::Item, A::Error>),
+}
+
+impl<A: Future> _BufferedSlot<A> {
+    fn poll(self) -> _BufferedSlot<A> {
+        match self {
+            _BufferedSlot::Pending(a) => match a.poll() {
+                Ok(result) => _BufferedSlot::Done(result),
+                Err(a) => _BufferedSlot::Pending(a),
+            },
+            done => done,
+        }
+    }
+}
+
+pub struct BufferedResults<S>
+where
+    S: Stream,
+    S::Item: IntoFuture,
+{
+    // `None` once the underlying stream has ended.
+    stream: Option<S>,
+    queue: VecDeque<_BufferedSlot<<S::Item as IntoFuture>::Future>>,
+    n: usize,
+}
+
+impl<S> Stream for BufferedResults<S>
+where
+    S: Stream,
+    S::Item: IntoFuture,
+{
+    type Item = Result<<S::Item as IntoFuture>::Item, <S::Item as IntoFuture>::Error>;
+    type Error = S::Error;
+
+    fn poll(mut self) -> StreamPoll<Self> {
+        while self.queue.len() < self.n {
+            let stream = match self.stream.take() {
+                Some(stream) => stream,
+                None => break,
+            };
+            match stream.poll() {
+                Ok(Ok(Some((item, s)))) => {
+                    self.stream = Some(s);
+                    self.queue
+                        .push_back(_BufferedSlot::Pending(item.into_future()));
+                }
+                Ok(Ok(None)) => {}
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(s) => {
+                    self.stream = Some(s);
+                    break;
+                }
+            }
+        }
+        self.queue = self.queue.into_iter().map(_BufferedSlot::poll).collect();
+        match self.queue.pop_front() {
+            Some(_BufferedSlot::Done(result)) => Ok(Ok(Some((result, self)))),
+            Some(pending) => {
+                self.queue.push_front(pending);
+                Err(self)
+            }
+            None if self.stream.is_none() => Ok(Ok(None)),
+            None => Err(self),
+        }
+    }
+}
+
+pub struct SampleEvery<S> {
+    stream: S,
+    n: usize,
+    count: usize,
+}
+
+impl<S> Stream for SampleEvery<S>
+where
+    S: Stream,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(mut self) -> StreamPoll<Self> {
+        loop {
+            match self.stream.poll() {
+                Ok(Ok(Some((item, s)))) => {
+                    self.stream = s;
+                    self.count += 1;
+                    if self.count == self.n {
+                        self.count = 0;
+                        return Ok(Ok(Some((item, self))));
+                    }
+                }
+                Ok(Ok(None)) => return Ok(Ok(None)),
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(s) => {
+                    self.stream = s;
+                    return Err(self);
+                }
+            }
+        }
+    }
+}
+
+pub struct StreamIter<S> {
+    stream: Option<S>,
+}
+
+impl<S> Iterator for StreamIter<S>
+where
+    S: Stream,
+{
+    type Item = Result<S::Item, S::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut stream = self.stream.take()?;
+        // Like `Future::wait`, parking between polls which come up empty.
+        let task = Task::new();
+        let mut backoff = Backoff::new();
+        loop {
+            match task.enter(|| stream.poll()) {
+                Ok(Ok(Some((item, s)))) => {
+                    self.stream = Some(s);
+                    return Some(Ok(item));
+                }
+                Ok(Ok(None)) => return None,
+                Ok(Err(e)) => return Some(Err(e)),
+                Err(s) => stream = s,
+            }
+            backoff.park(&task);
+        }
+    }
+}
+
+pub struct Batching<S, F>
+where
+    S: Stream,
+{
+    // `None` once the underlying stream has ended.
+    stream: Option<S>,
+    buf: VecDeque<S::Item>,
+    f: F,
+}
+
+impl<S, F, U> Stream for Batching<S, F>
+where
+    S: Stream,
+    F: FnMut(&mut VecDeque<S::Item>) -> Option<U>,
+{
+    type Item = U;
+    type Error = S::Error;
+
+    fn poll(mut self) -> StreamPoll<Self> {
+        while let Some(stream) = self.stream.take() {
+            match stream.poll() {
+                Ok(Ok(Some((item, s)))) => {
+                    self.stream = Some(s);
+                    self.buf.push_back(item);
+                    if let Some(batch) = (self.f)(&mut self.buf) {
+                        return Ok(Ok(Some((batch, self))));
+                    }
+                }
+                Ok(Ok(None)) => {}
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(s) => {
+                    self.stream = Some(s);
+                    return Err(self);
+                }
+            }
+        }
+        match (self.f)(&mut self.buf) {
+            Some(batch) => Ok(Ok(Some((batch, self)))),
+            None => Ok(Ok(None)),
+        }
+    }
+}
+
+pub struct ThrottleFirst<S> {
+    stream: S,
+    window: usize,
+    // Polls left in the current window; zero means the next item leads.
+    cooldown: usize,
+}
+
+impl<S> Stream for ThrottleFirst<S>
+where
+    S: Stream,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(self) -> StreamPoll<Self> {
+        let ThrottleFirst {
+            stream,
+            window,
+            cooldown,
+        } = self;
+        match stream.poll() {
+            Ok(Ok(Some((item, stream)))) if cooldown == 0 => Ok(Ok(Some((
+                item,
+                ThrottleFirst {
+                    stream,
+                    window,
+                    cooldown: window.saturating_sub(1),
+                },
+            )))),
+            Ok(Ok(Some((_, stream)))) | Err(stream) => Err(ThrottleFirst {
+                stream,
+                window,
+                cooldown: cooldown.saturating_sub(1),
+            }),
+            Ok(Ok(None)) => Ok(Ok(None)),
+            Ok(Err(e)) => Ok(Err(e)),
+        }
+    }
+}
+
+pub struct TerminateOn<S, P> {
+    // `None` once the boundary item has been yielded.
+    stream: Option<S>,
+    p: P,
+}
+
+impl<S, P> Stream for TerminateOn<S, P>
+where
+    S: Stream,
+    P: FnMut(&S::Item) -> bool,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(self) -> StreamPoll<Self> {
+        let TerminateOn { stream, mut p } = self;
+        let stream = match stream {
+            Some(stream) => stream,
+            None => return Ok(Ok(None)),
+        };
+        match stream.poll() {
+            Ok(Ok(Some((item, stream)))) => {
+                let stream = if p(&item) { None } else { Some(stream) };
+                Ok(Ok(Some((item, TerminateOn { stream, p }))))
+            }
+            Ok(Ok(None)) => Ok(Ok(None)),
+            Ok(Err(e)) => Ok(Err(e)),
+            Err(stream) => Err(TerminateOn {
+                stream: Some(stream),
+                p,
+            }),
+        }
+    }
+}
+
+pub struct EnumerateFrom<S> {
+    stream: S,
+    next: usize,
+}
+
+impl<S> Stream for EnumerateFrom<S>
+where
+    S: Stream,
+{
+    type Item = (usize, S::Item);
+    type Error = S::Error;
+
+    fn poll(self) -> StreamPoll<Self> {
+        let next = self.next;
+        match self.stream.poll() {
+            Ok(Ok(Some((item, stream)))) => Ok(Ok(Some((
+                (next, item),
+                EnumerateFrom {
+                    stream,
+                    next: next + 1,
+                },
+            )))),
+            Ok(Ok(None)) => Ok(Ok(None)),
+            Ok(Err(e)) => Ok(Err(e)),
+            Err(stream) => Err(EnumerateFrom { stream, next }),
+        }
+    }
+}
+
+pub struct RetryObserve<F, A>
+where
+    A: IntoFuture,
+{
+    factory: F,
+    current: Option<A::Future>,
+    // Attempts which may still be started, including the current one.
+    remaining: usize,
+    done: bool,
+}
+
+// Runs futures built by `factory` until one succeeds or `max` attempts have
+// been made, yielding every attempt's outcome as an item. The stream itself
+// never fails.
+pub fn retry_observe<F, A>(factory: F, max: usize) -> RetryObserve<F, A>
+where
+    F: FnMut() -> A,
+    A: IntoFuture,
+{
+    RetryObserve {
+        factory,
+        current: None,
+        remaining: max,
+        done: false,
+    }
+}
+
+impl<F, A> Stream for RetryObserve<F, A>
+where
+    F: FnMut() -> A,
+    A: IntoFuture,
+{
+    type Item = Result<A::Item, A::Error>;
+    type Error = A::Error;
+
+    fn poll(mut self) -> StreamPoll<Self> {
+        if self.done {
+            return Ok(Ok(None));
+        }
+        let attempt = match self.current.take() {
+            Some(attempt) => attempt,
+            None if self.remaining == 0 => return Ok(Ok(None)),
+            None => (self.factory)().into_future(),
+        };
+        match attempt.poll() {
+            Ok(result) => {
+                self.remaining -= 1;
+                self.done = result.is_ok() || self.remaining == 0;
+                Ok(Ok(Some((result, self))))
+            }
+            Err(attempt) => {
+                self.current = Some(attempt);
+                Err(self)
+            }
+        }
+    }
+}
+
+pub struct CollectUntilError<S>
+where
+    S: Stream,
+{
+    stream: S,
+    items: Vec<S::Item>,
+}
+
+impl<S> Future for CollectUntilError<S>
+where
+    S: Stream,
+{
+    type Item = (Vec<S::Item>, Option<S::Error>);
+    type Error = S::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let CollectUntilError {
+            mut stream,
+            mut items,
+        } = self;
+        loop {
+            match stream.poll() {
+                Ok(Ok(Some((item, s)))) => {
+                    items.push(item);
+                    stream = s;
+                }
+                Ok(Ok(None)) => return Ok(Ok((items, None))),
+                Ok(Err(e)) => return Ok(Ok((items, Some(e)))),
+                Err(stream) => return Err(CollectUntilError { stream, items }),
+            }
+        }
+    }
+}
+
+pub struct MapBuffered<S, B, F>
+where
+    B: IntoFuture,
+{
+    // `None` once the underlying stream has ended.
+    stream: Option<S>,
+    queue: VecDeque<_BufferedSlot<B::Future>>,
+    n: usize,
+    f: F,
+}
+
+impl<S, B, F> Stream for MapBuffered<S, B, F>
+where
+    S: Stream,
+    F: FnMut(S::Item) -> B,
+    B: IntoFuture<Error = S::Error>,
+{
+    type Item = B::Item;
+    type Error = S::Error;
+
+    fn poll(mut self) -> StreamPoll<Self> {
+        while self.queue.len() < self.n {
+            let stream = match self.stream.take() {
+                Some(stream) => stream,
+                None => break,
+            };
+            match stream.poll() {
+                Ok(Ok(Some((item, s)))) => {
+                    self.stream = Some(s);
+                    let future = (self.f)(item).into_future();
+                    self.queue.push_back(_BufferedSlot::Pending(future));
+                }
+                Ok(Ok(None)) => {}
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(s) => {
+                    self.stream = Some(s);
+                    break;
+                }
+            }
+        }
+        self.queue = self.queue.into_iter().map(_BufferedSlot::poll).collect();
+        match self.queue.pop_front() {
+            Some(_BufferedSlot::Done(Ok(item))) => Ok(Ok(Some((item, self)))),
+            Some(_BufferedSlot::Done(Err(e))) => Ok(Err(e)),
+            Some(pending) => {
+                self.queue.push_front(pending);
+                Err(self)
+            }
+            None if self.stream.is_none() => Ok(Ok(None)),
+            None => Err(self),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TraceEvent<T, E> {
+    Polled,
+    Ready(Result<T, E>),
+}
+
+pub struct PollTrace<A> {
+    // `None` once the future has resolved.
+    future: Option<A>,
+}
+
+impl<A> PollTrace<A> {
+    pub(crate) fn new(future: A) -> PollTrace<A> {
+        PollTrace {
+            future: Some(future),
+        }
+    }
+}
+
+impl<A> Stream for PollTrace<A>
+where
+    A: Future,
+{
+    type Item = TraceEvent<A::Item, A::Error>;
+    type Error = A::Error;
+
+    fn poll(self) -> StreamPoll<Self> {
+        let future = match self.future {
+            Some(future) => future,
+            None => return Ok(Ok(None)),
+        };
+        let (event, future) = match future.poll() {
+            Ok(result) => (TraceEvent::Ready(result), None),
+            Err(future) => (TraceEvent::Polled, Some(future)),
+        };
+        Ok(Ok(Some((event, PollTrace { future }))))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Notification<T, E> {
+    OnNext(T),
+    OnError(E),
+}
+
+pub struct Materialize<A> {
+    // `None` once the outcome has been yielded.
+    future: Option<A>,
+}
+
+impl<A> Materialize<A> {
+    pub(crate) fn new(future: A) -> Materialize<A> {
+        Materialize {
+            future: Some(future),
+        }
+    }
+}
+
+impl<A> Stream for Materialize<A>
+where
+    A: Future,
+{
+    type Item = Notification<A::Item, A::Error>;
+    type Error = Never;
+
+    fn poll(self) -> StreamPoll<Self> {
+        let future = match self.future {
+            Some(future) => future,
+            None => return Ok(Ok(None)),
+        };
+        let notification = match future.poll() {
+            Ok(Ok(item)) => Notification::OnNext(item),
+            Ok(Err(e)) => Notification::OnError(e),
+            Err(future) => {
+                return Err(Materialize {
+                    future: Some(future),
+                })
+            }
+        };
+        Ok(Ok(Some((notification, Materialize { future: None }))))
+    }
+}
+
+pub struct Dematerialize<S, T, E> {
+    stream: S,
+    _marker: marker::PhantomData<(T, E)>,
+}
+
+impl<S, T, E> Stream for Dematerialize<S, T, E>
+where
+    S: Stream<Item = Notification<T, E>>,
+    E: From<S::Error>,
+{
+    type Item = T;
+    type Error = E;
+
+    fn poll(self) -> StreamPoll<Self> {
+        match self.stream.poll() {
+            Ok(Ok(Some((Notification::OnNext(item), stream)))) => Ok(Ok(Some((
+                item,
+                Dematerialize {
+                    stream,
+                    _marker: marker::PhantomData,
+                },
+            )))),
+            Ok(Ok(Some((Notification::OnError(e), _)))) => Ok(Err(e)),
+            Ok(Ok(None)) => Ok(Ok(None)),
+            Ok(Err(e)) => Ok(Err(E::from(e))),
+            Err(stream) => Err(Dematerialize {
+                stream,
+                _marker: marker::PhantomData,
+            }),
+        }
+    }
+}
+
+pub struct SplitOnError<S>
+where
+    S: Stream,
+{
+    inner: CollectUntilError<S>,
+}
+
+impl<S> Future for SplitOnError<S>
+where
+    S: Stream,
+{
+    type Item = (Vec<S::Item>, Option<(S::Error, ())>);
+    type Error = S::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        match self.inner.poll() {
+            Ok(result) => Ok(result.map(|(prefix, e)| (prefix, e.map(|e| (e, ()))))),
+            Err(inner) => Err(SplitOnError { inner }),
+        }
+    }
+}
+
+struct Replay<T> {
+    // The most recent items, each tagged with its position in the source.
+    buffer: VecDeque<(u64, T)>,
+    capacity: usize,
+    next_seq: u64,
+    done: bool,
+}
+
+pub struct ReplayStream<S>
+where
+    S: Stream,
+{
+    stream: S,
+    shared: Rc<RefCell<Replay<S::Item>>>,
+}
+
+pub struct ReplaySubscriber<T> {
+    shared: Rc<RefCell<Replay<T>>>,
+    next: u64,
+}
+
+impl<S> ReplayStream<S>
+where
+    S: Stream,
+{
+    // A new subscriber starts with the items currently buffered.
+    pub fn subscribe(&self) -> ReplaySubscriber<S::Item> {
+        let next = match self.shared.borrow().buffer.front() {
+            Some(&(seq, _)) => seq,
+            None => self.shared.borrow().next_seq,
+        };
+        ReplaySubscriber {
+            shared: self.shared.clone(),
+            next,
+        }
+    }
+}
+
+impl<S> Stream for ReplayStream<S>
+where
+    S: Stream,
+    S::Item: Clone,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(self) -> StreamPoll<Self> {
+        let ReplayStream { stream, shared } = self;
+        match stream.poll() {
+            Ok(Ok(Some((item, stream)))) => {
+                {
+                    let mut replay = shared.borrow_mut();
+                    if replay.buffer.len() == replay.capacity {
+                        replay.buffer.pop_front();
+                    }
+                    let seq = replay.next_seq;
+                    replay.buffer.push_back((seq, item.clone()));
+                    replay.next_seq += 1;
+                }
+                Ok(Ok(Some((item, ReplayStream { stream, shared }))))
+            }
+            Ok(done) => {
+                shared.borrow_mut().done = true;
+                Ok(done.map(|_| None))
+            }
+            Err(stream) => Err(ReplayStream { stream, shared }),
+        }
+    }
+}
+
+impl<T> Stream for ReplaySubscriber<T>
+where
+    T: Clone,
+{
+    type Item = T;
+    type Error = ();
+
+    fn poll(mut self) -> StreamPoll<Self> {
+        let found = {
+            let replay = self.shared.borrow();
+            match replay.buffer.iter().find(|&&(seq, _)| seq >= self.next) {
+                Some((seq, item)) => Some((*seq, item.clone())),
+                None if replay.done => return Ok(Ok(None)),
+                None => None,
+            }
+        };
+        match found {
+            Some((seq, item)) => {
+                self.next = seq + 1;
+                Ok(Ok(Some((item, self))))
+            }
+            None => Err(self),
+        }
+    }
+}
+
+pub struct BufferedRecover<S, F>
+where
+    S: Stream,
+    S::Item: IntoFuture,
+{
+    inner: BufferedResults<S>,
+    recover: F,
+}
+
+impl<S, F> Stream for BufferedRecover<S, F>
+where
+    S: Stream,
+    S::Item: IntoFuture,
+    F: FnMut(<S::Item as IntoFuture>::Error) -> <S::Item as IntoFuture>::Item,
+{
+    type Item = <S::Item as IntoFuture>::Item;
+    type Error = S::Error;
+
+    fn poll(self) -> StreamPoll<Self> {
+        let BufferedRecover { inner, mut recover } = self;
+        match inner.poll() {
+            Ok(Ok(Some((result, inner)))) => {
+                let item = result.unwrap_or_else(&mut recover);
+                Ok(Ok(Some((item, BufferedRecover { inner, recover }))))
+            }
+            Ok(Ok(None)) => Ok(Ok(None)),
+            Ok(Err(e)) => Ok(Err(e)),
+            Err(inner) => Err(BufferedRecover { inner, recover }),
+        }
+    }
+}
+
+pub struct RollingSum<S>
+where
+    S: Stream,
+{
+    stream: S,
+    window: usize,
+    buf: VecDeque<S::Item>,
+    // The sum of everything in `buf`, or `None` while it's empty.
+    total: Option<S::Item>,
+}
+
+impl<S> Stream for RollingSum<S>
+where
+    S: Stream,
+    S::Item: Clone + Add<Output = S::Item> + Sub<Output = S::Item>,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(mut self) -> StreamPoll<Self> {
+        loop {
+            match self.stream.poll() {
+                Ok(Ok(Some((item, s)))) => {
+                    self.stream = s;
+                    let mut total = match self.total.take() {
+                        Some(total) => total + item.clone(),
+                        None => item.clone(),
+                    };
+                    self.buf.push_back(item);
+                    if self.buf.len() > self.window {
+                        if let Some(oldest) = self.buf.pop_front() {
+                            total = total - oldest;
+                        }
+                    }
+                    self.total = Some(total.clone());
+                    if self.buf.len() == self.window {
+                        return Ok(Ok(Some((total, self))));
+                    }
+                }
+                Ok(Ok(None)) => return Ok(Ok(None)),
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(s) => {
+                    self.stream = s;
+                    return Err(self);
+                }
+            }
+        }
+    }
+}
+
+pub struct Fanout<S, A, B>
+where
+    S: Stream,
+{
+    // `None` once the stream has ended and only flushing is left.
+    stream: Option<S>,
+    a: A,
+    b: B,
+    pending_a: Option<S::Item>,
+    pending_b: Option<S::Item>,
+}
+
+impl<S, A, B> Future for Fanout<S, A, B>
+where
+    S: Stream,
+    S::Item: Clone,
+    A: Sink<SinkItem = S::Item, SinkError = S::Error>,
+    B: Sink<SinkItem = S::Item, SinkError = S::Error>,
+{
+    type Item = (A, B);
+    type Error = S::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let Fanout {
+            mut stream,
+            a,
+            b,
+            pending_a,
+            pending_b,
+        } = self;
+        let (mut a, mut pending_a) = match send_pending(a, pending_a) {
+            Ok(sent) => sent,
+            Err(e) => return Ok(Err(e)),
+        };
+        let (mut b, mut pending_b) = match send_pending(b, pending_b) {
+            Ok(sent) => sent,
+            Err(e) => return Ok(Err(e)),
+        };
+        while pending_a.is_none() && pending_b.is_none() {
+            let s = match stream.take() {
+                Some(s) => s,
+                None => {
+                    let a = match a.poll_complete() {
+                        Ok(Ok(a)) => Ok(a),
+                        Ok(Err(e)) => return Ok(Err(e)),
+                        Err(a) => Err(a),
+                    };
+                    let b = match b.poll_complete() {
+                        Ok(Ok(b)) => Ok(b),
+                        Ok(Err(e)) => return Ok(Err(e)),
+                        Err(b) => Err(b),
+                    };
+                    return match (a, b) {
+                        (Ok(a), Ok(b)) => Ok(Ok((a, b))),
+                        (Ok(a), Err(b)) | (Err(a), Ok(b)) | (Err(a), Err(b)) => Err(Fanout {
+                            stream: None,
+                            a,
+                            b,
+                            pending_a: None,
+                            pending_b: None,
+                        }),
+                    };
+                }
+            };
+            match s.poll() {
+                Ok(Ok(Some((item, s)))) => {
+                    stream = Some(s);
+                    let (a2, rest_a) = match send_pending(a, Some(item.clone())) {
+                        Ok(sent) => sent,
+                        Err(e) => return Ok(Err(e)),
+                    };
+                    let (b2, rest_b) = match send_pending(b, Some(item)) {
+                        Ok(sent) => sent,
+                        Err(e) => return Ok(Err(e)),
+                    };
+                    a = a2;
+                    b = b2;
+                    pending_a = rest_a;
+                    pending_b = rest_b;
+                }
+                Ok(Ok(None)) => {}
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(s) => {
+                    stream = Some(s);
+                    break;
+                }
+            }
+        }
+        Err(Fanout {
+            stream,
+            a,
+            b,
+            pending_a,
+            pending_b,
+        })
+    }
+}
+
+pub struct SkipLast<S>
+where
+    S: Stream,
+{
+    stream: S,
+    n: usize,
+    buf: VecDeque<S::Item>,
+}
+
+impl<S> Stream for SkipLast<S>
+where
+    S: Stream,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(mut self) -> StreamPoll<Self> {
+        loop {
+            match self.stream.poll() {
+                Ok(Ok(Some((item, s)))) => {
+                    self.stream = s;
+                    self.buf.push_back(item);
+                    if self.buf.len() > self.n {
+                        if let Some(item) = self.buf.pop_front() {
+                            return Ok(Ok(Some((item, self))));
+                        }
+                    }
+                }
+                Ok(Ok(None)) => return Ok(Ok(None)),
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(s) => {
+                    self.stream = s;
+                    return Err(self);
+                }
+            }
+        }
+    }
+}
+
+pub struct AccumulateInto<S, T, F> {
+    stream: S,
+    cell: Rc<AtomicCell<T>>,
+    f: F,
+}
+
+impl<S, T, F> Stream for AccumulateInto<S, T, F>
+where
+    S: Stream,
+    S::Item: Clone,
+    F: FnMut(&mut T, S::Item),
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(self) -> StreamPoll<Self> {
+        let AccumulateInto {
+            stream,
+            cell,
+            mut f,
+        } = self;
+        let polled = match cell.try_borrow() {
+            Some(mut acc) => {
+                let polled = stream.poll();
+                if let Ok(Ok(Some((ref item, _)))) = polled {
+                    f(&mut acc, item.clone());
+                }
+                polled
+            }
+            None => Err(stream),
+        };
+        match polled {
+            Ok(Ok(Some((item, stream)))) => {
+                Ok(Ok(Some((item, AccumulateInto { stream, cell, f }))))
+            }
+            Ok(Ok(None)) => Ok(Ok(None)),
+            Ok(Err(e)) => Ok(Err(e)),
+            Err(stream) => Err(AccumulateInto { stream, cell, f }),
+        }
+    }
+}
+
+pub struct CollectWithCapacity<S>
+where
+    S: Stream,
+{
+    stream: S,
+    items: Vec<S::Item>,
+}
+
+impl<S> Future for CollectWithCapacity<S>
+where
+    S: Stream,
+{
+    type Item = Vec<S::Item>;
+    type Error = S::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let CollectWithCapacity {
+            mut stream,
+            mut items,
+        } = self;
+        loop {
+            match stream.poll() {
+                Ok(Ok(Some((item, s)))) => {
+                    items.push(item);
+                    stream = s;
+                }
+                Ok(Ok(None)) => return Ok(Ok(items)),
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(stream) => return Err(CollectWithCapacity { stream, items }),
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Progress {
+    pub completed: usize,
+    pub total: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum JoinEvent<T> {
+    Progress(Progress),
+    Done(Vec<T>),
+}
+
+pub struct JoinProgress<A>
+where
+    A: Future,
+{
+    slots: Vec<_JoinSlot<A>>,
+    completed: usize,
+    done: bool,
+}
+
+// Joins every future in `iter` like `zip_all`, but as a stream which yields
+// a `Progress` event each time one of them completes, followed by all the
+// items in input order. The first error ends the stream.
+pub fn join_progress<I>(iter: I) -> JoinProgress<<I::Item as IntoFuture>::Future>
+where
+    I: IntoIterator,
+    I::Item: IntoFuture,
+{
+    JoinProgress {
+        slots: iter
+            .into_iter()
+            .map(|f| _JoinSlot::Pending(f.into_future()))
+            .collect(),
+        completed: 0,
+        done: false,
+    }
+}
+
+impl<A> Stream for JoinProgress<A>
+where
+    A: Future,
+{
+    type Item = JoinEvent<A::Item>;
+    type Error = A::Error;
+
+    fn poll(mut self) -> StreamPoll<Self> {
+        if self.done {
+            return Ok(Ok(None));
+        }
+        let total = self.slots.len();
+        if self.completed == total {
+            let items = self
+                .slots
+                .drain(..)
+                .map(|slot| match slot {
+                    _JoinSlot::Done(item) => item,
+                    _JoinSlot::Pending(_) => unreachable!(),
+                })
+                .collect();
+            self.done = true;
+            return Ok(Ok(Some((JoinEvent::Done(items), self))));
+        }
+        // Report at most one completion per poll, leaving any others to be
+        // picked up next time.
+        let mut slots = Vec::with_capacity(total);
+        let mut polled = std::mem::take(&mut self.slots).into_iter();
+        let mut finished = false;
+        for slot in &mut polled {
+            match slot {
+                _JoinSlot::Pending(f) => match f.poll() {
+                    Ok(Ok(item)) => {
+                        slots.push(_JoinSlot::Done(item));
+                        finished = true;
+                        break;
+                    }
+                    Ok(Err(e)) => return Ok(Err(e)),
+                    Err(f) => slots.push(_JoinSlot::Pending(f)),
+                },
+                done => slots.push(done),
+            }
+        }
+        slots.extend(polled);
+        self.slots = slots;
+        if !finished {
+            return Err(self);
+        }
+        self.completed += 1;
+        let progress = Progress {
+            completed: self.completed,
+            total,
+        };
+        Ok(Ok(Some((JoinEvent::Progress(progress), self))))
+    }
+}
+
+pub struct SplitBefore<S, P>
+where
+    S: Stream,
+{
+    stream: Option<S>,
+    batch: Vec<S::Item>,
+    p: P,
+}
+
+impl<S, P> Stream for SplitBefore<S, P>
+where
+    S: Stream,
+    P: FnMut(&S::Item) -> bool,
+{
+    type Item = Vec<S::Item>;
+    type Error = S::Error;
+
+    fn poll(mut self) -> StreamPoll<Self> {
+        while let Some(stream) = self.stream.take() {
+            match stream.poll() {
+                Ok(Ok(Some((item, s)))) => {
+                    self.stream = Some(s);
+                    if (self.p)(&item) && !self.batch.is_empty() {
+                        let batch = std::mem::replace(&mut self.batch, vec![item]);
+                        return Ok(Ok(Some((batch, self))));
+                    }
+                    self.batch.push(item);
+                }
+                Ok(Ok(None)) => {}
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(s) => {
+                    self.stream = Some(s);
+                    return Err(self);
+                }
+            }
+        }
+        if self.batch.is_empty() {
+            return Ok(Ok(None));
+        }
+        let batch = std::mem::take(&mut self.batch);
+        Ok(Ok(Some((batch, self))))
+    }
+}
+
+pub struct RunLength<S>
+where
+    S: Stream,
+{
+    stream: Option<S>,
+    run: Option<(S::Item, usize)>,
+}
+
+impl<S> Stream for RunLength<S>
+where
+    S: Stream,
+    S::Item: PartialEq + Clone,
+{
+    type Item = (S::Item, usize);
+    type Error = S::Error;
+
+    fn poll(mut self) -> StreamPoll<Self> {
+        while let Some(stream) = self.stream.take() {
+            match stream.poll() {
+                Ok(Ok(Some((item, s)))) => {
+                    self.stream = Some(s);
+                    match self.run.take() {
+                        Some((value, n)) if value == item => self.run = Some((value, n + 1)),
+                        Some(run) => {
+                            self.run = Some((item, 1));
+                            return Ok(Ok(Some((run, self))));
+                        }
+                        None => self.run = Some((item, 1)),
+                    }
+                }
+                Ok(Ok(None)) => {}
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(s) => {
+                    self.stream = Some(s);
+                    return Err(self);
+                }
+            }
+        }
+        match self.run.take() {
+            Some(run) => Ok(Ok(Some((run, self)))),
+            None => Ok(Ok(None)),
+        }
+    }
+}
+
+pub struct TotalTimeout<S> {
+    stream: S,
+    polls: usize,
+    max: usize,
+}
+
+impl<S> Stream for TotalTimeout<S>
+where
+    S: Stream,
+    S::Error: From<TimeoutError>,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(mut self) -> StreamPoll<Self> {
+        if self.polls == self.max {
+            return Ok(Err(TimeoutError.into()));
+        }
+        self.polls += 1;
+        match self.stream.poll() {
+            Ok(Ok(Some((item, s)))) => {
+                self.stream = s;
+                Ok(Ok(Some((item, self))))
+            }
+            Ok(Ok(None)) => Ok(Ok(None)),
+            Ok(Err(e)) => Ok(Err(e)),
+            Err(s) => {
+                self.stream = s;
+                Err(self)
+            }
+        }
+    }
+}
+
+pub struct Inspect<S, F> {
+    stream: S,
+    f: F,
+}
+
+impl<S, F> Stream for Inspect<S, F>
+where
+    S: Stream,
+    F: FnMut(&S::Item),
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(mut self) -> StreamPoll<Self> {
+        match self.stream.poll() {
+            Ok(Ok(Some((item, s)))) => {
+                (self.f)(&item);
+                self.stream = s;
+                Ok(Ok(Some((item, self))))
+            }
+            Ok(Ok(None)) => Ok(Ok(None)),
+            Ok(Err(e)) => Ok(Err(e)),
+            Err(s) => {
+                self.stream = s;
+                Err(self)
+            }
+        }
+    }
+}
+
+pub struct Drain<S> {
+    stream: S,
+}
+
+impl<S> Future for Drain<S>
+where
+    S: Stream,
+{
+    type Item = ();
+    type Error = S::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let mut stream = self.stream;
+        loop {
+            match stream.poll() {
+                Ok(Ok(Some((_, s)))) => stream = s,
+                Ok(Ok(None)) => return Ok(Ok(())),
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(stream) => return Err(Drain { stream }),
+            }
+        }
+    }
+}
+
+pub struct CollectBursts<S>
+where
+    S: Stream,
+{
+    stream: Option<S>,
+    batch: Vec<S::Item>,
+    idle: usize,
+    // Pending polls since the last item.
+    spins: usize,
+}
+
+impl<S> Stream for CollectBursts<S>
+where
+    S: Stream,
+{
+    type Item = Vec<S::Item>;
+    type Error = S::Error;
+
+    fn poll(mut self) -> StreamPoll<Self> {
+        while let Some(stream) = self.stream.take() {
+            match stream.poll() {
+                Ok(Ok(Some((item, s)))) => {
+                    self.stream = Some(s);
+                    self.spins = 0;
+                    self.batch.push(item);
+                }
+                Ok(Ok(None)) => {}
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(s) => {
+                    self.stream = Some(s);
+                    if !self.batch.is_empty() {
+                        self.spins += 1;
+                        if self.spins == self.idle {
+                            self.spins = 0;
+                            let batch = std::mem::take(&mut self.batch);
+                            return Ok(Ok(Some((batch, self))));
+                        }
+                    }
+                    return Err(self);
+                }
+            }
+        }
+        if self.batch.is_empty() {
+            return Ok(Ok(None));
+        }
+        let batch = std::mem::take(&mut self.batch);
+        Ok(Ok(Some((batch, self))))
+    }
+}
+
+pub struct Sorted<S>
+where
+    S: Stream,
+{
+    inner: CollectWithCapacity<S>,
+}
+
+impl<S> Future for Sorted<S>
+where
+    S: Stream,
+    S::Item: Ord,
+{
+    type Item = Vec<S::Item>;
+    type Error = S::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        match self.inner.poll() {
+            Ok(result) => Ok(result.map(|mut items| {
+                items.sort();
+                items
+            })),
+            Err(inner) => Err(Sorted { inner }),
+        }
+    }
+}
+
+pub struct SortedBy<S, F>
+where
+    S: Stream,
+{
+    inner: CollectWithCapacity<S>,
+    cmp: F,
+}
+
+impl<S, F> Future for SortedBy<S, F>
+where
+    S: Stream,
+    F: FnMut(&S::Item, &S::Item) -> Ordering,
+{
+    type Item = Vec<S::Item>;
+    type Error = S::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let SortedBy { inner, mut cmp } = self;
+        match inner.poll() {
+            Ok(result) => Ok(result.map(|mut items| {
+                items.sort_by(&mut cmp);
+                items
+            })),
+            Err(inner) => Err(SortedBy { inner, cmp }),
+        }
+    }
+}
+
+pub struct CollectGrouped<S, K, V> {
+    stream: S,
+    groups: HashMap<K, Vec<V>>,
+}
+
+impl<S, K, V> Future for CollectGrouped<S, K, V>
+where
+    S: Stream<Item = (K, V)>,
+    K: Eq + Hash,
+{
+    type Item = HashMap<K, Vec<V>>;
+    type Error = S::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let CollectGrouped {
+            mut stream,
+            mut groups,
+        } = self;
+        loop {
+            match stream.poll() {
+                Ok(Ok(Some(((k, v), s)))) => {
+                    groups.entry(k).or_insert_with(Vec::new).push(v);
+                    stream = s;
+                }
+                Ok(Ok(None)) => return Ok(Ok(groups)),
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(stream) => return Err(CollectGrouped { stream, groups }),
+            }
+        }
+    }
+}
+
+pub struct Histogram<S>
+where
+    S: Stream,
+{
+    stream: S,
+    counts: HashMap<S::Item, usize>,
+}
+
+impl<S> Future for Histogram<S>
+where
+    S: Stream,
+    S::Item: Eq + Hash,
+{
+    type Item = HashMap<S::Item, usize>;
+    type Error = S::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let Histogram {
+            mut stream,
+            mut counts,
+        } = self;
+        loop {
+            match stream.poll() {
+                Ok(Ok(Some((item, s)))) => {
+                    *counts.entry(item).or_insert(0) += 1;
+                    stream = s;
+                }
+                Ok(Ok(None)) => return Ok(Ok(counts)),
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(stream) => return Err(Histogram { stream, counts }),
+            }
+        }
+    }
+}
+
+pub struct LastN<S>
+where
+    S: Stream,
+{
+    stream: S,
+    n: usize,
+    buf: VecDeque<S::Item>,
+}
+
+impl<S> Future for LastN<S>
+where
+    S: Stream,
+    S::Item: Clone,
+{
+    type Item = Vec<S::Item>;
+    type Error = S::Error;
+
+    fn poll(mut self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        loop {
+            match self.stream.poll() {
+                Ok(Ok(Some((item, s)))) => {
+                    self.stream = s;
+                    if self.n == 0 {
+                        continue;
+                    }
+                    if self.buf.len() == self.n {
+                        self.buf.pop_front();
+                    }
+                    self.buf.push_back(item);
+                }
+                Ok(Ok(None)) => return Ok(Ok(self.buf.into_iter().collect())),
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(s) => {
+                    self.stream = s;
+                    return Err(self);
+                }
+            }
+        }
+    }
+}
+
+pub struct MapWhile<S, F> {
+    stream: S,
+    f: F,
+}
+
+impl<S, F, U> Stream for MapWhile<S, F>
+where
+    S: Stream,
+    F: FnMut(S::Item) -> Option<U>,
+{
+    type Item = U;
+    type Error = S::Error;
+
+    fn poll(mut self) -> StreamPoll<Self> {
+        match self.stream.poll() {
+            Ok(Ok(Some((item, s)))) => match (self.f)(item) {
+                Some(u) => {
+                    self.stream = s;
+                    Ok(Ok(Some((u, self))))
+                }
+                None => Ok(Ok(None)),
+            },
+            Ok(Ok(None)) => Ok(Ok(None)),
+            Ok(Err(e)) => Ok(Err(e)),
+            Err(s) => {
+                self.stream = s;
+                Err(self)
+            }
+        }
+    }
+}
+
+pub struct Coalesce<S, F>
+where
+    S: Stream,
+{
+    stream: Option<S>,
+    held: Option<S::Item>,
+    f: F,
+}
+
+impl<S, F> Stream for Coalesce<S, F>
+where
+    S: Stream,
+    F: FnMut(S::Item, S::Item) -> Result<S::Item, (S::Item, S::Item)>,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(mut self) -> StreamPoll<Self> {
+        while let Some(stream) = self.stream.take() {
+            match stream.poll() {
+                Ok(Ok(Some((item, s)))) => {
+                    self.stream = Some(s);
+                    let merged = match self.held.take() {
+                        Some(held) => (self.f)(held, item),
+                        None => Ok(item),
+                    };
+                    match merged {
+                        Ok(item) => self.held = Some(item),
+                        Err((held, item)) => {
+                            self.held = Some(item);
+                            return Ok(Ok(Some((held, self))));
+                        }
+                    }
+                }
+                Ok(Ok(None)) => {}
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(s) => {
+                    self.stream = Some(s);
+                    return Err(self);
+                }
+            }
+        }
+        match self.held.take() {
+            Some(held) => Ok(Ok(Some((held, self)))),
+            None => Ok(Ok(None)),
+        }
+    }
+}
+
+pub struct FillGaps<S, V> {
+    stream: S,
+    default: V,
+    // The index following the last one yielded.
+    next: Option<usize>,
+    pending: Option<(usize, V)>,
+}
+
+impl<S, V> Stream for FillGaps<S, V>
+where
+    S: Stream<Item = (usize, V)>,
+    V: Clone,
+{
+    type Item = (usize, V);
+    type Error = S::Error;
+
+    fn poll(mut self) -> StreamPoll<Self> {
+        if self.pending.is_none() {
+            match self.stream.poll() {
+                Ok(Ok(Some((item, s)))) => {
+                    self.stream = s;
+                    self.pending = Some(item);
+                }
+                Ok(Ok(None)) => return Ok(Ok(None)),
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(s) => {
+                    self.stream = s;
+                    return Err(self);
+                }
+            }
+        }
+        let (i, v) = self.pending.take().unwrap();
+        let next = self.next.unwrap_or(i);
+        if next < i {
+            self.pending = Some((i, v));
+            self.next = Some(next + 1);
+            let filler = (next, self.default.clone());
+            return Ok(Ok(Some((filler, self))));
+        }
+        self.next = Some(next.max(i + 1));
+        Ok(Ok(Some(((i, v), self))))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct OrderViolation;
+
+pub struct AssertIncreasing<S>
+where
+    S: Stream,
+{
+    stream: S,
+    last: Option<S::Item>,
+}
+
+impl<S> Stream for AssertIncreasing<S>
+where
+    S: Stream,
+    S::Item: PartialOrd + Clone,
+    S::Error: From<OrderViolation>,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(mut self) -> StreamPoll<Self> {
+        match self.stream.poll() {
+            Ok(Ok(Some((item, s)))) => {
+                if let Some(ref last) = self.last {
+                    if item < *last {
+                        return Ok(Err(OrderViolation.into()));
+                    }
+                }
+                self.stream = s;
+                self.last = Some(item.clone());
+                Ok(Ok(Some((item, self))))
+            }
+            Ok(Ok(None)) => Ok(Ok(None)),
+            Ok(Err(e)) => Ok(Err(e)),
+            Err(s) => {
+                self.stream = s;
+                Err(self)
+            }
+        }
+    }
+}
+
+pub struct ThrottleBy<S, F> {
+    stream: S,
+    f: F,
+    cooldown: usize,
+}
+
+impl<S, F> Stream for ThrottleBy<S, F>
+where
+    S: Stream,
+    F: FnMut(&S::Item) -> usize,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(mut self) -> StreamPoll<Self> {
+        if self.cooldown > 0 {
+            self.cooldown -= 1;
+            return Err(self);
+        }
+        match self.stream.poll() {
+            Ok(Ok(Some((item, s)))) => {
+                self.stream = s;
+                self.cooldown = (self.f)(&item);
+                Ok(Ok(Some((item, self))))
+            }
+            Ok(Ok(None)) => Ok(Ok(None)),
+            Ok(Err(e)) => Ok(Err(e)),
+            Err(s) => {
+                self.stream = s;
+                Err(self)
+            }
+        }
+    }
+}
+
+pub struct IdleTimeout<S> {
+    stream: S,
+    idle: usize,
+    max: usize,
+}
+
+impl<S> Stream for IdleTimeout<S>
+where
+    S: Stream,
+    S::Error: From<TimeoutError>,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(mut self) -> StreamPoll<Self> {
+        match self.stream.poll() {
+            Ok(Ok(Some((item, s)))) => {
+                self.stream = s;
+                self.idle = 0;
+                Ok(Ok(Some((item, self))))
+            }
+            Ok(Ok(None)) => Ok(Ok(None)),
+            Ok(Err(e)) => Ok(Err(e)),
+            Err(_) if self.idle == self.max => Ok(Err(TimeoutError.into())),
+            Err(s) => {
+                self.stream = s;
+                self.idle += 1;
+                Err(self)
+            }
+        }
+    }
+}
+
+pub struct Rechunk<S, T> {
+    stream: Option<S>,
+    buf: VecDeque<T>,
+    size: usize,
+}
+
+impl<S, T> Stream for Rechunk<S, T>
+where
+    S: Stream<Item = Vec<T>>,
+{
+    type Item = Vec<T>;
+    type Error = S::Error;
+
+    fn poll(mut self) -> StreamPoll<Self> {
+        while self.buf.len() < self.size {
+            let stream = match self.stream.take() {
+                Some(stream) => stream,
+                None => break,
+            };
+            match stream.poll() {
+                Ok(Ok(Some((items, s)))) => {
+                    self.stream = Some(s);
+                    self.buf.extend(items);
+                }
+                Ok(Ok(None)) => {}
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(s) => {
+                    self.stream = Some(s);
+                    return Err(self);
+                }
+            }
+        }
+        if self.buf.is_empty() {
+            return Ok(Ok(None));
+        }
+        let n = self.size.min(self.buf.len());
+        let chunk = self.buf.drain(..n).collect();
+        Ok(Ok(Some((chunk, self))))
+    }
+}
+
+pub struct TeeInto<S>
+where
+    S: Stream,
+{
+    stream: S,
+    cell: Rc<AtomicCell<Vec<S::Item>>>,
+}
+
+impl<S> Stream for TeeInto<S>
+where
+    S: Stream,
+    S::Item: Clone,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(self) -> StreamPoll<Self> {
+        let TeeInto { stream, cell } = self;
+        let polled = match cell.try_borrow() {
+            Some(mut seen) => {
+                let polled = stream.poll();
+                if let Ok(Ok(Some((ref item, _)))) = polled {
+                    seen.push(item.clone());
+                }
+                polled
+            }
+            None => Err(stream),
+        };
+        match polled {
+            Ok(Ok(Some((item, stream)))) => Ok(Ok(Some((item, TeeInto { stream, cell })))),
+            Ok(Ok(None)) => Ok(Ok(None)),
+            Ok(Err(e)) => Ok(Err(e)),
+            Err(stream) => Err(TeeInto { stream, cell }),
+        }
+    }
+}
+
+pub struct Backpressure<S, G> {
+    stream: S,
+    gauge: G,
+}
+
+impl<S, G> Stream for Backpressure<S, G>
+where
+    S: Stream,
+    G: FnMut() -> bool,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(mut self) -> StreamPoll<Self> {
+        if (self.gauge)() {
+            return Err(self);
+        }
+        match self.stream.poll() {
+            Ok(Ok(Some((item, s)))) => {
+                self.stream = s;
+                Ok(Ok(Some((item, self))))
+            }
+            Ok(Ok(None)) => Ok(Ok(None)),
+            Ok(Err(e)) => Ok(Err(e)),
+            Err(s) => {
+                self.stream = s;
+                Err(self)
+            }
+        }
+    }
+}
+
+struct Unzip<S, A, B> {
+    // `None` once the source has ended or failed.
+    stream: Option<S>,
+    left: VecDeque<A>,
+    right: VecDeque<B>,
+}
+
+impl<S, A, B> Unzip<S, A, B>
+where
+    S: Stream<Item = (A, B)>,
+{
+    // Pulls the next pair from the source, `Err(())` meaning nothing is ready.
+    #[allow(clippy::type_complexity)]
+    fn pull(&mut self) -> Result<Result<Option<(A, B)>, S::Error>, ()> {
+        let stream = match self.stream.take() {
+            Some(stream) => stream,
+            None => return Ok(Ok(None)),
+        };
+        match stream.poll() {
+            Ok(Ok(Some((pair, stream)))) => {
+                self.stream = Some(stream);
+                Ok(Ok(Some(pair)))
+            }
+            Ok(Ok(None)) => Ok(Ok(None)),
+            Ok(Err(e)) => Ok(Err(e)),
+            Err(stream) => {
+                self.stream = Some(stream);
+                Err(())
+            }
+        }
+    }
+}
+
+pub struct UnzipLeft<S, A, B> {
+    shared: Rc<RefCell<Unzip<S, A, B>>>,
+}
+
+pub struct UnzipRight<S, A, B> {
+    shared: Rc<RefCell<Unzip<S, A, B>>>,
+}
+
+impl<S, A, B> Stream for UnzipLeft<S, A, B>
+where
+    S: Stream<Item = (A, B)>,
+{
+    type Item = A;
+    type Error = S::Error;
+
+    fn poll(self) -> StreamPoll<Self> {
+        let pulled = {
+            let mut unzip = self.shared.borrow_mut();
+            match unzip.left.pop_front() {
+                Some(a) => Ok(Ok(Some(a))),
+                None => unzip.pull().map(|pulled| {
+                    pulled.map(|pair| {
+                        pair.map(|(a, b)| {
+                            unzip.right.push_back(b);
+                            a
+                        })
+                    })
+                }),
+            }
+        };
+        match pulled {
+            Ok(Ok(Some(a))) => Ok(Ok(Some((a, self)))),
+            Ok(Ok(None)) => Ok(Ok(None)),
+            Ok(Err(e)) => Ok(Err(e)),
+            Err(()) => Err(self),
+        }
+    }
+}
+
+impl<S, A, B> Stream for UnzipRight<S, A, B>
+where
+    S: Stream<Item = (A, B)>,
+{
+    type Item = B;
+    type Error = S::Error;
+
+    fn poll(self) -> StreamPoll<Self> {
+        let pulled = {
+            let mut unzip = self.shared.borrow_mut();
+            match unzip.right.pop_front() {
+                Some(b) => Ok(Ok(Some(b))),
+                None => unzip.pull().map(|pulled| {
+                    pulled.map(|pair| {
+                        pair.map(|(a, b)| {
+                            unzip.left.push_back(a);
+                            b
+                        })
+                    })
+                }),
+            }
+        };
+        match pulled {
+            Ok(Ok(Some(b))) => Ok(Ok(Some((b, self)))),
+            Ok(Ok(None)) => Ok(Ok(None)),
+            Ok(Err(e)) => Ok(Err(e)),
+            Err(()) => Err(self),
+        }
+    }
+}
+
+pub struct PrefixScan<S, F, U> {
+    stream: S,
+    f: F,
+    acc: U,
+}
+
+impl<S, F, U> Stream for PrefixScan<S, F, U>
+where
+    S: Stream,
+    U: Clone,
+    F: FnMut(&U, S::Item) -> U,
+{
+    type Item = U;
+    type Error = S::Error;
+
+    fn poll(mut self) -> StreamPoll<Self> {
+        match self.stream.poll() {
+            Ok(Ok(Some((item, s)))) => {
+                self.stream = s;
+                self.acc = (self.f)(&self.acc, item);
+                Ok(Ok(Some((self.acc.clone(), self))))
+            }
+            Ok(Ok(None)) => Ok(Ok(None)),
+            Ok(Err(e)) => Ok(Err(e)),
+            Err(s) => {
+                self.stream = s;
+                Err(self)
+            }
+        }
+    }
+}
+
+pub struct TakeDistinct<S>
+where
+    S: Stream,
+{
+    stream: S,
+    seen: HashSet<S::Item>,
+    remaining: usize,
+}
+
+impl<S> Stream for TakeDistinct<S>
+where
+    S: Stream,
+    S::Item: Eq + Hash + Clone,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(mut self) -> StreamPoll<Self> {
+        if self.remaining == 0 {
+            return Ok(Ok(None));
+        }
+        loop {
+            match self.stream.poll() {
+                Ok(Ok(Some((item, s)))) => {
+                    self.stream = s;
+                    if self.seen.insert(item.clone()) {
+                        self.remaining -= 1;
+                        return Ok(Ok(Some((item, self))));
+                    }
+                }
+                Ok(Ok(None)) => return Ok(Ok(None)),
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(s) => {
+                    self.stream = s;
+                    return Err(self);
+                }
+            }
+        }
+    }
+}
+
+pub struct TumblingReduce<S, F>
+where
+    S: Stream,
+{
+    // `None` once the source has ended.
+    stream: Option<S>,
+    f: F,
+    size: usize,
+    window: Vec<S::Item>,
+}
+
+impl<S, F, U> Stream for TumblingReduce<S, F>
+where
+    S: Stream,
+    F: FnMut(&[S::Item]) -> U,
+{
+    type Item = U;
+    type Error = S::Error;
+
+    fn poll(mut self) -> StreamPoll<Self> {
+        loop {
+            let stream = match self.stream.take() {
+                Some(stream) => stream,
+                None => return Ok(Ok(None)),
+            };
+            match stream.poll() {
+                Ok(Ok(Some((item, s)))) => {
+                    self.stream = Some(s);
+                    self.window.push(item);
+                    if self.window.len() == self.size {
+                        let reduced = (self.f)(&self.window);
+                        self.window.clear();
+                        return Ok(Ok(Some((reduced, self))));
+                    }
+                }
+                Ok(Ok(None)) if self.window.is_empty() => return Ok(Ok(None)),
+                Ok(Ok(None)) => {
+                    let reduced = (self.f)(&self.window);
+                    self.window.clear();
+                    return Ok(Ok(Some((reduced, self))));
+                }
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(s) => {
+                    self.stream = Some(s);
+                    return Err(self);
+                }
+            }
+        }
+    }
+}
+
+pub struct Gated<S, Fut> {
+    state: _Gated<S, Fut>,
+}
+
+enum _Gated<S, Fut> {
+    Waiting(Fut, S),
+    Open(S),
+}
+
+impl<S, Fut> Stream for Gated<S, Fut>
+where
+    S: Stream,
+    Fut: Future<Item = (), Error = S::Error>,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(self) -> StreamPoll<Self> {
+        let stream = match self.state {
+            _Gated::Waiting(gate, stream) => match gate.poll() {
+                Ok(Ok(())) => stream,
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(gate) => {
+                    return Err(Gated {
+                        state: _Gated::Waiting(gate, stream),
+                    })
+                }
+            },
+            _Gated::Open(stream) => stream,
+        };
+        match stream.poll() {
+            Ok(Ok(Some((item, s)))) => Ok(Ok(Some((
+                item,
+                Gated {
+                    state: _Gated::Open(s),
+                },
+            )))),
+            Ok(Ok(None)) => Ok(Ok(None)),
+            Ok(Err(e)) => Ok(Err(e)),
+            Err(s) => Err(Gated {
+                state: _Gated::Open(s),
+            }),
+        }
+    }
+}
+
+pub struct ReportTo<S, F, M> {
+    stream: S,
+    tx: Sender<M>,
+    f: F,
+}
+
+impl<S, F, M> Stream for ReportTo<S, F, M>
+where
+    S: Stream,
+    F: FnMut(&S::Item) -> M,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(mut self) -> StreamPoll<Self> {
+        match self.stream.poll() {
+            Ok(Ok(Some((item, s)))) => {
+                self.stream = s;
+                let _ = self.tx.send((self.f)(&item));
+                Ok(Ok(Some((item, self))))
+            }
+            Ok(Ok(None)) => Ok(Ok(None)),
+            Ok(Err(e)) => Ok(Err(e)),
+            Err(s) => {
+                self.stream = s;
+                Err(self)
+            }
+        }
+    }
+}
+
+// A growing set of futures, yielding their items in the order they finish.
+// Each future is polled as a task of its own, so a poll only looks at the
+// futures whose sources have unparked them since. A poll which finds none
+// unparked looks at all of them, so sources which can't unpark anything,
+// like an mpsc `Receiver`, are still seen to. The stream ends once the set
+// is empty, and the first error ends it too.
+pub struct FuturesUnordered<A> {
+    slots: Vec<Option<A>>,
+    tasks: Vec<Task>,
+    free: Vec<usize>,
+    ready: Arc<AtomicCell<Ready>>,
+    sweep: bool,
+}
+
+// The slots unparked since the last poll, and the task which made it.
+struct Ready {
+    slots: Vec<usize>,
+    task: Option<Task>,
+}
+
+// Unparks one slot of a `FuturesUnordered`.
+struct SlotWaker {
+    slot: usize,
+    ready: Arc<AtomicCell<Ready>>,
+}
+
+impl Wake for SlotWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        let task = {
+            let mut ready = self.ready.borrow();
+            ready.slots.push(self.slot);
+            ready.task.take()
+        };
+        if let Some(task) = task {
+            task.unpark();
+        }
+    }
+}
+
+pub fn futures_unordered<I>(iter: I) -> FuturesUnordered<<I::Item as IntoFuture>::Future>
+where
+    I: IntoIterator,
+    I::Item: IntoFuture,
+{
+    let mut set = FuturesUnordered::new();
+    for f in iter {
+        set.push(f.into_future());
+    }
+    set
+}
+
+impl<A> FuturesUnordered<A> {
+    pub fn new() -> FuturesUnordered<A> {
+        FuturesUnordered {
+            slots: Vec::new(),
+            tasks: Vec::new(),
+            free: Vec::new(),
+            ready: Arc::new(AtomicCell::new(Ready {
+                slots: Vec::new(),
+                task: None,
+            })),
+            sweep: true,
+        }
+    }
+
+    // A set which never looks at a future its source hasn't unparked, for
+    // when every source is known to unpark.
+    pub fn unparked_only() -> FuturesUnordered<A> {
+        FuturesUnordered {
+            sweep: false,
+            ..FuturesUnordered::new()
+        }
+    }
+
+    // Adds `future` to the set, to be polled on the next poll.
+    pub fn push(&mut self, future: A) {
+        let slot = match self.free.pop() {
+            Some(slot) => {
+                self.slots[slot] = Some(future);
+                slot
+            }
+            None => {
+                let slot = self.slots.len();
+                self.slots.push(Some(future));
+                self.tasks
+                    .push(Task::from_waker(Waker::from(Arc::new(SlotWaker {
+                        slot,
+                        ready: self.ready.clone(),
+                    }))));
+                slot
+            }
+        };
+        self.ready.borrow().slots.push(slot);
+    }
+
+    // Has the next poll look at every future in the set, whether or not its
+    // source has unparked it.
+    pub(crate) fn unpark_all(&self) {
+        let mut ready = self.ready.borrow();
+        ready
+            .slots
+            .extend((0..self.slots.len()).filter(|&slot| self.slots[slot].is_some()));
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<A> Default for FuturesUnordered<A> {
+    fn default() -> FuturesUnordered<A> {
+        FuturesUnordered::new()
+    }
+}
+
+impl<A> Stream for FuturesUnordered<A>
+where
+    A: Future,
+{
+    type Item = A::Item;
+    type Error = A::Error;
+
+    fn poll(mut self) -> StreamPoll<Self> {
+        if self.is_empty() {
+            return Ok(Ok(None));
+        }
+        let mut unparked = {
+            let mut ready = self.ready.borrow();
+            ready.task = Some(task::park());
+            ready.slots.split_off(0)
+        };
+        if unparked.is_empty() && self.sweep {
+            unparked = (0..self.slots.len()).collect();
+        }
+        unparked.sort_unstable();
+        unparked.dedup();
+        for (n, &slot) in unparked.iter().enumerate() {
+            let future = match self.slots[slot].take() {
+                Some(future) => future,
+                None => continue,
+            };
+            match self.tasks[slot].enter(|| future.poll()) {
+                Ok(Ok(item)) => {
+                    self.free.push(slot);
+                    // The rest are still owed a poll.
+                    self.ready.borrow().slots.extend(&unparked[n + 1..]);
+                    return Ok(Ok(Some((item, self))));
+                }
+                Ok(Err(e)) => return Ok(Err(e)),
+                Err(future) => self.slots[slot] = Some(future),
+            }
+        }
+        Err(self)
+    }
+}
@@ -0,0 +1,92 @@
+// Synchronization primitives which wait by parking the current task rather
+// than blocking the thread.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+use crate::cell::{AtomicCell, AtomicGuard};
+use crate::task::{self, Task};
+
+pub mod mpsc;
+
+// One of two handles on the same value, e.g. held by the read and write
+// halves of a stream. Whichever half finds the other holding the lock is
+// unparked once it's released, instead of blocking its thread.
+pub struct BiLock<T> {
+    inner: Arc<Inner<T>>,
+}
+
+struct Inner<T> {
+    value: AtomicCell<T>,
+    waiting: AtomicCell<Option<Task>>,
+}
+
+// Exclusive access to a `BiLock`'s value, released on drop.
+pub struct BiLockGuard<'a, T> {
+    guard: Option<AtomicGuard<'a, T>>,
+    waiting: &'a AtomicCell<Option<Task>>,
+}
+
+impl<T> BiLock<T> {
+    pub fn new(value: T) -> (BiLock<T>, BiLock<T>) {
+        let inner = Arc::new(Inner {
+            value: AtomicCell::new(value),
+            waiting: AtomicCell::new(None),
+        });
+        (
+            BiLock {
+                inner: inner.clone(),
+            },
+            BiLock { inner },
+        )
+    }
+
+    // Locks the value if the other half isn't holding it. Otherwise returns
+    // `None` and leaves the current task to be unparked on release.
+    pub fn poll_lock(&self) -> Option<BiLockGuard<'_, T>> {
+        let guard = self.inner.value.try_borrow().or_else(|| {
+            *self.inner.waiting.borrow() = Some(task::park());
+            // The other half may have released it before seeing this task.
+            self.inner.value.try_borrow()
+        })?;
+        Some(BiLockGuard {
+            guard: Some(guard),
+            waiting: &self.inner.waiting,
+        })
+    }
+
+    // Takes the value back once both halves are given up.
+    pub fn reunite(self, other: BiLock<T>) -> Result<T, (BiLock<T>, BiLock<T>)> {
+        if !Arc::ptr_eq(&self.inner, &other.inner) {
+            return Err((self, other));
+        }
+        drop(other);
+        match Arc::try_unwrap(self.inner) {
+            Ok(inner) => Ok(inner.value.into_inner()),
+            Err(_) => unreachable!("a BiLock only has two halves"),
+        }
+    }
+}
+
+impl<T> Deref for BiLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.guard.as_ref().unwrap()
+    }
+}
+
+impl<T> DerefMut for BiLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.guard.as_mut().unwrap()
+    }
+}
+
+impl<T> Drop for BiLockGuard<'_, T> {
+    fn drop(&mut self) {
+        drop(self.guard.take());
+        if let Some(task) = self.waiting.borrow().take() {
+            task.unpark();
+        }
+    }
+}
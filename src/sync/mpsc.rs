@@ -0,0 +1,174 @@
+use std::collections::VecDeque;
+use std::sync::mpsc::SendError;
+use std::sync::Arc;
+
+use crate::cell::AtomicCell;
+use crate::stream::{Stream, StreamPoll};
+use crate::task::{self, Task};
+use crate::{Future, Never};
+
+// The queue and the liveness of both ends. A receiver finding the queue
+// empty, or a sender finding it full, leaves its task to be unparked once
+// that changes.
+struct State<T> {
+    queue: VecDeque<T>,
+    buffer: usize,
+    senders: usize,
+    receiving: bool,
+    recv_task: Option<Task>,
+    send_tasks: Vec<Task>,
+}
+
+type Inner<T> = Arc<AtomicCell<State<T>>>;
+
+pub struct Sender<T> {
+    inner: Inner<T>,
+}
+
+// A stream of the items sent on the channel, ending once every `Sender` is
+// gone and the queue has been drained.
+pub struct Receiver<T> {
+    inner: Inner<T>,
+}
+
+// A channel holding at most `buffer` items which haven't been received yet.
+// Sending waits for room, so a slow receiver holds its senders back.
+pub fn channel<T>(buffer: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(
+        buffer > 0,
+        "a bounded channel needs room for at least one item"
+    );
+    let inner = Arc::new(AtomicCell::new(State {
+        queue: VecDeque::with_capacity(buffer),
+        buffer,
+        senders: 1,
+        receiving: true,
+        recv_task: None,
+        send_tasks: Vec::new(),
+    }));
+    (
+        Sender {
+            inner: inner.clone(),
+        },
+        Receiver { inner },
+    )
+}
+
+impl<T> Sender<T> {
+    // A future which queues `item` once there's room, handing the sender back
+    // to send the next one. Fails with the item if the receiver is gone.
+    pub fn send(self, item: T) -> Sending<T> {
+        Sending { sender: self, item }
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        self.inner.borrow().senders += 1;
+        Sender {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let task = {
+            let mut state = self.inner.borrow();
+            state.senders -= 1;
+            match state.senders {
+                0 => state.recv_task.take(),
+                _ => None,
+            }
+        };
+        if let Some(task) = task {
+            task.unpark();
+        }
+    }
+}
+
+pub struct Sending<T> {
+    sender: Sender<T>,
+    item: T,
+}
+
+impl<T> Future for Sending<T> {
+    type Item = Sender<T>;
+    type Error = SendError<T>;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let Sending { sender, item } = self;
+        // The other end holding the cell means it's mid-update and may not
+        // see this task, so ask to be polled again straight away.
+        let inner = sender.inner.clone();
+        let mut state = match inner.try_borrow() {
+            Some(state) => state,
+            None => {
+                task::park().unpark();
+                return Err(Sending { sender, item });
+            }
+        };
+        if !state.receiving {
+            return Ok(Err(SendError(item)));
+        }
+        if state.queue.len() == state.buffer {
+            // A sender polled again before there's room is already waiting.
+            let task = task::park();
+            if !state.send_tasks.iter().any(|t| t.will_unpark(&task)) {
+                state.send_tasks.push(task);
+            }
+            return Err(Sending { sender, item });
+        }
+        state.queue.push_back(item);
+        let task = state.recv_task.take();
+        drop(state);
+        if let Some(task) = task {
+            task.unpark();
+        }
+        Ok(Ok(sender))
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+    type Error = Never;
+
+    fn poll(self) -> StreamPoll<Self> {
+        let inner = self.inner.clone();
+        let mut state = match inner.try_borrow() {
+            Some(state) => state,
+            None => {
+                task::park().unpark();
+                return Err(self);
+            }
+        };
+        match state.queue.pop_front() {
+            Some(item) => {
+                let waiting = state.send_tasks.split_off(0);
+                drop(state);
+                for task in waiting {
+                    task.unpark();
+                }
+                Ok(Ok(Some((item, self))))
+            }
+            None if state.senders == 0 => Ok(Ok(None)),
+            None => {
+                state.recv_task = Some(task::park());
+                Err(self)
+            }
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let waiting = {
+            let mut state = self.inner.borrow();
+            state.receiving = false;
+            state.send_tasks.split_off(0)
+        };
+        for task in waiting {
+            task.unpark();
+        }
+    }
+}
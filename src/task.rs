@@ -0,0 +1,155 @@
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::Waker;
+use std::thread::{self, Thread};
+use std::time::Duration;
+
+// A handle on whatever is driving a future, which a source that can't make
+// progress yet keeps hold of and unparks once it can. Futures still poll by
+// value, so a source only has to register with `park()` when it comes up
+// empty; combinators pass the current task along without knowing about it.
+#[derive(Clone)]
+pub struct Task {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    thread: Thread,
+    unparked: AtomicBool,
+    // Set for a task driven by a `std::future::Future` executor, which is
+    // woken instead of the thread.
+    waker: Option<Waker>,
+}
+
+thread_local!(static CURRENT: RefCell<Option<Task>> = const { RefCell::new(None) });
+
+impl Task {
+    // A task driven by the current thread.
+    pub fn new() -> Task {
+        Task {
+            inner: Arc::new(Inner {
+                thread: thread::current(),
+                unparked: AtomicBool::new(false),
+                waker: None,
+            }),
+        }
+    }
+
+    // A task driven by whichever executor `waker` belongs to.
+    pub fn from_waker(waker: Waker) -> Task {
+        Task {
+            inner: Arc::new(Inner {
+                thread: thread::current(),
+                unparked: AtomicBool::new(false),
+                waker: Some(waker),
+            }),
+        }
+    }
+
+    // Signals that a future driven by this task may be able to move on, and
+    // wakes the driving thread if it's parked.
+    pub fn unpark(&self) {
+        self.inner.unparked.store(true, Ordering::SeqCst);
+        match &self.inner.waker {
+            Some(waker) => waker.wake_by_ref(),
+            None => self.inner.thread.unpark(),
+        }
+    }
+
+    // Whether unparking `other` does the same as unparking this, so a source
+    // which is already holding one needn't keep the other as well.
+    pub fn will_unpark(&self, other: &Task) -> bool {
+        if Arc::ptr_eq(&self.inner, &other.inner) {
+            return true;
+        }
+        match (&self.inner.waker, &other.inner.waker) {
+            (Some(a), Some(b)) => a.will_wake(b),
+            _ => false,
+        }
+    }
+
+    // Whether `unpark` has been called since the last time this was asked.
+    pub fn take_unpark(&self) -> bool {
+        self.inner.unparked.swap(false, Ordering::SeqCst)
+    }
+
+    // Runs `f`, typically a poll, with this as the current task.
+    pub fn enter<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        let prev = CURRENT.with(|current| current.replace(Some(self.clone())));
+        let _reset = Reset(prev);
+        f()
+    }
+}
+
+// Puts back the task which was current before `Task::enter`, even if `f`
+// panics.
+struct Reset(Option<Task>);
+
+impl Drop for Reset {
+    fn drop(&mut self) {
+        let prev = self.0.take();
+        CURRENT.with(|current| *current.borrow_mut() = prev);
+    }
+}
+
+impl Default for Task {
+    fn default() -> Task {
+        Task::new()
+    }
+}
+
+// The task currently being driven on this thread, for a source to unpark
+// later. Outside of `Task::enter` this is a fresh task for the thread, so
+// polling by hand still works.
+pub fn park() -> Task {
+    CURRENT
+        .with(|current| current.borrow().clone())
+        .unwrap_or_default()
+}
+
+// How long to park between polls of a future whose sources can't unpark
+// it, doubling from the first to the second while it stays pending.
+const MIN_PARK: Duration = Duration::from_micros(50);
+const MAX_PARK: Duration = Duration::from_millis(5);
+
+// The wait between polls of a pending future, shared by everything which
+// drives futures: none at all if its task was unparked, and otherwise a
+// timeout which grows for as long as nothing unparks it.
+pub(crate) struct Backoff {
+    timeout: Duration,
+}
+
+impl Backoff {
+    pub(crate) fn new() -> Backoff {
+        Backoff { timeout: MIN_PARK }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.timeout = MIN_PARK;
+    }
+
+    // The timeout to wait out next, then doubles it.
+    pub(crate) fn next_timeout(&mut self) -> Duration {
+        let timeout = self.timeout;
+        self.timeout = (timeout * 2).min(MAX_PARK);
+        timeout
+    }
+
+    // Parks the current thread after a poll of `task`'s future came up empty,
+    // unless something unparked the task meanwhile. Returns whether the task
+    // was unparked, rather than the timeout running out.
+    pub(crate) fn park(&mut self, task: &Task) -> bool {
+        if !task.take_unpark() {
+            thread::park_timeout(self.next_timeout());
+            if !task.take_unpark() {
+                return false;
+            }
+        }
+        self.reset();
+        true
+    }
+}
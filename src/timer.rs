@@ -0,0 +1,144 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::task::{self, Task};
+use crate::{Future, Never, TimeoutError};
+
+// A task to unpark once `at` has passed.
+struct Entry {
+    at: Instant,
+    task: Task,
+}
+
+// Ordered so the `BinaryHeap` pops the earliest deadline first.
+impl Ord for Entry {
+    fn cmp(&self, other: &Entry) -> Ordering {
+        other.at.cmp(&self.at)
+    }
+}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Entry) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Entry) -> bool {
+        self.at == other.at
+    }
+}
+
+impl Eq for Entry {}
+
+static TIMER: OnceLock<Sender<Entry>> = OnceLock::new();
+
+// Hands `task` to the timer thread, starting it on first use, to be
+// unparked at `at`.
+pub(crate) fn register(at: Instant, task: Task) {
+    let timer = TIMER.get_or_init(|| {
+        let (tx, rx) = channel::<Entry>();
+        thread::spawn(move || {
+            let mut entries = BinaryHeap::new();
+            loop {
+                let now = Instant::now();
+                while entries.peek().is_some_and(|e: &Entry| e.at <= now) {
+                    entries.pop().unwrap().task.unpark();
+                }
+                let next = match entries.peek() {
+                    Some(e) => rx.recv_timeout(e.at - now),
+                    None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                match next {
+                    Ok(entry) => entries.push(entry),
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }
+        });
+        tx
+    });
+    let _ = timer.send(Entry { at, task });
+}
+
+// About thirty years, which any `Instant` has room for.
+const FAR_FUTURE: Duration = Duration::from_secs(60 * 60 * 24 * 365 * 30);
+
+// A future which resolves once a given amount of time has passed. The
+// background timer thread unparks whichever task first finds it pending.
+pub struct Delay {
+    at: Instant,
+    registered: bool,
+}
+
+impl Delay {
+    // A duration too long to add to the current time waits for as long as
+    // makes no difference instead.
+    pub fn new(duration: Duration) -> Delay {
+        let now = Instant::now();
+        Delay::until(now.checked_add(duration).unwrap_or(now + FAR_FUTURE))
+    }
+
+    pub fn until(at: Instant) -> Delay {
+        Delay {
+            at,
+            registered: false,
+        }
+    }
+}
+
+impl Future for Delay {
+    type Item = ();
+    type Error = Never;
+
+    fn poll(mut self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        if Instant::now() >= self.at {
+            return Ok(Ok(()));
+        }
+        if !self.registered {
+            register(self.at, task::park());
+            self.registered = true;
+        }
+        Err(self)
+    }
+}
+
+// See `Future::timeout`.
+pub struct Timeout<A> {
+    future: A,
+    delay: Delay,
+}
+
+impl<A> Timeout<A> {
+    pub(crate) fn new(future: A, duration: Duration) -> Timeout<A> {
+        Timeout {
+            future,
+            delay: Delay::new(duration),
+        }
+    }
+}
+
+impl<A> Future for Timeout<A>
+where
+    A: Future,
+    A::Error: From<TimeoutError>,
+{
+    type Item = A::Item;
+    type Error = A::Error;
+
+    fn poll(self) -> Result<Result<Self::Item, Self::Error>, Self> {
+        let Timeout { future, delay } = self;
+        let future = match future.poll() {
+            Ok(result) => return Ok(result),
+            Err(future) => future,
+        };
+        match delay.poll() {
+            Ok(_) => Ok(Err(From::from(TimeoutError))),
+            Err(delay) => Err(Timeout { future, delay }),
+        }
+    }
+}
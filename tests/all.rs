@@ -1,5 +1,14 @@
 extern crate futures;
 
+use std::cell::{Cell, RefCell};
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
 use futures::*;
 
 fn is_future_v<A, B, C: Future<Item = A, Error = B>>(_: C) {}
@@ -9,6 +18,7 @@ fn get<F: Future>(f: F) -> Result<F::Item, F::Error> {
 }
 
 #[test]
+#[allow(clippy::redundant_closure)]
 fn result_smoke() {
     let f = Ok(1).into_future();
     println!("{:#?}", f);
@@ -61,3 +71,1493 @@ fn result_smoke() {
     assert!(empty.map(|a| a + 1).poll().is_err());
     assert!(empty.map_err(|a| a + 1).poll().is_err());
 }
+
+#[test]
+fn join_collect() {
+    let f_ok: FutureResult<i32, i32> = Ok(1).into_future();
+    let f_err: FutureResult<i32, i32> = Err(1).into_future();
+    let empty: Empty<i32, i32> = Empty::new();
+
+    assert_eq!(get(f_ok.join_collect(Ok(2))), Ok((1, 2)));
+    assert_eq!(get(f_ok.join_collect(f_err)), Err(vec![1]));
+    assert_eq!(get(f_err.join_collect(Err::<i32, i32>(2))), Err(vec![1, 2]));
+    assert!(f_err.join_collect(empty).poll().is_err());
+}
+
+#[test]
+fn future_fn_runs_on_poll() {
+    let calls = Cell::new(0);
+    let f = future_fn(|| {
+        calls.set(calls.get() + 1);
+        Ok::<i32, u32>(1)
+    });
+    assert_eq!(calls.get(), 0);
+    assert_eq!(get(f), Ok(1));
+    assert_eq!(calls.get(), 1);
+
+    assert_eq!(get(future_fn(|| Err::<i32, u32>(2))), Err(2));
+}
+
+#[test]
+fn map_join_in_order() {
+    assert_eq!(
+        get(map_join(vec![1, 2, 3], |n| Ok::<i32, u32>(n * n))),
+        Ok(vec![1, 4, 9])
+    );
+    assert_eq!(
+        get(map_join(vec![1, 2, 3], |n| if n == 2 {
+            Err(n)
+        } else {
+            Ok(n)
+        })),
+        Err(2)
+    );
+
+    let (tx, rx) = channel();
+    let (tx2, rx2) = channel();
+    let f = map_join(vec![rx, rx2], |rx| rx);
+    let f = f.poll().err().unwrap();
+    tx2.send(2).unwrap();
+    let f = f.poll().err().unwrap();
+    tx.send(1).unwrap();
+    assert_eq!(get(f), Ok(vec![1, 2]));
+}
+
+#[test]
+fn and_then_if() {
+    fn halve_evens(n: i32) -> Result<i32, u32> {
+        get(Ok(n)
+            .into_future()
+            .and_then_if(|n| n % 2 == 0, |n| Ok(n / 2)))
+    }
+    assert_eq!(halve_evens(4), Ok(2));
+    assert_eq!(halve_evens(3), Ok(3));
+
+    let f_err: FutureResult<i32, i32> = Err(1).into_future();
+    assert_eq!(get(f_err.and_then_if(|_| true, |n| Ok(n + 1))), Err(1));
+    let f_ok: FutureResult<i32, i32> = Ok(2).into_future();
+    assert_eq!(get(f_ok.and_then_if(|_| true, |n| Err(n + 1))), Err(3));
+    assert!(f_ok.and_then_if(|_| true, |_| Empty::new()).poll().is_err());
+}
+
+#[test]
+fn timeout_return() {
+    let f_ok: FutureResult<i32, i32> = Ok(1).into_future();
+    assert_eq!(get(f_ok.timeout_return(1)).ok(), Some(Ok(1)));
+
+    let (tx, rx) = channel::<i32>();
+    let f = rx.timeout_return(2);
+    let f = f.poll().err().unwrap();
+    let rx = match get(f) {
+        Err((TimeoutError, rx)) => rx,
+        Ok(_) => panic!("expected a timeout"),
+    };
+    tx.send(3).unwrap();
+    assert_eq!(get(rx), Ok(3));
+}
+
+#[test]
+fn fold_futures_sum() {
+    assert_eq!(
+        get(fold_futures(vec![1, 2, 3], 0, |acc, x| Ok::<i32, u32>(
+            acc + x
+        ))),
+        Ok(6)
+    );
+    assert_eq!(
+        get(fold_futures(vec![1, 2, 3], 0, |acc, x| if x == 2 {
+            Err(x)
+        } else {
+            Ok(acc + x)
+        })),
+        Err(2)
+    );
+
+    let (tx, rx) = channel();
+    let (tx2, rx2) = channel();
+    let f = fold_futures(vec![rx, rx2], 10, |acc, rx| rx.map(move |y: i32| acc + y));
+    let f = f.poll().err().unwrap();
+    tx.send(1).unwrap();
+    let f = f.poll().err().unwrap();
+    tx2.send(2).unwrap();
+    assert_eq!(get(f), Ok(13));
+}
+
+#[test]
+fn and_then_keep() {
+    let f_ok: FutureResult<i32, i32> = Ok(1).into_future();
+    let f_err: FutureResult<i32, i32> = Err(1).into_future();
+
+    assert_eq!(get(f_ok.and_then_keep(|a| Ok(a + 2))), Ok(3));
+    assert_eq!(
+        get(f_ok.and_then_keep(|a| Err::<i32, i32>(a + 2))),
+        Err((Some(1), 3))
+    );
+    assert_eq!(get(f_err.and_then_keep(|a| Ok(a + 2))), Err((None, 1)));
+
+    let (tx, rx) = channel();
+    let f = f_ok.and_then_keep(|_| rx.map_err(|_| 0));
+    let f = f.poll().err().unwrap();
+    drop(tx);
+    assert_eq!(get(f), Err::<i32, _>((Some(1), 0)));
+}
+
+#[test]
+fn into_blocking_iter() {
+    let f_ok: FutureResult<i32, i32> = Ok(1).into_future();
+    assert_eq!(f_ok.into_blocking_iter().collect::<Vec<_>>(), vec![Ok(1)]);
+
+    let (tx, rx) = channel();
+    let t = thread::spawn(move || tx.send(2).unwrap());
+    let mut results = Vec::new();
+    for result in rx.into_blocking_iter() {
+        results.push(result);
+    }
+    t.join().unwrap();
+    assert_eq!(results, vec![Ok(2)]);
+}
+
+#[test]
+fn observable_counts_polls() {
+    let (tx, rx) = channel();
+    let (f, progress) = rx.observable();
+    assert_eq!(progress.polls(), 0);
+    let f = f.poll().err().unwrap();
+    let f = f.poll().err().unwrap();
+    assert_eq!(progress.polls(), 2);
+    tx.send(1).unwrap();
+    assert_eq!(get(f), Ok(1));
+    assert_eq!(progress.polls(), 3);
+}
+
+#[test]
+fn zip_all_reduces() {
+    assert_eq!(get(zip_all(vec![Ok::<i32, u32>(1), Ok(2)])), Ok(vec![1, 2]));
+    assert_eq!(get(zip_all(vec![Ok(1), Err(2), Err(3)])), Err(2));
+
+    let (tx, rx) = channel();
+    let (tx2, rx2) = channel();
+    let (tx3, rx3) = channel();
+    let f = zip_all_with(vec![rx, rx2, rx3], |items: Vec<i32>| {
+        items.iter().sum::<i32>()
+    });
+    tx.send(1).unwrap();
+    tx3.send(3).unwrap();
+    let f = f.poll().err().unwrap();
+    tx2.send(2).unwrap();
+    assert_eq!(get(f), Ok(6));
+}
+
+#[test]
+fn join_priority() {
+    let f_ok: FutureResult<i32, i32> = Ok(1).into_future();
+    let f_err: FutureResult<i32, i32> = Err(1).into_future();
+    let f_err2: FutureResult<i32, i32> = Err(2).into_future();
+
+    assert_eq!(get(f_ok.join_priority(Ok(2), true)), Ok((1, 2)));
+    assert_eq!(get(f_err.join_priority(f_err2, true)), Err(1));
+    assert_eq!(get(f_err.join_priority(f_err2, false)), Err(2));
+    assert_eq!(get(f_ok.join_priority(f_err2, true)), Err(2));
+
+    // The configured side is driven first on every poll.
+    let order = Cell::new(Vec::new());
+    let log = &order;
+    let record = |side| {
+        future_fn(move || {
+            let mut seen = log.take();
+            seen.push(side);
+            log.set(seen);
+            Ok::<_, i32>(side)
+        })
+    };
+    assert_eq!(
+        get(record("a").join_priority(record("b"), false)),
+        Ok(("a", "b"))
+    );
+    assert_eq!(order.take(), vec!["b", "a"]);
+}
+
+#[test]
+fn pipe_applies_adapter_stacks() {
+    fn doubled<F: Future<Item = i32, Error = u8>>(f: F) -> impl Future<Item = i32, Error = u32> {
+        f.map(|a| a * 2).map_err(u32::from)
+    }
+
+    let f_ok: FutureResult<i32, u8> = Ok(2).into_future();
+    let f_err: FutureResult<i32, u8> = Err(3).into_future();
+    assert_eq!(get(f_ok.pipe(doubled)), Ok(4));
+    assert_eq!(get(f_err.pipe(doubled).map(|a| a + 1)), Err(3u32));
+}
+
+#[test]
+fn with_budget_is_shared() {
+    fn fails_once() -> impl FnMut() -> Result<&'static str, &'static str> {
+        let mut calls = 0;
+        move || {
+            calls += 1;
+            if calls == 1 {
+                Err("flaky")
+            } else {
+                Ok("done")
+            }
+        }
+    }
+
+    let budget = RetryBudget::new(1);
+    let a = with_budget(fails_once(), budget.clone());
+    let b = with_budget(fails_once(), budget.clone());
+    assert_eq!(get(a), Ok("done"));
+    assert_eq!(budget.remaining(), 0);
+    assert_eq!(get(b), Err("flaky"));
+}
+
+#[test]
+fn with_metrics_counts() {
+    let metrics = Metrics::new();
+    let (tx, rx) = channel::<i32>();
+    let f = rx.with_metrics("recv", metrics.clone());
+    let f = f.poll().err().unwrap();
+    drop(tx);
+    assert!(get(f).is_err());
+    assert_eq!(metrics.get("recv.polls"), 2);
+    assert_eq!(metrics.get("recv.errors"), 1);
+    assert_eq!(metrics.get("recv.completions"), 0);
+
+    let f_ok: FutureResult<i32, i32> = Ok(1).into_future();
+    assert_eq!(get(f_ok.with_metrics("ok", metrics.clone())), Ok(1));
+    assert_eq!(metrics.get("ok.completions"), 1);
+}
+
+#[test]
+fn select_map_applies_winner_mapper() {
+    let number: FutureResult<i32, u32> = Ok(2).into_future();
+    let empty_str: Empty<&str, u32> = Empty::new();
+    let win_a = number.select_map(empty_str, |n| n.to_string(), |s| s.to_string());
+    assert_eq!(get(win_a), Ok("2".to_string()));
+
+    let empty_num: Empty<i32, u32> = Empty::new();
+    let win_b = empty_num.select_map(Ok("b"), |n| n.to_string(), |s: &str| s.repeat(2));
+    assert_eq!(get(win_b), Ok("bb".to_string()));
+
+    let (tx, rx) = channel::<i32>();
+    let f = rx.map_err(|_| 0u32).select_map(empty_str, |n| n * 2, |_| 0);
+    let f = f.poll().err().unwrap();
+    tx.send(4).unwrap();
+    assert_eq!(get(f), Ok(8));
+}
+
+#[test]
+fn for_result_invokes_callback() {
+    let outcome = Cell::new(None);
+    let f_err: FutureResult<i32, i32> = Err(2).into_future();
+    assert_eq!(get(f_err.for_result(|r| outcome.set(Some(r)))), Ok(()));
+    assert_eq!(outcome.take(), Some(Err(2)));
+
+    let (tx, rx) = channel();
+    let f = rx.for_result(|r| outcome.set(Some(r.map_err(|_| 0))));
+    let f = f.poll().err().unwrap();
+    assert_eq!(outcome.take(), None);
+    tx.send(1).unwrap();
+    assert_eq!(get(f), Ok(()));
+    assert_eq!(outcome.take(), Some(Ok(1)));
+}
+
+#[test]
+fn stage_timeouts() {
+    #[derive(Debug, PartialEq)]
+    enum ChainError {
+        Recv,
+        Timeout(usize),
+    }
+    impl From<StageTimeout> for ChainError {
+        fn from(e: StageTimeout) -> ChainError {
+            ChainError::Timeout(e.stage)
+        }
+    }
+
+    let stages = StageCounter::new();
+    let (tx, rx) = channel::<i32>();
+    let next = stages.clone();
+    let chain = rx
+        .map_err(|_| ChainError::Recv)
+        .and_then(move |_| {
+            next.advance();
+            Empty::<i32, ChainError>::new()
+        })
+        .with_stage_timeouts(vec![2, 3], stages);
+
+    let chain = chain.poll().err().unwrap();
+    tx.send(1).unwrap();
+    let mut chain = chain.poll().err().unwrap();
+    for _ in 0..2 {
+        chain = chain.poll().err().unwrap();
+    }
+    assert_eq!(get(chain), Err(ChainError::Timeout(1)));
+
+    let stages = StageCounter::new();
+    let f_ok: FutureResult<i32, ChainError> = Ok(1).into_future();
+    assert_eq!(get(f_ok.with_stage_timeouts(vec![0], stages)), Ok(1));
+}
+
+#[test]
+fn hold_withholds_result() {
+    let f_ok: FutureResult<i32, i32> = Ok(1).into_future();
+    assert_eq!(get(f_ok.hold(0)), Ok(1));
+
+    let (tx, rx) = channel();
+    let f = rx.hold(2);
+    let f = f.poll().err().unwrap();
+    tx.send(5).unwrap();
+    let f = f.poll().err().unwrap();
+    let f = f.poll().err().unwrap();
+    assert_eq!(get(f), Ok(5));
+}
+
+#[test]
+fn race_keep_returns_losers() {
+    let (tx, rx) = channel::<i32>();
+    let (tx2, rx2) = channel::<i32>();
+    let (tx3, rx3) = channel::<i32>();
+    let f = race_keep(vec![rx, rx2, rx3]);
+    let f = f.poll().err().unwrap();
+    tx2.send(2).unwrap();
+    let (winner, losers) = get(f).unwrap();
+    assert_eq!(winner, Ok(2));
+    assert_eq!(losers.len(), 2);
+
+    tx.send(1).unwrap();
+    tx3.send(3).unwrap();
+    let values: Vec<_> = losers.into_iter().map(get).collect();
+    assert_eq!(values, vec![Ok(1), Ok(3)]);
+}
+
+#[test]
+fn timeout_stream() {
+    let f = Empty::<i32, TimeoutError>::new().timeout_stream(stream::iter(vec![(), ()]));
+    assert_eq!(get(f), Err(TimeoutError));
+
+    // One tick isn't enough to time out.
+    let f = Empty::<i32, TimeoutError>::new().timeout_stream(stream::iter(vec![()]));
+    assert!(f.poll().is_err());
+
+    // Resolving before the ticks run out wins.
+    let f = Ok::<i32, TimeoutError>(1)
+        .into_future()
+        .timeout_stream(stream::iter(vec![(), ()]));
+    assert_eq!(get(f), Ok(1));
+}
+
+#[test]
+fn orchestrate() {
+    let attempts = Cell::new(0);
+    let f = Orchestrate::new(|| {
+        attempts.set(attempts.get() + 1);
+        // The first attempt takes too long; the second is quick enough.
+        let polls = if attempts.get() == 1 { 5 } else { 1 };
+        Ok::<i32, TimeoutError>(attempts.get())
+            .into_future()
+            .hold(polls)
+    })
+    .timeout_spins(2)
+    .retries(2)
+    .fallback(-1)
+    .build();
+    let f = f.poll().err().unwrap();
+    let f = f.poll().err().unwrap();
+    assert_eq!(attempts.get(), 2);
+    assert_eq!(get(f), Ok(2));
+    assert_eq!(attempts.get(), 2);
+
+    // A timed-out attempt is replaced straight away, within the same poll.
+    let f = Orchestrate::new(Empty::<i32, TimeoutError>::new)
+        .timeout_spins(1)
+        .retries(1)
+        .fallback(-1)
+        .build();
+    assert_eq!(get(f), Ok(-1));
+}
+
+#[test]
+fn shared_sync_runs_once() {
+    let runs = Arc::new(AtomicUsize::new(0));
+    let counter = runs.clone();
+    let (tx, rx) = channel::<i32>();
+    let f = rx
+        .map(move |x| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            x * 2
+        })
+        .shared_sync();
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let f = f.clone();
+            thread::spawn(move || get(f))
+        })
+        .collect();
+    tx.send(21).unwrap();
+    for handle in handles {
+        assert_eq!(handle.join().unwrap(), Ok(42));
+    }
+    assert_eq!(get(f), Ok(42));
+    assert_eq!(runs.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn shared_clones_see_one_outcome() {
+    let runs = Rc::new(Cell::new(0));
+    let counter = runs.clone();
+    let (tx, rx) = channel::<i32>();
+    let f = rx
+        .map(move |x| {
+            counter.set(counter.get() + 1);
+            x * 2
+        })
+        .shared();
+    let f2 = f.clone();
+    let f = f.poll().err().unwrap();
+    tx.send(21).unwrap();
+    assert_eq!(get(f2).map(|x| *x), Ok(42));
+    assert_eq!(get(f.clone()).map(|x| *x), Ok(42));
+    assert_eq!(get(f).map(|x| *x), Ok(42));
+    assert_eq!(runs.get(), 1);
+
+    let f = err::<(), String>("once".to_string()).shared();
+    let e = get(f.clone()).unwrap_err();
+    assert!(Arc::ptr_eq(&e, &get(f).unwrap_err()));
+
+    // Clones on other threads wait for whichever one drives the future.
+    let (tx, rx) = channel::<i32>();
+    let f = rx.map(|x| x + 1).shared();
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let f = f.clone();
+            thread::spawn(move || f.wait().map(|x| *x))
+        })
+        .collect();
+    tx.send(1).unwrap();
+    for handle in handles {
+        assert_eq!(handle.join().unwrap(), Ok(2));
+    }
+}
+
+#[test]
+fn shared_wakes_clones_when_put_back() {
+    let (inside_tx, inside) = channel::<()>();
+    let (go, go_rx) = channel::<()>();
+    let (_tx, rx) = channel::<i32>();
+    let f = rx
+        .on_poll(move |_| {
+            inside_tx.send(()).unwrap();
+            go_rx.recv().unwrap();
+        })
+        .shared();
+    let f2 = f.clone();
+    let polling = thread::spawn(move || f.poll().is_err());
+    inside.recv().unwrap();
+    // The first clone has the future out, so this one waits for it.
+    let task = task::Task::new();
+    assert!(task.enter(|| f2.poll()).is_err());
+    go.send(()).unwrap();
+    assert!(polling.join().unwrap());
+    assert!(task.take_unpark());
+}
+
+#[test]
+fn shared_panics_poison_clones() {
+    let f = lazy(|| -> Result<i32, i32> { panic!("boom") }).shared();
+    let f2 = f.clone();
+    assert!(panic::catch_unwind(AssertUnwindSafe(|| f.poll())).is_err());
+    assert!(panic::catch_unwind(AssertUnwindSafe(|| f2.poll())).is_err());
+}
+
+#[test]
+fn wait_drives_to_completion() {
+    assert_eq!(Ok::<i32, i32>(1).into_future().wait(), Ok(1));
+    assert_eq!(Err::<i32, i32>(2).into_future().wait(), Err(2));
+
+    let (tx, rx) = channel::<i32>();
+    let sender = thread::spawn(move || {
+        thread::sleep(std::time::Duration::from_millis(10));
+        tx.send(3).unwrap();
+    });
+    assert_eq!(rx.wait(), Ok(3));
+    sender.join().unwrap();
+}
+
+#[test]
+fn wait_spins_gives_up() {
+    let f = Empty::<i32, i32>::new().wait_spins(10);
+    assert!(f.is_err());
+
+    let (tx, rx) = channel::<i32>();
+    let rx = rx.wait_spins(3).err().unwrap();
+    tx.send(4).unwrap();
+    assert_eq!(rx.wait_spins(1).ok(), Some(Ok(4)));
+}
+
+#[test]
+fn then_sees_both_outcomes() {
+    let done = Ok::<i32, u32>(1).into_future();
+    let f = done.then(|r| match r {
+        Ok(v) => Ok::<i32, ()>(v + 1),
+        Err(_) => Ok(0),
+    });
+    assert_eq!(get(f), Ok(2));
+
+    let failed = Err::<i32, u32>(1).into_future();
+    let f = failed.then(|r| match r {
+        Ok(v) => Ok::<i32, ()>(v + 1),
+        Err(_) => Ok(0),
+    });
+    assert_eq!(get(f), Ok(0));
+
+    // Both stages may stay pending for a while.
+    let (tx, rx) = channel::<i32>();
+    let (tx2, rx2) = channel::<i32>();
+    let f = rx.then(move |r| {
+        assert_eq!(r, Ok(3));
+        rx2
+    });
+    let f = f.poll().err().unwrap();
+    tx.send(3).unwrap();
+    let f = f.poll().err().unwrap();
+    tx2.send(4).unwrap();
+    assert_eq!(get(f), Ok(4));
+}
+
+#[test]
+fn then_runs_cleanup_once_for_either_outcome() {
+    let cleaned = Rc::new(Cell::new(0));
+    let finish = |f: FutureResult<i32, u32>| {
+        let cleaned = cleaned.clone();
+        f.then(move |r| {
+            cleaned.set(cleaned.get() + 1);
+            r.map_err(|e| format!("failed with {}", e))
+        })
+    };
+    assert_eq!(get(finish(Ok(1).into_future())), Ok(1));
+    assert_eq!(
+        get(finish(Err(2).into_future())),
+        Err("failed with 2".to_string())
+    );
+    assert_eq!(cleaned.get(), 2);
+}
+
+#[test]
+fn map_poll_converts_outcome() {
+    let convert = |r: Result<i32, u32>| match r {
+        Ok(v) => Ok(v.to_string()),
+        Err(e) => Err(e != 0),
+    };
+    assert_eq!(
+        get(Ok::<i32, u32>(5).into_future().map_poll(convert)),
+        Ok("5".to_string())
+    );
+    assert_eq!(
+        get(Err::<i32, u32>(1).into_future().map_poll(convert)),
+        Err(true)
+    );
+
+    let (tx, rx) = channel::<i32>();
+    let f = rx.map_poll(|r| r.map(|v| v * 2).map_err(|_| ()));
+    let f = f.poll().err().unwrap();
+    tx.send(4).unwrap();
+    assert_eq!(get(f), Ok(8));
+}
+
+#[derive(Debug, PartialEq)]
+struct MyErr;
+
+#[derive(Debug, PartialEq)]
+enum OuterOrInner {
+    Outer(MyErr),
+    Inner,
+}
+
+impl From<MyErr> for OuterOrInner {
+    fn from(e: MyErr) -> OuterOrInner {
+        OuterOrInner::Outer(e)
+    }
+}
+
+#[test]
+fn map_both_async() {
+    let item_ran = Cell::new(false);
+    let f = Ok::<i32, u32>(2).into_future().map_both_async(
+        |v| {
+            item_ran.set(true);
+            Ok::<String, String>(format!("item {}", v)).into_future()
+        },
+        |e| Ok::<String, String>(format!("error {}", e)).into_future(),
+    );
+    assert_eq!(get(f), Ok("item 2".to_string()));
+    assert!(item_ran.get());
+
+    // The error transform's result becomes the error, even when it succeeds.
+    let f = Err::<i32, u32>(7).into_future().map_both_async(
+        |v| Ok::<String, String>(format!("item {}", v)).into_future(),
+        |e| Ok::<String, String>(format!("error {}", e)).into_future(),
+    );
+    assert_eq!(get(f), Err("error 7".to_string()));
+
+    // The transform future may stay pending for a while.
+    let (tx, rx) = channel::<i32>();
+    let f = Ok::<i32, RecvError>(1)
+        .into_future()
+        .map_both_async(move |_| rx, |e| Ok::<RecvError, RecvError>(e).into_future());
+    let f = f.poll().err().unwrap();
+    tx.send(5).unwrap();
+    assert_eq!(get(f), Ok(5));
+}
+
+#[test]
+fn keep_error() {
+    let f = Err::<i32, &str>("boom").into_future().keep_error();
+    let (e, kept) = get(f).unwrap_err();
+    assert_eq!(e, "boom");
+    assert_eq!(kept.last_error(), Some(&"boom"));
+
+    // Polling the spent future fails the same way again.
+    let (again, kept) = get(kept).unwrap_err();
+    assert_eq!(again, "boom");
+    assert_eq!(kept.last_error(), Some(&"boom"));
+
+    let (tx, rx) = channel::<i32>();
+    let f = rx.keep_error().poll().err().unwrap();
+    assert_eq!(f.last_error(), None);
+    tx.send(1).unwrap();
+    assert_eq!(get(f).ok(), Some(1));
+}
+
+#[test]
+fn select_ok_verbose_reports_failures() {
+    let (tx, rx) = channel::<i32>();
+    let (_slow_tx, slow) = channel::<i32>();
+    let (fail_tx, failing) = channel::<i32>();
+    drop(fail_tx);
+    let f = select_ok_verbose(vec![failing, rx, slow]);
+    let f = f.poll().err().unwrap();
+    tx.send(2).unwrap();
+    let (item, errors, rest) = get(f).unwrap();
+    assert_eq!(item, 2);
+    assert_eq!(errors, vec![RecvError]);
+    assert_eq!(rest.len(), 1);
+
+    let all_fail = select_ok_verbose(vec![err::<i32, &str>("a"), err("b")]);
+    assert_eq!(get(all_fail).err(), Some(vec!["a", "b"]));
+}
+
+#[test]
+fn quorum_of_five() {
+    let polls = Rc::new(Cell::new(0));
+    let tracked = |f: Hold<FutureResult<i32, &'static str>>| {
+        let polls = polls.clone();
+        f.on_poll(move |n| polls.set(polls.get().max(n)))
+    };
+    let futures = vec![
+        tracked(ok(1).hold(4)),
+        tracked(err("down").hold(1)),
+        tracked(ok(3).hold(2)),
+        tracked(ok(4).hold(9)),
+        tracked(ok(5).hold(3)),
+    ];
+    assert_eq!(quorum(futures, 3).wait(), Ok(vec![3, 5, 1]));
+    // Resolved on the poll where the third success came in.
+    assert_eq!(polls.get(), 5);
+
+    let doomed = vec![err::<i32, &str>("a"), ok(2), err("b")];
+    assert_eq!(quorum(doomed, 2).wait(), Err("b"));
+}
+
+// The same three futures for every policy: a slow success, a quick failure
+// and a success in between.
+fn contenders() -> Vec<Hold<FutureResult<i32, &'static str>>> {
+    vec![
+        Ok(1).into_future().hold(3),
+        Err("quick").into_future().hold(1),
+        Ok(3).into_future().hold(2),
+    ]
+}
+
+#[test]
+fn combine_policies() {
+    assert_eq!(combine(contenders(), Policy::All).wait(), Err("quick"));
+    assert_eq!(combine(contenders(), Policy::First).wait(), Err("quick"));
+    assert_eq!(combine(contenders(), Policy::Any).wait(), Ok(vec![(2, 3)]));
+
+    let successes = vec![
+        Ok::<i32, ()>(1).into_future().hold(2),
+        Ok(2).into_future().hold(1),
+    ];
+    assert_eq!(
+        combine(successes, Policy::All).wait(),
+        Ok(vec![(0, 1), (1, 2)])
+    );
+
+    let failures: Vec<FutureResult<i32, &str>> =
+        vec![Err("a").into_future(), Err("b").into_future()];
+    assert_eq!(combine(failures, Policy::Any).wait(), Err("b"));
+}
+
+#[test]
+fn ignore_err_if_falls_back() {
+    let f = err::<Vec<i32>, &str>("not found").ignore_err_if(|e| *e == "not found", vec![]);
+    assert_eq!(get(f), Ok(vec![]));
+
+    let (tx, rx) = channel::<i32>();
+    let f = rx.ignore_err_if(|_| true, -1);
+    let f = f.poll().err().unwrap();
+    drop(tx);
+    assert_eq!(get(f), Ok(-1));
+}
+
+#[test]
+fn ignore_err_if_propagates_others() {
+    let f = err::<Vec<i32>, &str>("denied").ignore_err_if(|e| *e == "not found", vec![]);
+    assert_eq!(get(f), Err("denied"));
+
+    let f = ok::<_, &str>(vec![1]).ignore_err_if(|_| true, vec![]);
+    assert_eq!(get(f), Ok(vec![1]));
+}
+
+#[test]
+fn on_poll_counts() {
+    let (tx, rx) = channel::<i32>();
+    let seen = RefCell::new(Vec::new());
+    let f = rx.on_poll(|n| seen.borrow_mut().push(n));
+    let f = f.poll().err().unwrap();
+    let f = f.poll().err().unwrap();
+    tx.send(1).unwrap();
+    assert_eq!(get(f), Ok(1));
+    assert_eq!(*seen.borrow(), vec![1, 2, 3]);
+}
+
+#[test]
+fn select_either_reports_winner() {
+    let (tx, rx) = channel::<i32>();
+    let (_tx2, rx2) = channel::<&str>();
+    let f = rx.select_either(rx2.map_err(|_| "gone"));
+    let f = f.poll().err().unwrap();
+    tx.send(1).unwrap();
+    assert_eq!(get(f).ok(), Some(Either::Left(Ok(1))));
+
+    let (_tx, rx) = channel::<i32>();
+    let f = rx.select_either(err::<(), &str>("boom"));
+    assert_eq!(get(f).ok(), Some(Either::Right(Err("boom"))));
+}
+
+#[test]
+fn select2_hands_back_loser() {
+    let (tx, rx) = channel::<i32>();
+    let (tx2, rx2) = channel::<&str>();
+    let f = rx.map(|n| n * 2).select2(rx2);
+    let f = f.poll().err().unwrap();
+    tx2.send("first").unwrap();
+    let rest = match get(f) {
+        Ok(Either::Right(("first", rest))) => rest,
+        _ => panic!("expected the right side to win"),
+    };
+    tx.send(4).unwrap();
+    assert_eq!(get(rest), Ok(8));
+
+    let (_tx, rx) = channel::<i32>();
+    match get(err::<(), u32>(3).select2(rx)) {
+        Err(Either::Left((3, rx))) => assert!(rx.poll().is_err()),
+        _ => panic!("expected the left side to fail"),
+    }
+}
+
+#[test]
+fn flatten() {
+    let f = Ok::<_, MyErr>(Ok::<i32, MyErr>(5)).into_future().flatten();
+    assert_eq!(get(f), Ok(5));
+
+    // The outer error is converted, and the inner future never runs.
+    let ran = Cell::new(false);
+    let f = Err::<i32, MyErr>(MyErr)
+        .into_future()
+        .map(|_| {
+            ran.set(true);
+            Err::<i32, OuterOrInner>(OuterOrInner::Inner)
+        })
+        .flatten();
+    assert_eq!(get(f), Err(OuterOrInner::Outer(MyErr)));
+    assert!(!ran.get());
+
+    let (tx, rx) = channel::<Receiver<i32>>();
+    let (tx2, rx2) = channel::<i32>();
+    let f = rx.flatten();
+    let f = f.poll().err().unwrap();
+    tx.send(rx2).unwrap();
+    let f = f.poll().err().unwrap();
+    tx2.send(6).unwrap();
+    assert_eq!(get(f), Ok(6));
+}
+
+#[test]
+fn flatten_result() {
+    let f = ok::<Result<i32, OuterOrInner>, MyErr>(Ok(5)).flatten_result();
+    assert_eq!(get(f), Ok(5));
+    let f = ok::<Result<i32, OuterOrInner>, MyErr>(Err(OuterOrInner::Inner)).flatten_result();
+    assert_eq!(get(f), Err(OuterOrInner::Inner));
+    let f = err::<Result<i32, OuterOrInner>, MyErr>(MyErr).flatten_result();
+    assert_eq!(get(f), Err(OuterOrInner::Outer(MyErr)));
+
+    let (tx, rx) = channel::<Result<i32, RecvError>>();
+    let f = rx.flatten_result();
+    let f = f.poll().err().unwrap();
+    tx.send(Ok(7)).unwrap();
+    assert_eq!(get(f), Ok(7));
+}
+
+#[test]
+fn fuse_stays_terminated() {
+    let (tx, rx) = channel::<i32>();
+    let mut f = rx.fuse();
+    assert_eq!(f.poll_fused(), None);
+    assert!(!f.is_terminated());
+    tx.send(1).unwrap();
+    assert_eq!(f.poll_fused(), Some(Ok(1)));
+    assert!(f.is_terminated());
+    // The receiver is gone, so another poll of it would fail.
+    drop(tx);
+    assert_eq!(f.poll_fused(), None);
+
+    let done = Fuse::<FutureResult<i32, u32>>::terminated();
+    assert!(done.is_terminated());
+    let done = done.poll().err().unwrap();
+    assert!(done.poll().is_err());
+
+    // A terminated child never wins a select or finishes a join.
+    let f = Fuse::<FutureResult<i32, u32>>::terminated().select(ok(2));
+    assert_eq!(get(f), Ok(2));
+    let f = Fuse::<FutureResult<i32, u32>>::terminated().join(ok(3));
+    assert!(f.poll().is_err());
+
+    // A joined future which has finished isn't polled again.
+    let polls = Rc::new(Cell::new(0));
+    let counted = polls.clone();
+    let (tx, rx) = channel::<i32>();
+    let f = ok::<i32, RecvError>(1)
+        .on_poll(move |_| counted.set(counted.get() + 1))
+        .join(rx);
+    let f = f.poll().err().unwrap();
+    let f = f.poll().err().unwrap();
+    tx.send(2).unwrap();
+    assert_eq!(get(f), Ok((1, 2)));
+    assert_eq!(polls.get(), 1);
+}
+
+#[test]
+fn join_all_keeps_order() {
+    let f = join_all(vec![Ok::<i32, i32>(1), Ok(2), Ok(3)]);
+    assert_eq!(get(f), Ok(vec![1, 2, 3]));
+
+    let (tx, rx) = channel::<i32>();
+    let (tx2, rx2) = channel::<i32>();
+    let (tx3, rx3) = channel::<i32>();
+    let f = join_all(vec![rx, rx2, rx3]);
+    tx3.send(3).unwrap();
+    let f = f.poll().err().unwrap();
+    tx2.send(2).unwrap();
+    let f = f.poll().err().unwrap();
+    tx.send(1).unwrap();
+    assert_eq!(get(f), Ok(vec![1, 2, 3]));
+
+    let f = join_all(vec![Ok::<i32, i32>(1), Err(2), Err(3)]);
+    assert_eq!(get(f), Err(2));
+}
+
+#[test]
+fn either_picks_branch() {
+    let branch = |cond| {
+        either(
+            cond,
+            Ok::<i32, u32>(1).into_future(),
+            Ok(2).into_future().map(|x| x),
+        )
+    };
+    assert_eq!(get(branch(true)), Ok(1));
+    assert_eq!(get(branch(false)), Ok(2));
+
+    let (tx, rx) = channel::<i32>();
+    let f = either(false, Empty::new(), rx);
+    let f = f.poll().err().unwrap();
+    tx.send(3).unwrap();
+    assert_eq!(get(f), Ok(3));
+}
+
+#[test]
+fn select_all_hands_back_rest() {
+    let (tx, rx) = channel::<i32>();
+    let (tx2, rx2) = channel::<i32>();
+    let (tx3, rx3) = channel::<i32>();
+    let f = select_all(vec![rx, rx2, rx3]);
+    let f = f.poll().err().unwrap();
+    tx2.send(2).unwrap();
+    let (i, item, rest) = get(f).unwrap();
+    assert_eq!((i, item, rest.len()), (1, 2, 2));
+
+    // Indices are relative to the vector handed back.
+    drop(tx);
+    tx3.send(3).unwrap();
+    let (i, err, rest) = get(select_all(rest)).unwrap_err();
+    assert_eq!((i, err, rest.len()), (0, RecvError, 1));
+    let (i, item, rest) = get(select_all(rest)).unwrap();
+    assert_eq!((i, item, rest.len()), (0, 3, 0));
+}
+
+#[test]
+fn select_all_over_boxed_replicas() {
+    // Replicas of different types answering the same query.
+    let (slow_tx, slow) = channel::<&str>();
+    let replicas: Vec<BoxFuture<&str, RecvError>> =
+        vec![slow.boxed(), Empty::new().boxed(), ok("eu").hold(2).boxed()];
+    let first = select_all(replicas);
+    let first = first.poll().err().unwrap();
+    let first = first.poll().err().unwrap();
+    let (i, answer, rest) = get(first).ok().unwrap();
+    assert_eq!((i, answer), (2, "eu"));
+
+    // The losers can still be driven for a second opinion.
+    slow_tx.send("us").unwrap();
+    let (i, answer, rest) = get(select_all(rest)).ok().unwrap();
+    assert_eq!((i, answer, rest.len()), (0, "us", 1));
+}
+
+#[test]
+#[should_panic(expected = "select_all needs at least one future")]
+fn select_all_empty_panics() {
+    select_all(Vec::<Empty<i32, i32>>::new());
+}
+
+#[test]
+fn trace_into_logs_transitions() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let (tx, rx) = channel::<i32>();
+    let f = rx.trace_into(log.clone());
+    let f = f.poll().err().unwrap();
+    tx.send(1).unwrap();
+    assert_eq!(get(f), Ok(1));
+    assert_eq!(*log.borrow(), vec!["poll", "poll", "ready_ok"]);
+
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let f = Err::<i32, i32>(1).into_future().trace_into(log.clone());
+    assert_eq!(get(f), Err(1));
+    assert_eq!(*log.borrow(), vec!["poll", "ready_err"]);
+}
+
+#[test]
+fn constructors() {
+    assert_eq!(get(ok::<i32, u32>(1)), Ok(1));
+    assert_eq!(get(err::<i32, u32>(2)), Err(2));
+    assert!(empty::<i32, u32>().poll().is_err());
+}
+
+#[test]
+fn lazy_defers_until_polled() {
+    let calls = Cell::new(0);
+    let (tx, rx) = channel::<i32>();
+    let f = lazy(|| {
+        calls.set(calls.get() + 1);
+        rx
+    });
+    assert_eq!(calls.get(), 0);
+    let f = f.poll().err().unwrap();
+    assert_eq!(calls.get(), 1);
+    tx.send(3).unwrap();
+    assert_eq!(get(f), Ok(3));
+    assert_eq!(calls.get(), 1);
+
+    let f = lazy(|| finished::<u32, u32>(1)).and_then(|n| failed::<i32, u32>(n + 1));
+    assert_eq!(get(f), Err(2));
+}
+
+#[test]
+fn compose_n_repeats() {
+    assert_eq!(get(compose_n(ok::<i32, u32>(0), 3, |x| ok(x + 1))), Ok(3));
+    assert_eq!(get(compose_n(ok::<i32, u32>(5), 0, |x| ok(x + 1))), Ok(5));
+
+    let f = compose_n(
+        ok::<i32, i32>(0),
+        3,
+        |x| if x < 1 { ok(x + 1) } else { err(x) },
+    );
+    assert_eq!(get(f), Err(1));
+}
+
+#[derive(Debug, PartialEq)]
+enum ParseError {
+    Empty,
+}
+
+#[derive(Debug, PartialEq)]
+enum NetError {
+    Down,
+}
+
+#[derive(Debug, PartialEq)]
+enum AppError {
+    Parse(ParseError),
+    Net(NetError),
+}
+
+impl From<ParseError> for AppError {
+    fn from(e: ParseError) -> AppError {
+        AppError::Parse(e)
+    }
+}
+
+impl From<NetError> for AppError {
+    fn from(e: NetError) -> AppError {
+        AppError::Net(e)
+    }
+}
+
+#[test]
+fn from_err_unifies_errors() {
+    let f = ok::<i32, ParseError>(1)
+        .from_err::<AppError>()
+        .join(ok::<i32, NetError>(2).from_err());
+    assert_eq!(get(f), Ok((1, 2)));
+
+    let f = ok::<i32, ParseError>(1)
+        .from_err::<AppError>()
+        .join(err::<i32, NetError>(NetError::Down).from_err());
+    assert_eq!(get(f), Err(AppError::Net(NetError::Down)));
+
+    let f = err::<i32, ParseError>(ParseError::Empty).from_err::<AppError>();
+    assert_eq!(get(f), Err(AppError::Parse(ParseError::Empty)));
+}
+
+#[test]
+fn inspect_fires_once_on_success() {
+    let seen = Cell::new(None);
+    let calls = Cell::new(0);
+    let (tx, rx) = channel::<i32>();
+    let f = rx.inspect(|x| {
+        seen.set(Some(*x));
+        calls.set(calls.get() + 1);
+    });
+    let f = f.poll().err().unwrap();
+    assert_eq!(calls.get(), 0);
+    tx.send(4).unwrap();
+    assert_eq!(get(f), Ok(4));
+    assert_eq!((seen.get(), calls.get()), (Some(4), 1));
+
+    let f = err::<i32, i32>(1).inspect(|_| calls.set(calls.get() + 1));
+    assert_eq!(get(f), Err(1));
+    assert_eq!(calls.get(), 1);
+}
+
+#[test]
+fn until_flag_cancels() {
+    let flag = Rc::new(cell::AtomicCell::new(false));
+    let f = empty::<i32, Canceled>().until_flag(flag.clone());
+    let f = f.poll().err().unwrap();
+    *flag.try_borrow().unwrap() = true;
+    assert_eq!(get(f), Err(Canceled));
+}
+
+#[test]
+fn retry_until_success() {
+    let calls = Cell::new(0);
+    let f = retry(3, || {
+        calls.set(calls.get() + 1);
+        if calls.get() < 3 {
+            err::<i32, i32>(calls.get())
+        } else {
+            ok(calls.get())
+        }
+    });
+    assert_eq!(calls.get(), 0);
+    assert_eq!(get(f), Ok(3));
+    assert_eq!(calls.get(), 3);
+}
+
+#[test]
+fn retry_gives_up() {
+    let calls = Cell::new(0);
+    let f = retry(4, || {
+        calls.set(calls.get() + 1);
+        err::<i32, i32>(calls.get())
+    });
+    assert_eq!(get(f), Err(4));
+    assert_eq!(calls.get(), 4);
+}
+
+#[test]
+fn retry_jitter_delays() {
+    let delays: Vec<usize> = jitter_delays(3, 12345).take(4).collect();
+    assert_eq!(delays, vec![3, 6, 5, 4]);
+
+    // Each attempt is built exactly that many polls after the last one.
+    let polls = Cell::new(0);
+    let built = RefCell::new(Vec::new());
+    let mut f = retry_jitter(
+        || {
+            built.borrow_mut().push(polls.get());
+            err::<i32, usize>(built.borrow().len())
+        },
+        3,
+        5,
+        12345,
+    );
+    let result = loop {
+        polls.set(polls.get() + 1);
+        f = match f.poll() {
+            Ok(result) => break result,
+            Err(f) => f,
+        };
+    };
+    assert_eq!(result, Err(5));
+    assert_eq!(*built.borrow(), vec![1, 4, 10, 15, 19]);
+}
+
+#[test]
+fn retry_jitter_recovers() {
+    let calls = Cell::new(0);
+    let f = retry_jitter(
+        || {
+            calls.set(calls.get() + 1);
+            if calls.get() < 2 {
+                err::<i32, i32>(calls.get())
+            } else {
+                ok(calls.get())
+            }
+        },
+        2,
+        3,
+        7,
+    );
+    assert_eq!(f.wait(), Ok(2));
+    assert_eq!(calls.get(), 2);
+}
+
+#[test]
+fn join3_to_join5_flatten() {
+    let f_ok: FutureResult<i32, i32> = ok(1);
+    assert_eq!(get(f_ok.join3(Ok(2), Ok(3))), Ok((1, 2, 3)));
+    assert_eq!(get(f_ok.join4(Ok(2), Err::<i32, i32>(3), Ok(4))), Err(3));
+    assert_eq!(
+        get(f_ok.join5(Ok("two"), Ok(3), Ok(4), Ok(5))),
+        Ok((1, "two", 3, 4, 5))
+    );
+
+    let (tx, rx) = channel::<i32>();
+    let (tx2, rx2) = channel::<i32>();
+    let f = rx.join4(Ok(2), rx2, Ok(4));
+    tx2.send(3).unwrap();
+    let f = f.poll().err().unwrap();
+    tx.send(1).unwrap();
+    assert_eq!(get(f), Ok((1, 2, 3, 4)));
+}
+
+#[test]
+fn join_both_results_keeps_both() {
+    let (tx, rx) = channel::<i32>();
+    let f = err::<&str, u32>(7).join_both_results(rx);
+    let f = f.poll().err().unwrap();
+    tx.send(1).unwrap();
+    let (a, b) = get(f).unwrap();
+    assert_eq!(a, Err(7));
+    assert_eq!(b, Ok(1));
+}
+
+#[test]
+fn select_then_keeps_loser() {
+    let (primary_tx, primary) = channel::<i32>();
+    let (backup_tx, backup) = channel::<i32>();
+    let f = primary.select_then(backup);
+    let f = f.poll().err().unwrap();
+    primary_tx.send(1).unwrap();
+    let (item, next) = get(f).ok().unwrap();
+    assert_eq!(item, 1);
+    backup_tx.send(2).unwrap();
+    assert_eq!(next.wait(), Ok(2));
+
+    // A failed winner still hands back the survivor.
+    let (tx, rx) = channel::<i32>();
+    let (e, next) = get(err(RecvError).select_then(rx)).err().unwrap();
+    assert_eq!(e, RecvError);
+    let next = next.poll().err().unwrap();
+    tx.send(3).unwrap();
+    assert_eq!(next.wait(), Ok(3));
+}
+
+#[test]
+fn step_round_trips() {
+    let done: Step<i32, u32, ()> = Ok(Ok(1)).into();
+    assert_eq!(done, Step::Done(Ok(1)));
+    let poll: Result<Result<i32, u32>, ()> = done.into();
+    assert_eq!(poll, Ok(Ok(1)));
+
+    let yielded: Step<i32, u32, ()> = Err(()).into();
+    assert_eq!(yielded, Step::Yield(()));
+    let poll: Result<Result<i32, u32>, ()> = yielded.into();
+    assert_eq!(poll, Err(()));
+}
+
+#[test]
+fn step_drives_chain() {
+    let (tx, rx) = channel::<i32>();
+    let (tx2, rx2) = channel::<i32>();
+    let mut f = rx.and_then(|x| rx2.map(move |y| x + y));
+    let mut yields = 0;
+    let result = loop {
+        f = match f.step() {
+            Step::Done(result) => break result,
+            Step::Yield(f) => f,
+        };
+        yields += 1;
+        match yields {
+            1 => tx.send(1).unwrap(),
+            3 => tx2.send(2).unwrap(),
+            _ => {}
+        }
+    };
+    assert_eq!(result, Ok(3));
+    assert_eq!(yields, 3);
+}
+
+#[test]
+fn and_then_retry_reruns_second_stage() {
+    let first_runs = Cell::new(0);
+    let attempts = Cell::new(0);
+    let first = lazy(|| {
+        first_runs.set(first_runs.get() + 1);
+        ok::<i32, i32>(10)
+    });
+    let f = first.and_then_retry(2, |x| {
+        attempts.set(attempts.get() + 1);
+        if attempts.get() < 3 {
+            err(attempts.get())
+        } else {
+            ok(x + attempts.get())
+        }
+    });
+    assert_eq!(get(f), Ok(13));
+    assert_eq!((first_runs.get(), attempts.get()), (1, 3));
+
+    let f = ok::<i32, i32>(1).and_then_retry(1, |_| err::<i32, i32>(7));
+    assert_eq!(get(f), Err(7));
+}
+
+#[test]
+fn zip_with_dependent_returns_both() {
+    let (tx, rx) = channel::<String>();
+    let f = ok::<&str, RecvError>("user-1").zip_with_dependent(|id| {
+        tx.send(format!("profile of {}", id)).unwrap();
+        rx
+    });
+    assert_eq!(get(f), Ok(("user-1", "profile of user-1".to_string())));
+}
+
+#[test]
+fn fallback_chain_tries_each_source() {
+    let tried = RefCell::new(Vec::new());
+    let source = |name: &'static str, result: Result<i32, &'static str>| {
+        let tried = &tried;
+        move || {
+            tried.borrow_mut().push(name);
+            result
+        }
+    };
+    let factories: Vec<Box<dyn FnOnce() -> Result<i32, &'static str>>> = vec![
+        Box::new(source("primary", Err("primary down"))),
+        Box::new(source("mirror", Err("mirror down"))),
+        Box::new(source("cache", Ok(3))),
+        Box::new(source("unused", Ok(4))),
+    ];
+    assert_eq!(get(fallback_chain(factories)), Ok(3));
+    assert_eq!(*tried.borrow(), vec!["primary", "mirror", "cache"]);
+
+    let all_fail: Vec<fn() -> FutureResult<i32, i32>> = vec![|| err(1), || err(2)];
+    assert_eq!(get(fallback_chain(all_fail)), Err(2));
+}
+
+#[test]
+fn send_result_bridges_to_channel() {
+    let (tx, rx) = channel();
+    assert_eq!(get(ok::<i32, i32>(1).send_result(tx)), Ok(()));
+    assert_eq!(rx.wait(), Ok(Ok(1)));
+
+    let (tx, rx) = channel();
+    assert_eq!(get(err::<i32, i32>(2).send_result(tx)), Ok(()));
+    assert_eq!(rx.wait(), Ok(Err(2)));
+
+    // Nobody listening is fine too.
+    let (tx, rx) = channel();
+    drop(rx);
+    assert_eq!(get(ok::<i32, i32>(3).send_result(tx)), Ok(()));
+}
+
+#[test]
+fn spawn_detached_runs_to_completion() {
+    let hits = Arc::new(AtomicUsize::new(0));
+    let (tx, rx) = channel::<usize>();
+    let seen = hits.clone();
+    spawn_detached(rx.map(move |n| {
+        seen.fetch_add(n, Ordering::SeqCst);
+    }));
+    tx.send(2).unwrap();
+    while hits.load(Ordering::SeqCst) == 0 {
+        thread::yield_now();
+    }
+    assert_eq!(hits.load(Ordering::SeqCst), 2);
+
+    // A panicking future doesn't affect anything else.
+    spawn_detached(lazy(|| -> Result<(), ()> { panic!("detached") }));
+    spawn_detached(ok::<(), ()>(()).map({
+        let hits = hits.clone();
+        move |_| {
+            hits.fetch_add(1, Ordering::SeqCst);
+        }
+    }));
+    while hits.load(Ordering::SeqCst) == 2 {
+        thread::yield_now();
+    }
+    assert_eq!(hits.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn wait_parks_instead_of_spinning() {
+    let (tx, rx) = oneshot::<i32>();
+    let sender = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        tx.complete(1);
+    });
+    let polls = Cell::new(0);
+    assert_eq!(rx.on_poll(|n| polls.set(n)).wait(), Ok(1));
+    sender.join().unwrap();
+    assert!(polls.get() < 100, "polled {} times", polls.get());
+
+    // Sources which can't unpark the task are still picked up.
+    let (tx, rx) = channel::<i32>();
+    let sender = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        tx.send(2).unwrap();
+    });
+    let polls = Cell::new(0);
+    assert_eq!(rx.on_poll(|n| polls.set(n)).wait(), Ok(2));
+    sender.join().unwrap();
+    assert!(polls.get() < 100, "polled {} times", polls.get());
+}
+
+#[test]
+fn boxed_futures_can_be_polled() {
+    let (tx, rx) = channel::<i32>();
+    let futures: Vec<BoxFuture<i32, RecvError>> = vec![
+        ok(1).boxed(),
+        rx.boxed(),
+        ok(2).and_then(|x| Ok(x * 10)).boxed(),
+    ];
+    let all = join_all(futures);
+    let all = all.poll().err().unwrap();
+    tx.send(5).unwrap();
+    assert_eq!(get(all), Ok(vec![1, 5, 20]));
+
+    let f: BoxFuture<&str, ()> = Empty::new().select(ok("boxed")).boxed();
+    assert_eq!(f.map(str::len).wait(), Ok(5));
+}
+
+#[test]
+fn loop_fn_iterates_without_nesting() {
+    let f = loop_fn(0, |n| {
+        if n == 5 {
+            Ok::<_, u32>(Loop::Break(n * 10))
+        } else {
+            Ok(Loop::Continue(n + 1))
+        }
+    });
+    assert_eq!(get(f), Ok(50));
+
+    // Each step waits on one more channel.
+    let (tx, rx) = channel::<i32>();
+    let (tx2, rx2) = channel::<i32>();
+    let f = loop_fn((vec![rx, rx2], 0), |(mut rxs, sum)| match rxs.pop() {
+        Some(rx) => Either::Left(rx.map(move |n| Loop::Continue((rxs, sum + n)))),
+        None => Either::Right(ok(Loop::Break(sum))),
+    });
+    tx.send(1).unwrap();
+    let f = f.poll().err().unwrap();
+    tx2.send(2).unwrap();
+    assert_eq!(get(f), Ok(3));
+}
+
+#[test]
+fn catch_unwind_turns_panics_into_values() {
+    let f = ok::<i32, u32>(1).catch_unwind();
+    assert_eq!(get(f).ok().map(|r| r.ok()), Some(Some(Ok(1))));
+
+    let f = ok::<i32, u32>(1)
+        .map(|_| -> i32 { panic!("boom") })
+        .catch_unwind();
+    let payload = get(f).ok().unwrap().unwrap_err();
+    assert_eq!(payload.downcast_ref::<&str>(), Some(&"boom"));
+
+    let (tx, rx) = channel::<i32>();
+    let f = rx.map(|n| n + 1).catch_unwind();
+    let f = f.poll().err().unwrap();
+    tx.send(1).unwrap();
+    assert_eq!(get(f).ok().map(|r| r.ok()), Some(Some(Ok(2))));
+}
+
+#[test]
+fn cancellable_fails_after_cancel() {
+    let (tx, rx) = channel::<i32>();
+    let (f, handle) = rx.map_err(|_| Canceled).cancellable();
+    let task = task::Task::new();
+    let f = task.enter(|| f.poll()).err().unwrap();
+    let canceling = handle.clone();
+    thread::spawn(move || canceling.cancel()).join().unwrap();
+    assert!(task.take_unpark());
+    tx.send(1).unwrap();
+    assert_eq!(get(f), Err(Canceled));
+    assert!(handle.is_canceled());
+
+    let (f, handle) = ok::<i32, Canceled>(2).cancellable();
+    assert_eq!(get(f), Ok(2));
+    assert!(!handle.is_canceled());
+}
+
+#[derive(Clone, Default)]
+struct Events(Rc<RefCell<Vec<PollEvent>>>);
+
+impl TraceSink for Events {
+    fn record(&self, event: &PollEvent) {
+        self.0.borrow_mut().push(*event);
+    }
+}
+
+#[test]
+fn trace_reports_each_poll_and_state() {
+    let events = Events::default();
+    let (tx, rx) = channel::<i32>();
+    let (tx2, rx2) = channel::<i32>();
+    let f = rx
+        .and_then(move |_| rx2)
+        .trace_with("chain", events.clone());
+    let f = f.poll().err().unwrap();
+    tx.send(1).unwrap();
+    let f = f.poll().err().unwrap();
+    tx2.send(2).unwrap();
+    assert_eq!(get(f), Ok(2));
+
+    let seen: Vec<_> = events
+        .0
+        .borrow()
+        .iter()
+        .map(|e| (e.label, e.poll, e.state, e.outcome))
+        .collect();
+    assert_eq!(
+        seen,
+        vec![
+            ("chain", 1, "AndThen::First", TraceOutcome::Pending),
+            ("chain", 2, "AndThen::First", TraceOutcome::Pending),
+            ("chain", 3, "AndThen::Second", TraceOutcome::Ok),
+        ]
+    );
+    let events = events.0.borrow();
+    assert!(events[0].elapsed <= events[2].elapsed);
+}
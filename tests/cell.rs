@@ -0,0 +1,48 @@
+extern crate futures;
+
+use std::sync::Arc;
+use std::thread;
+
+use futures::cell::AtomicCell;
+
+#[test]
+fn borrows_are_exclusive() {
+    let cell = AtomicCell::new(1);
+    let guard = cell.try_borrow().unwrap();
+    assert!(cell.try_borrow().is_none());
+    drop(guard);
+    assert!(cell.try_borrow().is_some());
+}
+
+#[test]
+fn mutation_is_visible_to_next_borrow() {
+    let cell = AtomicCell::new(vec![1]);
+    cell.borrow().push(2);
+    assert_eq!(*cell.try_borrow().unwrap(), vec![1, 2]);
+}
+
+#[test]
+fn owned_access_skips_the_flag() {
+    let mut cell = AtomicCell::new(1);
+    *cell.get_mut() += 1;
+    assert_eq!(cell.into_inner(), 2);
+}
+
+#[test]
+fn concurrent_increments() {
+    let cell = Arc::new(AtomicCell::new(0));
+    let threads: Vec<_> = (0..8)
+        .map(|_| {
+            let cell = cell.clone();
+            thread::spawn(move || {
+                for _ in 0..1000 {
+                    *cell.borrow() += 1;
+                }
+            })
+        })
+        .collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+    assert_eq!(*cell.borrow(), 8000);
+}
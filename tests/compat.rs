@@ -0,0 +1,121 @@
+extern crate futures;
+
+use std::future::Future as StdFuture;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+use futures::compat::FromStd;
+use futures::task::Task;
+use futures::*;
+
+// Unparks the executor's thread, counting each wake-up.
+struct ThreadWaker {
+    thread: Thread,
+    wakes: AtomicUsize,
+}
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wakes.fetch_add(1, Ordering::SeqCst);
+        self.thread.unpark();
+    }
+}
+
+// A minimal executor which parks until the future asks to be polled again,
+// returning the output along with how many polls it took.
+fn block_on<F: StdFuture + Unpin>(mut f: F) -> (F::Output, usize, Arc<ThreadWaker>) {
+    let state = Arc::new(ThreadWaker {
+        thread: thread::current(),
+        wakes: AtomicUsize::new(0),
+    });
+    let waker = Waker::from(state.clone());
+    let mut cx = Context::from_waker(&waker);
+    let mut polls = 0;
+    loop {
+        polls += 1;
+        if let Poll::Ready(output) = Pin::new(&mut f).poll(&mut cx) {
+            return (output, polls, state);
+        }
+        thread::park();
+    }
+}
+
+// Polls `f` once from the test thread with a waker counting into `state`.
+fn poll_once<F: StdFuture + Unpin>(f: &mut F, state: &Arc<ThreadWaker>) -> Poll<F::Output> {
+    let waker = Waker::from(state.clone());
+    Pin::new(f).poll(&mut Context::from_waker(&waker))
+}
+
+fn counting_waker() -> Arc<ThreadWaker> {
+    Arc::new(ThreadWaker {
+        thread: thread::current(),
+        wakes: AtomicUsize::new(0),
+    })
+}
+
+#[test]
+fn wakes_only_on_progress() {
+    let (tx, rx) = channel::<i32>();
+    let mut f = rx.map(|x| x * 2).to_std_smart();
+    let progress = f.progress_waker();
+    let state = counting_waker();
+    assert!(poll_once(&mut f, &state).is_pending());
+    // Nothing asks to be polled again until there's progress.
+    assert_eq!(state.wakes.load(Ordering::SeqCst), 0);
+    tx.send(21).unwrap();
+    progress.notify();
+    assert_eq!(state.wakes.load(Ordering::SeqCst), 1);
+    assert_eq!(poll_once(&mut f, &state), Poll::Ready(Ok(42)));
+    assert_eq!(state.wakes.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn ready_without_waking() {
+    let (output, polls, state) = block_on(ok::<i32, i32>(1).to_std_smart());
+    assert_eq!(output, Ok(1));
+    assert_eq!(polls, 1);
+    assert_eq!(state.wakes.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn into_std_woken_by_parked_source() {
+    let (tx, rx) = oneshot::<i32>();
+    let mut f = rx.into_std();
+    let state = counting_waker();
+    assert!(poll_once(&mut f, &state).is_pending());
+    assert_eq!(state.wakes.load(Ordering::SeqCst), 0);
+    tx.complete(7);
+    assert_eq!(state.wakes.load(Ordering::SeqCst), 1);
+    assert_eq!(poll_once(&mut f, &state), Poll::Ready(Ok(7)));
+}
+
+#[test]
+fn from_std_wakes_polling_task() {
+    let mut first = true;
+    let f = FromStd::new(std::future::poll_fn(move |cx| {
+        if first {
+            first = false;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        } else {
+            Poll::Ready(Ok::<i32, ()>(5))
+        }
+    }));
+    let task = Task::new();
+    let f = task.enter(|| f.poll()).err().unwrap();
+    assert!(task.take_unpark());
+    assert_eq!(f.poll().ok(), Some(Ok(5)));
+
+    assert_eq!(
+        FromStd::new(async { Err::<(), &str>("no") }).wait(),
+        Err("no")
+    );
+}
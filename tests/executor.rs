@@ -0,0 +1,154 @@
+extern crate futures;
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures::executor::{CpuPool, LocalRunner};
+use futures::timer::Delay;
+use futures::*;
+
+#[test]
+fn runs_chains_in_the_background() {
+    let pool = CpuPool::new(2);
+    let a = pool.spawn(ok::<i32, ()>(1).map(|x| x + 1).and_then(|x| Ok(x * 10)));
+    let b = pool.spawn(err::<i32, &str>("nope").join(ok(2)));
+    assert_eq!(a.wait(), Ok(20));
+    assert_eq!(b.wait(), Err("nope"));
+}
+
+#[test]
+fn work_runs_on_pool_threads() {
+    let pool = CpuPool::new(1);
+    let here = thread::current().id();
+    let there = pool.spawn(lazy(|| Ok::<_, ()>(thread::current().id())));
+    assert_ne!(there.wait().unwrap(), here);
+}
+
+#[test]
+fn dropping_the_handle_cancels() {
+    let pool = CpuPool::new(1);
+    let polls = Arc::new(AtomicUsize::new(0));
+    let counted = polls.clone();
+    let (_tx, rx) = channel::<()>();
+    let stuck = pool.spawn(rx.on_poll(move |_| {
+        counted.fetch_add(1, Ordering::SeqCst);
+    }));
+    while polls.load(Ordering::SeqCst) == 0 {
+        thread::yield_now();
+    }
+    drop(stuck);
+
+    // The only thread is free again once the stuck future is abandoned.
+    assert_eq!(pool.spawn(ok::<i32, ()>(3)).wait(), Ok(3));
+}
+
+#[test]
+fn pending_futures_give_up_their_thread() {
+    let pool = CpuPool::new(1);
+    let (tx, rx) = oneshot::<i32>();
+    // With one thread, `waiting` has to step aside for `sending` to run.
+    let waiting = pool.spawn(rx.map(|n| n + 1));
+    let sending = pool.spawn(lazy(move || {
+        tx.complete(1);
+        Ok::<(), Canceled>(())
+    }));
+    assert_eq!(sending.wait(), Ok(()));
+    assert_eq!(waiting.wait(), Ok(2));
+}
+
+#[test]
+#[should_panic(expected = "spawned boom")]
+fn panics_resume_in_the_waiter() {
+    let pool = CpuPool::new(1);
+    let _ = pool
+        .spawn(lazy(|| -> Result<(), ()> { panic!("spawned boom") }))
+        .wait();
+}
+
+#[test]
+fn local_runner_interleaves_spawned_chains() {
+    let runner = LocalRunner::new();
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let (tx, rx) = oneshot::<i32>();
+    let (tx2, rx2) = oneshot::<i32>();
+
+    let first = log.clone();
+    runner.spawn_local(rx.map(move |n| first.borrow_mut().push(n)));
+    let second = log.clone();
+    runner.spawn_local(lazy(move || {
+        second.borrow_mut().push(1);
+        tx2.complete(2);
+        Ok::<(), ()>(())
+    }));
+    let done = log.clone();
+    let main = rx2.map(move |n| {
+        tx.complete(n + 1);
+        done.borrow().len()
+    });
+    assert_eq!(runner.run(main), Ok(1));
+    // The first chain only became ready as `main` finished, so it's left
+    // for the next run to pick up.
+    assert_eq!(*log.borrow(), vec![1]);
+    assert_eq!(runner.run(Delay::new(Duration::from_millis(1))), Ok(()));
+    assert_eq!(*log.borrow(), vec![1, 3]);
+}
+
+#[test]
+fn local_runner_parks_for_timers() {
+    let runner = LocalRunner::new();
+    let fired = Rc::new(Cell::new(false));
+    let flag = fired.clone();
+    runner.spawn_local(Delay::new(Duration::from_millis(10)).map(move |()| flag.set(true)));
+    let start = Instant::now();
+    assert_eq!(runner.run(Delay::new(Duration::from_millis(20))), Ok(()));
+    assert!(start.elapsed() >= Duration::from_millis(20));
+    assert!(fired.get());
+}
+
+#[test]
+fn local_runner_spawns_from_running_futures() {
+    let runner = LocalRunner::new();
+    let count = Rc::new(Cell::new(0));
+    let (tx, rx) = oneshot::<()>();
+    let spawner = runner.clone();
+    let counter = count.clone();
+    runner.spawn_local(lazy(move || {
+        for _ in 0..3 {
+            let counter = counter.clone();
+            spawner.spawn_local(lazy(move || {
+                counter.set(counter.get() + 1);
+                Ok::<(), ()>(())
+            }));
+        }
+        spawner.spawn_local(lazy(move || {
+            tx.complete(());
+            Ok::<(), ()>(())
+        }));
+        Ok::<(), ()>(())
+    }));
+    assert_eq!(runner.run(rx), Ok(()));
+    assert_eq!(count.get(), 3);
+}
+
+#[test]
+fn local_runner_sees_to_sources_that_cannot_unpark() {
+    let runner = LocalRunner::new();
+    let (tx, rx) = channel::<i32>();
+    let (done, seen) = oneshot::<i32>();
+    runner.spawn_local(rx.map(move |n| done.complete(n)));
+    let sender = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(5));
+        tx.send(4).unwrap();
+    });
+    assert_eq!(runner.run(seen), Ok(4));
+    sender.join().unwrap();
+
+    let (done, seen) = oneshot::<i32>();
+    runner.spawn_local(ok::<i32, ()>(1).hold(2).map(move |n| done.complete(n)));
+    assert_eq!(runner.run(seen), Ok(1));
+}
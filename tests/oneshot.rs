@@ -0,0 +1,99 @@
+extern crate futures;
+
+use std::thread;
+
+use futures::oneshot::{oneshot, oneshot_slot, Canceled};
+use futures::task::Task;
+use futures::*;
+
+#[test]
+fn send_before_poll() {
+    let (tx, rx) = oneshot();
+    tx.complete(1);
+    assert_eq!(rx.poll().ok(), Some(Ok(1)));
+}
+
+#[test]
+fn poll_before_send() {
+    let (tx, rx) = oneshot();
+    let rx = rx.poll().err().unwrap();
+    let rx = rx.poll().err().unwrap();
+    tx.complete(2);
+    assert_eq!(rx.poll().ok(), Some(Ok(2)));
+
+    let (tx, rx) = oneshot();
+    let sender = thread::spawn(move || tx.complete("hello".to_string()));
+    assert_eq!(rx.wait(), Ok("hello".to_string()));
+    sender.join().unwrap();
+}
+
+#[test]
+fn dropped_sender_cancels() {
+    let (tx, rx) = oneshot::<i32>();
+    let rx = rx.poll().err().unwrap();
+    drop(tx);
+    assert_eq!(rx.poll().ok(), Some(Err(Canceled)));
+}
+
+#[test]
+fn dropped_receiver_is_seen_as_cancel() {
+    let (tx, rx) = oneshot::<i32>();
+    assert!(!tx.is_canceled());
+    drop(rx.poll().err().unwrap());
+    assert!(tx.is_canceled());
+}
+
+#[derive(Debug, PartialEq)]
+enum SlotError {
+    Failed(&'static str),
+    Canceled,
+}
+
+impl From<Canceled> for SlotError {
+    fn from(_: Canceled) -> SlotError {
+        SlotError::Canceled
+    }
+}
+
+#[test]
+fn slot_across_threads() {
+    let (writer, reader) = oneshot_slot::<i32, SlotError>();
+    let reading = thread::spawn(move || reader.wait());
+    let writing = thread::spawn(move || {
+        let result = ok::<i32, SlotError>(20).map(|x| x + 1).wait();
+        writer.set(result);
+    });
+    writing.join().unwrap();
+    assert_eq!(reading.join().unwrap(), Ok(21));
+
+    let (writer, reader) = oneshot_slot::<i32, SlotError>();
+    let reader = reader.poll().err().unwrap();
+    writer.set(Err(SlotError::Failed("nope")));
+    assert_eq!(reader.poll().ok(), Some(Err(SlotError::Failed("nope"))));
+}
+
+#[test]
+fn slot_fails_once_writer_is_gone() {
+    let (writer, reader) = oneshot_slot::<i32, SlotError>();
+    let task = Task::new();
+    let reader = task.enter(|| reader.poll()).err().unwrap();
+    drop(writer);
+    assert!(task.take_unpark());
+    assert_eq!(reader.poll().ok(), Some(Err(SlotError::Canceled)));
+}
+
+// Stands in for an API which reports its result through a callback.
+fn on_done<F: FnOnce(u32) + Send + 'static>(value: u32, callback: F) {
+    thread::spawn(move || callback(value));
+}
+
+#[test]
+fn bridges_callbacks() {
+    let (tx, rx): (Complete<u32>, Oneshot<u32>) = futures::oneshot();
+    on_done(4, move |v| tx.complete(v));
+    assert_eq!(rx.map(|v| v * 10).wait(), Ok(40));
+
+    let (tx, rx) = futures::oneshot::<u32>();
+    on_done(4, move |_| drop(tx));
+    assert_eq!(rx.wait(), Err(Canceled));
+}
@@ -0,0 +1,92 @@
+extern crate futures;
+
+use std::sync::mpsc::channel;
+use std::thread;
+
+use futures::sink::{PollComplete, StartSend};
+use futures::stream::{iter, Stream};
+use futures::*;
+
+// A sink which refuses a new item until the previous one has been flushed.
+#[derive(Default)]
+struct OneAtATime {
+    items: Vec<i32>,
+    held: Option<i32>,
+}
+
+impl Sink for OneAtATime {
+    type SinkItem = i32;
+    type SinkError = Never;
+
+    fn start_send(mut self, item: i32) -> StartSend<Self> {
+        if self.held.is_some() {
+            return Err((self, item));
+        }
+        self.held = Some(item);
+        Ok(Ok(self))
+    }
+
+    fn poll_complete(mut self) -> PollComplete<Self> {
+        if let Some(item) = self.held.take() {
+            self.items.push(item);
+        }
+        Ok(Ok(self))
+    }
+}
+
+fn finish<F: Future>(mut f: F) -> Result<F::Item, F::Error> {
+    loop {
+        f = match f.poll() {
+            Ok(result) => return result,
+            Err(f) => f,
+        };
+    }
+}
+
+#[test]
+fn send_all_to_a_vec() {
+    let sink = finish(Vec::new().send_all(iter::<_, Never>(vec![1, 2, 3])));
+    assert_eq!(sink.ok(), Some(vec![1, 2, 3]));
+}
+
+#[test]
+fn send_all_respects_backpressure() {
+    let sink = finish(OneAtATime::default().send_all(iter::<_, Never>(vec![1, 2, 3])));
+    assert_eq!(sink.ok().unwrap().items, vec![1, 2, 3]);
+}
+
+#[test]
+fn channel_pipeline() {
+    let (tx, rx) = channel::<String>();
+    let consumer = thread::spawn(move || {
+        stream::receiver(rx)
+            .into_blocking_iter()
+            .collect::<Vec<_>>()
+    });
+    let producer = tx
+        .with(|n: i32| format!("#{}", n))
+        .send_all(iter(vec![1, 2]));
+    assert!(finish(producer).is_ok());
+    assert_eq!(
+        consumer.join().unwrap(),
+        vec![Ok("#1".to_string()), Ok("#2".to_string())]
+    );
+
+    // A hung-up receiver fails the send.
+    let (tx, rx) = channel::<i32>();
+    drop(rx);
+    let failed = finish(tx.send_all(iter(vec![4])));
+    assert_eq!(failed.err().map(|e| e.0), Some(4));
+}
+
+#[test]
+fn with_holds_refused_items() {
+    let sink = OneAtATime::default().with(|x: i32| x * 10);
+    let sink = sink.start_send(1).ok().unwrap().ok().unwrap();
+    // The inner sink is full; `with` converts and holds the next item.
+    let sink = sink.start_send(2).ok().unwrap().ok().unwrap();
+    let sink = finish(sink.send_all(iter::<_, Never>(vec![3])))
+        .ok()
+        .unwrap();
+    assert_eq!(sink.get_ref().items, vec![10, 20, 30]);
+}
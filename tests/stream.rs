@@ -0,0 +1,1295 @@
+extern crate futures;
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::rc::Rc;
+use std::sync::mpsc::channel;
+use std::thread;
+
+use futures::cell::AtomicCell;
+use futures::sink::{PollComplete, StartSend};
+use futures::stream::{
+    futures_unordered, iter, join_progress, receiver, retry_observe, FuturesUnordered, JoinEvent,
+    Notification, OrderViolation, Progress, Stream, StreamPoll, TraceEvent,
+};
+use futures::*;
+
+// A stream which replays a fixed script: `Some(x)` yields `x` and `None` is a
+// poll on which nothing is ready yet. The stream ends once the script is done.
+struct Scripted<T, E = u32> {
+    steps: VecDeque<Option<T>>,
+    _error: PhantomData<E>,
+}
+
+fn scripted<T>(steps: Vec<Option<T>>) -> Scripted<T> {
+    scripted_as(steps)
+}
+
+// Like `scripted`, for a stream with any error type.
+fn scripted_as<T, E>(steps: Vec<Option<T>>) -> Scripted<T, E> {
+    Scripted {
+        steps: steps.into(),
+        _error: PhantomData,
+    }
+}
+
+impl<T, E> Stream for Scripted<T, E> {
+    type Item = T;
+    type Error = E;
+
+    fn poll(mut self) -> StreamPoll<Self> {
+        match self.steps.pop_front() {
+            Some(Some(item)) => Ok(Ok(Some((item, self)))),
+            Some(None) => Err(self),
+            None => Ok(Ok(None)),
+        }
+    }
+}
+
+// A future which resolves to `value` after `polls` pending polls, tracking
+// how many such futures are running in `gauge`.
+struct Countdown {
+    polls: usize,
+    value: i32,
+    gauge: Rc<Cell<usize>>,
+}
+
+impl Future for Countdown {
+    type Item = i32;
+    type Error = u32;
+
+    fn poll(mut self) -> Result<Result<i32, u32>, Self> {
+        if self.polls == 0 {
+            self.gauge.set(self.gauge.get() - 1);
+            return Ok(Ok(self.value));
+        }
+        self.polls -= 1;
+        Err(self)
+    }
+}
+
+// A sink holding at most one unflushed item, which needs two flushes before
+// it lands in `items`.
+#[derive(Default)]
+struct OneAtATime {
+    items: Vec<i32>,
+    held: Option<i32>,
+    stalled: bool,
+}
+
+impl Sink for OneAtATime {
+    type SinkItem = i32;
+    type SinkError = Never;
+
+    fn start_send(mut self, item: i32) -> StartSend<Self> {
+        if self.held.is_some() {
+            return Err((self, item));
+        }
+        self.held = Some(item);
+        Ok(Ok(self))
+    }
+
+    fn poll_complete(mut self) -> PollComplete<Self> {
+        match self.held.take() {
+            Some(item) if !self.stalled => {
+                self.stalled = true;
+                self.held = Some(item);
+                Err(self)
+            }
+            Some(item) => {
+                self.stalled = false;
+                self.items.push(item);
+                Ok(Ok(self))
+            }
+            None => Ok(Ok(self)),
+        }
+    }
+}
+
+// Polls `f` until it resolves.
+fn finish<F: Future>(mut f: F) -> Result<F::Item, F::Error> {
+    loop {
+        f = match f.poll() {
+            Ok(result) => return result,
+            Err(f) => f,
+        };
+    }
+}
+
+// Polls `s` until it ends or errors, collecting every item along the way.
+fn drain<S: Stream>(mut s: S) -> Result<Vec<S::Item>, S::Error> {
+    let mut items = Vec::new();
+    loop {
+        match s.poll() {
+            Ok(Ok(Some((item, next)))) => {
+                items.push(item);
+                s = next;
+            }
+            Ok(Ok(None)) => return Ok(items),
+            Ok(Err(e)) => return Err(e),
+            Err(next) => s = next,
+        }
+    }
+}
+
+#[test]
+fn map_filter_collect() {
+    let s = scripted(vec![Some(1), None, Some(2), Some(3), Some(4)]);
+    let evens = s.map(|x| x * 10).filter(|x| x % 20 == 0).collect();
+    assert_eq!(finish(evens), Ok(vec![20, 40]));
+
+    let failing = iter::<_, u32>(vec![Ok(1), Err(7)]).retry_each(0);
+    let s = failing.map_err(|e| format!("failed with {}", e)).collect();
+    assert_eq!(finish(s), Err("failed with 7".to_string()));
+}
+
+#[test]
+fn and_then_runs_futures_in_turn() {
+    let (tx, rx) = channel::<i32>();
+    let gauge = Rc::new(Cell::new(0));
+    let s = receiver(rx)
+        .map_err(|never| -> u32 { match never {} })
+        .and_then(|polls| {
+            gauge.set(gauge.get() + 1);
+            assert_eq!(gauge.get(), 1, "only one future at a time");
+            Countdown {
+                polls: polls as usize,
+                value: polls * 2,
+                gauge: gauge.clone(),
+            }
+        });
+    tx.send(2).unwrap();
+    tx.send(0).unwrap();
+    tx.send(1).unwrap();
+    drop(tx);
+    assert_eq!(drain(s), Ok(vec![4, 0, 2]));
+}
+
+#[test]
+fn fold_threads_the_accumulator() {
+    let s = scripted(vec![Some(1), None, Some(2), Some(3)]);
+    let sum = s.fold(10, |acc, x| Ok::<i32, u32>(acc + x).into_future().hold(1));
+    assert_eq!(finish(sum), Ok(16));
+
+    let s = iter::<_, u32>(vec![1, 2, 3]);
+    let f = s.fold(0, |acc, x| if x == 2 { Err(9) } else { Ok(acc + x) });
+    assert_eq!(finish(f), Err(9));
+}
+
+#[test]
+fn with_latest_from() {
+    let primary = scripted(vec![Some(1), None, Some(2), None, Some(3), Some(4)]);
+    let other = scripted(vec![None, Some("a"), Some("b"), None, None, Some("c")]);
+    assert_eq!(
+        drain(primary.with_latest_from(other)),
+        Ok(vec![
+            (1, None),
+            (2, Some("b")),
+            (3, Some("c")),
+            (4, Some("c"))
+        ])
+    );
+}
+
+#[test]
+fn then_stream() {
+    let seeded = Ok::<usize, u32>(3)
+        .into_future()
+        .then_stream(|r| iter::<_, u32>(0..r.unwrap_or(0)));
+    assert_eq!(drain(seeded), Ok(vec![0, 1, 2]));
+
+    let failed = Err::<usize, u32>(3)
+        .into_future()
+        .then_stream(|r| iter::<_, u32>(0..r.unwrap_or(0)));
+    assert_eq!(drain(failed), Ok(vec![]));
+
+    let pending: Empty<usize, u32> = Empty::new();
+    assert!(pending
+        .then_stream(|r| iter::<_, u32>(0..r.unwrap_or(0)))
+        .poll()
+        .is_err());
+}
+
+#[test]
+fn retry_each() {
+    let flaky = scripted(vec![Some(Ok(1)), Some(Err(7)), Some(Ok(2)), Some(Ok(3))]);
+    assert_eq!(drain(flaky.retry_each(1)), Ok(vec![1, 2, 3]));
+
+    // The counter resets after each successful item.
+    let flaky = scripted(vec![
+        Some(Err(7)),
+        None,
+        Some(Ok(1)),
+        Some(Err(8)),
+        Some(Ok(2)),
+    ]);
+    assert_eq!(drain(flaky.retry_each(1)), Ok(vec![1, 2]));
+
+    let broken = scripted(vec![Some(Ok(1)), Some(Err(7)), Some(Err(8)), Some(Ok(2))]);
+    assert_eq!(drain(broken.retry_each(1)), Err(8));
+    let broken = scripted(vec![Some(Ok(1)), Some(Err(7))]);
+    assert_eq!(drain(broken.retry_each(0)), Err(7));
+}
+
+#[test]
+fn pairwise() {
+    assert_eq!(
+        drain(iter::<_, u32>(vec![1, 2, 3]).pairwise()),
+        Ok(vec![(1, 2), (2, 3)])
+    );
+    assert_eq!(
+        drain(scripted(vec![Some(1), None, Some(2)]).pairwise()),
+        Ok(vec![(1, 2)])
+    );
+    assert_eq!(drain(iter::<_, u32>(vec![1]).pairwise()), Ok(vec![]));
+}
+
+#[test]
+fn chunks_or_spins() {
+    let s = scripted(vec![
+        Some(1),
+        Some(2),
+        Some(3),
+        Some(4),
+        None,
+        None,
+        Some(5),
+        None,
+        Some(6),
+        Some(7),
+    ]);
+    assert_eq!(
+        drain(s.chunks_or_spins(3, 2)),
+        Ok(vec![vec![1, 2, 3], vec![4], vec![5, 6, 7]])
+    );
+    assert_eq!(
+        drain(iter::<_, u32>(1..6).chunks_or_spins(2, 1)),
+        Ok(vec![vec![1, 2], vec![3, 4], vec![5]])
+    );
+}
+
+#[test]
+fn flatten_ok_vecs() {
+    let s = iter::<_, u32>(vec![Ok(vec![1, 2]), Ok(vec![]), Ok(vec![3])]);
+    assert_eq!(drain(s.flatten_ok_vecs::<_, u32>()), Ok(vec![1, 2, 3]));
+
+    let s = scripted(vec![
+        Some(Ok(vec![1])),
+        None,
+        Some(Err(5)),
+        Some(Ok(vec![2])),
+    ]);
+    assert_eq!(drain(s.flatten_ok_vecs::<i32, u32>()), Err(5));
+}
+
+#[test]
+fn latest_into() {
+    let cell = Rc::new(AtomicCell::new(None));
+    let mut s = iter::<_, u32>(vec![1, 2, 3]).latest_into(cell.clone());
+    let mut seen = Vec::new();
+    loop {
+        s = match s.poll() {
+            Ok(Ok(Some((item, s)))) => {
+                assert_eq!(*cell.try_borrow().unwrap(), Some(item));
+                seen.push(item);
+                s
+            }
+            Ok(Ok(None)) => break,
+            Ok(Err(e)) => panic!("stream failed: {}", e),
+            Err(s) => s,
+        };
+    }
+    assert_eq!(seen, vec![1, 2, 3]);
+    assert_eq!(*cell.try_borrow().unwrap(), Some(3));
+
+    // A reader holding the cell stalls the stream rather than losing items.
+    let s = iter::<_, u32>(vec![1]).latest_into(cell.clone());
+    let guard = cell.try_borrow().unwrap();
+    let s = s.poll().err().unwrap();
+    drop(guard);
+    assert_eq!(drain(s), Ok(vec![1]));
+    assert_eq!(*cell.try_borrow().unwrap(), Some(1));
+}
+
+#[test]
+fn buffered_results() {
+    let s = iter::<_, u32>(vec![Ok(1), Err(2), Ok(3)]);
+    assert_eq!(drain(s.buffered_results(2)), Ok(vec![Ok(1), Err(2), Ok(3)]));
+
+    // Later futures finishing first still come out in order.
+    let (tx, rx) = channel::<i32>();
+    let (tx2, rx2) = channel::<i32>();
+    let s = iter::<_, u32>(vec![rx, rx2]).buffered_results(2);
+    let s = s.poll().err().unwrap();
+    tx2.send(2).unwrap();
+    let s = s.poll().err().unwrap();
+    drop(tx);
+    let (first, s) = s.poll().ok().unwrap().unwrap().unwrap();
+    assert!(first.is_err());
+    assert_eq!(drain(s), Ok(vec![Ok(2)]));
+}
+
+#[test]
+fn sample_every() {
+    assert_eq!(
+        drain(iter::<_, u32>(vec![1, 2, 3, 4, 5, 6]).sample_every(3)),
+        Ok(vec![3, 6])
+    );
+    let s = scripted(vec![
+        Some(1),
+        None,
+        Some(2),
+        Some(3),
+        None,
+        Some(4),
+        Some(5),
+    ]);
+    assert_eq!(drain(s.sample_every(2)), Ok(vec![2, 4]));
+}
+
+#[test]
+fn into_blocking_iter() {
+    let mut items = Vec::new();
+    for item in iter::<_, u32>(vec![1, 2, 3]).into_blocking_iter() {
+        items.push(item);
+    }
+    assert_eq!(items, vec![Ok(1), Ok(2), Ok(3)]);
+
+    let (tx, rx) = channel();
+    let t = thread::spawn(move || {
+        for i in 0..5 {
+            tx.send(i).unwrap();
+        }
+    });
+    let items: Vec<_> = receiver(rx).into_blocking_iter().collect();
+    t.join().unwrap();
+    assert_eq!(items, vec![Ok(0), Ok(1), Ok(2), Ok(3), Ok(4)]);
+
+    let items: Vec<_> = scripted(vec![Some(Ok(1)), None, Some(Err(2))])
+        .retry_each(0)
+        .into_blocking_iter()
+        .collect();
+    assert_eq!(items, vec![Ok(1), Err(2)]);
+}
+
+// Ends after `polls` pending polls, unparking itself on each of them and
+// keeping the task it was polled under.
+struct Parks {
+    polls: usize,
+    tasks: Rc<RefCell<Vec<task::Task>>>,
+}
+
+impl Stream for Parks {
+    type Item = ();
+    type Error = u32;
+
+    fn poll(mut self) -> StreamPoll<Self> {
+        if self.polls == 0 {
+            return Ok(Ok(None));
+        }
+        self.polls -= 1;
+        let task = task::park();
+        task.unpark();
+        self.tasks.borrow_mut().push(task);
+        Err(self)
+    }
+}
+
+#[test]
+fn into_blocking_iter_polls_as_one_task() {
+    let tasks = Rc::new(RefCell::new(Vec::new()));
+    let s = Parks {
+        polls: 3,
+        tasks: tasks.clone(),
+    };
+    assert_eq!(s.into_blocking_iter().count(), 0);
+    let tasks = tasks.borrow();
+    assert_eq!(tasks.len(), 3);
+    assert!(tasks.iter().all(|t| t.will_unpark(&tasks[0])));
+}
+
+#[test]
+fn batching() {
+    // Emit everything up to and including each `0` sentinel.
+    fn until_zero(buf: &mut VecDeque<i32>) -> Option<Vec<i32>> {
+        let end = buf.iter().position(|&x| x == 0)?;
+        Some(buf.drain(..=end).collect())
+    }
+
+    let s = scripted(vec![
+        Some(1),
+        Some(2),
+        Some(0),
+        None,
+        Some(3),
+        Some(0),
+        Some(0),
+        Some(4),
+    ]);
+    assert_eq!(
+        drain(s.batching(until_zero)),
+        Ok(vec![vec![1, 2, 0], vec![3, 0], vec![0]])
+    );
+
+    // A trailing item the closure never claims is dropped.
+    let s = iter::<_, u32>(vec![1, 2, 3, 4, 5]);
+    let pairs = |buf: &mut VecDeque<i32>| {
+        if buf.len() >= 2 {
+            Some(buf.drain(..2).collect::<Vec<_>>())
+        } else {
+            None
+        }
+    };
+    assert_eq!(drain(s.batching(pairs)), Ok(vec![vec![1, 2], vec![3, 4]]));
+}
+
+#[test]
+fn throttle_first() {
+    assert_eq!(
+        drain(iter::<_, u32>(1..8).throttle_first(3)),
+        Ok(vec![1, 4, 7])
+    );
+    // Quiet polls count towards the window too.
+    let s = scripted(vec![Some(1), None, Some(2), Some(3), None, None, Some(4)]);
+    assert_eq!(drain(s.throttle_first(2)), Ok(vec![1, 2, 4]));
+    assert_eq!(
+        drain(iter::<_, u32>(1..4).throttle_first(0)),
+        Ok(vec![1, 2, 3])
+    );
+}
+
+#[test]
+fn terminate_on() {
+    assert_eq!(
+        drain(iter::<_, u32>(vec![1, 2, 3, 4]).terminate_on(|&x| x == 3)),
+        Ok(vec![1, 2, 3])
+    );
+    assert_eq!(
+        drain(scripted(vec![Some(1), None, Some(2)]).terminate_on(|&x| x == 3)),
+        Ok(vec![1, 2])
+    );
+}
+
+#[test]
+fn enumerate_from() {
+    assert_eq!(
+        drain(iter::<_, u32>(vec!["a", "b"]).enumerate_from(10)),
+        Ok(vec![(10, "a"), (11, "b")])
+    );
+    assert_eq!(
+        drain(scripted(vec![None, Some("a"), None, Some("b")]).enumerate_from(0)),
+        Ok(vec![(0, "a"), (1, "b")])
+    );
+}
+
+#[test]
+fn retry_observe_attempts() {
+    let mut calls = 0;
+    let attempts = retry_observe(
+        || {
+            calls += 1;
+            if calls == 1 {
+                Err::<i32, &str>("flaky")
+            } else {
+                Ok(calls)
+            }
+        },
+        5,
+    );
+    assert_eq!(drain(attempts), Ok(vec![Err("flaky"), Ok(2)]));
+
+    let attempts = retry_observe(|| Err::<i32, &str>("down"), 3);
+    assert_eq!(drain(attempts), Ok(vec![Err("down"); 3]));
+
+    let (tx, rx) = channel::<i32>();
+    let mut rx = Some(rx);
+    let attempts = retry_observe(move || rx.take().unwrap(), 2);
+    let attempts = attempts.poll().err().unwrap();
+    tx.send(1).unwrap();
+    assert_eq!(drain(attempts), Ok(vec![Ok(1)]));
+}
+
+#[test]
+fn collect_until_error() {
+    let s = scripted(vec![
+        Some(Ok(1)),
+        None,
+        Some(Ok(2)),
+        Some(Err(9)),
+        Some(Ok(3)),
+    ]);
+    let collected = finish(s.retry_each(0).collect_until_error());
+    assert_eq!(collected, Ok((vec![1, 2], Some(9))));
+
+    let f = iter::<_, u32>(vec![1, 2]).collect_until_error();
+    assert_eq!(f.poll().ok(), Some(Ok((vec![1, 2], None))));
+}
+
+#[test]
+fn map_buffered() {
+    let gauge = Rc::new(Cell::new(0));
+    let peak = Cell::new(0);
+    let s = iter::<_, u32>(vec![4, 0, 2, 1, 3]).map_buffered(3, |polls| {
+        gauge.set(gauge.get() + 1);
+        peak.set(peak.get().max(gauge.get()));
+        Countdown {
+            polls,
+            value: polls as i32 * 10,
+            gauge: gauge.clone(),
+        }
+    });
+    assert_eq!(drain(s), Ok(vec![40, 0, 20, 10, 30]));
+    assert_eq!(peak.get(), 3);
+    assert_eq!(gauge.get(), 0);
+
+    let s = iter(vec![1, 2, 3]).map_buffered(2, |x| if x == 2 { Err(x) } else { Ok(x) });
+    assert_eq!(drain(s), Err(2));
+}
+
+#[test]
+fn poll_trace() {
+    let (tx, rx) = channel();
+    let trace = rx.poll_trace();
+    let (first, trace) = trace.poll().ok().unwrap().unwrap().unwrap();
+    let (second, trace) = trace.poll().ok().unwrap().unwrap().unwrap();
+    tx.send(1).unwrap();
+    let rest = drain(trace).unwrap();
+    assert_eq!(first, TraceEvent::Polled);
+    assert_eq!(second, TraceEvent::Polled);
+    assert_eq!(rest, vec![TraceEvent::Ready(Ok(1))]);
+
+    let f: FutureResult<i32, i32> = Err(3).into_future();
+    assert_eq!(drain(f.poll_trace()), Ok(vec![TraceEvent::Ready(Err(3))]));
+}
+
+#[test]
+fn materialize() {
+    let f: FutureResult<i32, u32> = Ok(1).into_future();
+    assert_eq!(drain(f.materialize()), Ok(vec![Notification::OnNext(1)]));
+
+    let f: FutureResult<i32, u32> = Err(2).into_future();
+    assert_eq!(drain(f.materialize()), Ok(vec![Notification::OnError(2)]));
+
+    // Nothing is yielded while the future is pending.
+    let (tx, rx) = channel::<i32>();
+    let m = rx.materialize().poll().err().unwrap();
+    tx.send(3).unwrap();
+    assert_eq!(drain(m), Ok(vec![Notification::OnNext(3)]));
+}
+
+#[derive(Debug, PartialEq)]
+struct Boom(u32);
+
+impl From<Never> for Boom {
+    fn from(never: Never) -> Boom {
+        match never {}
+    }
+}
+
+#[test]
+fn dematerialize() {
+    let f: FutureResult<i32, Boom> = Ok(1).into_future();
+    assert_eq!(drain(f.materialize().dematerialize()), Ok(vec![1]));
+
+    let f: FutureResult<i32, Boom> = Err(Boom(2)).into_future();
+    assert_eq!(drain(f.materialize().dematerialize()), Err(Boom(2)));
+
+    // Items after an `OnError` are never reached.
+    let notes = iter::<_, Never>(vec![
+        Notification::OnNext(1),
+        Notification::OnNext(2),
+        Notification::OnError(Boom(3)),
+        Notification::OnNext(4),
+    ]);
+    let mut seen = Vec::new();
+    let s = notes.dematerialize().inspect(|x| seen.push(*x));
+    assert_eq!(drain(s), Err(Boom(3)));
+    assert_eq!(seen, vec![1, 2]);
+}
+
+#[test]
+fn split_on_error() {
+    let s = scripted(vec![Some(Ok(1)), None, Some(Err(4)), Some(Ok(2))]);
+    assert_eq!(
+        finish(s.retry_each(0).split_on_error()),
+        Ok((vec![1], Some((4, ()))))
+    );
+
+    let s = iter::<_, u32>(vec![1, 2]);
+    assert_eq!(finish(s.split_on_error()), Ok((vec![1, 2], None)));
+}
+
+#[test]
+fn replay() {
+    let (source, early) = iter::<_, u32>(vec![1, 2, 3, 4, 5]).replay(2);
+    let (a, source) = source.poll().ok().unwrap().unwrap().unwrap();
+    let (b, source) = source.poll().ok().unwrap().unwrap().unwrap();
+    assert_eq!((a, b), (1, 2));
+
+    let late = source.subscribe();
+    let (x, late) = late.poll().ok().unwrap().unwrap().unwrap();
+    let (y, late) = late.poll().ok().unwrap().unwrap().unwrap();
+    assert_eq!((x, y), (1, 2));
+    let late = late.poll().err().unwrap();
+
+    let (c, source) = source.poll().ok().unwrap().unwrap().unwrap();
+    assert_eq!(c, 3);
+    let (z, late) = late.poll().ok().unwrap().unwrap().unwrap();
+    assert_eq!(z, 3);
+
+    assert_eq!(drain(source), Ok(vec![4, 5]));
+    assert_eq!(drain(late), Ok(vec![4, 5]));
+    // The first subscriber fell behind by more than the capacity.
+    assert_eq!(drain(early), Ok(vec![4, 5]));
+}
+
+#[test]
+fn buffered_recover() {
+    let s = iter::<_, u32>(vec![Ok(1), Err("bad"), Ok(3)]);
+    assert_eq!(
+        drain(s.buffered_recover(2, |e| e.len() as i32)),
+        Ok(vec![1, 3, 3])
+    );
+
+    let (tx, rx) = channel::<i32>();
+    let (tx2, rx2) = channel::<i32>();
+    let s = iter::<_, u32>(vec![rx, rx2]).buffered_recover(2, |_| -1);
+    tx2.send(2).unwrap();
+    let s = s.poll().err().unwrap();
+    drop(tx);
+    assert_eq!(drain(s), Ok(vec![-1, 2]));
+}
+
+#[test]
+fn rolling_sum() {
+    assert_eq!(
+        drain(iter::<_, u32>(vec![1, 2, 3, 4]).rolling_sum(2)),
+        Ok(vec![3, 5, 7])
+    );
+    let s = scripted(vec![Some(1.5), None, Some(2.5), Some(-1.0)]);
+    assert_eq!(drain(s.rolling_sum(3)), Ok(vec![3.0]));
+    assert_eq!(drain(iter::<_, u32>(vec![1, 2]).rolling_sum(3)), Ok(vec![]));
+}
+
+#[test]
+fn fanout() {
+    let s = iter::<_, Never>(vec![1, 2, 3]);
+    let (a, b) = finish(s.fanout(Vec::new(), Vec::new())).unwrap();
+    assert_eq!(a, vec![1, 2, 3]);
+    assert_eq!(a, b);
+
+    // One slow sink doesn't cost the other any items.
+    let s = iter::<_, Never>(vec![1, 2, 3]);
+    let (a, b) = finish(s.fanout(OneAtATime::default(), Vec::new())).unwrap();
+    assert_eq!(a.items, vec![1, 2, 3]);
+    assert_eq!(b, vec![1, 2, 3]);
+}
+
+#[test]
+fn skip_last() {
+    assert_eq!(
+        drain(iter::<_, u32>(vec![1, 2, 3, 4, 5]).skip_last(2)),
+        Ok(vec![1, 2, 3])
+    );
+    assert_eq!(
+        drain(scripted(vec![Some(1), None, Some(2)]).skip_last(0)),
+        Ok(vec![1, 2])
+    );
+    assert_eq!(drain(iter::<_, u32>(vec![1, 2]).skip_last(3)), Ok(vec![]));
+}
+
+#[test]
+fn accumulate_into() {
+    let total = Rc::new(AtomicCell::new(0));
+    let s = scripted(vec![Some(1), None, Some(2), Some(3)])
+        .accumulate_into(total.clone(), |t, x| *t += x);
+    let (item, s) = s.poll().ok().unwrap().unwrap().unwrap();
+    assert_eq!(item, 1);
+    assert_eq!(*total.try_borrow().unwrap(), 1);
+    let s = s.poll().err().unwrap();
+    assert_eq!(*total.try_borrow().unwrap(), 1);
+    assert_eq!(drain(s), Ok(vec![2, 3]));
+    assert_eq!(*total.try_borrow().unwrap(), 6);
+}
+
+#[test]
+fn collect_with_capacity() {
+    let items = finish(iter::<_, u32>(0..100).collect_with_capacity(100)).unwrap();
+    assert_eq!(items.len(), 100);
+    assert!(items.capacity() >= 100);
+    assert_eq!(items, (0..100).collect::<Vec<_>>());
+
+    let s = scripted(vec![Some(1), None, Some(2)]);
+    assert_eq!(finish(s.collect_with_capacity(1)), Ok(vec![1, 2]));
+}
+
+#[test]
+fn join_progress_reports_completions() {
+    let (tx, rx) = channel::<i32>();
+    let (tx2, rx2) = channel::<i32>();
+    let (tx3, rx3) = channel::<i32>();
+    let s = join_progress(vec![rx, rx2, rx3]);
+    let s = s.poll().err().unwrap();
+    tx2.send(2).unwrap();
+    tx3.send(3).unwrap();
+    let (event, s) = s.poll().ok().unwrap().unwrap().unwrap();
+    let progress = |completed| {
+        JoinEvent::Progress(Progress {
+            completed,
+            total: 3,
+        })
+    };
+    assert_eq!(event, progress(1));
+    let (event, s) = s.poll().ok().unwrap().unwrap().unwrap();
+    assert_eq!(event, progress(2));
+    let s = s.poll().err().unwrap();
+    tx.send(1).unwrap();
+    assert_eq!(
+        drain(s),
+        Ok(vec![progress(3), JoinEvent::Done(vec![1, 2, 3])])
+    );
+}
+
+#[test]
+fn split_before() {
+    let s = scripted(vec![
+        Some(1),
+        Some(2),
+        Some(0),
+        None,
+        Some(3),
+        Some(0),
+        Some(4),
+    ]);
+    assert_eq!(
+        drain(s.split_before(|x| *x == 0)),
+        Ok(vec![vec![1, 2], vec![0, 3], vec![0, 4]])
+    );
+}
+
+#[test]
+fn run_length_encode() {
+    let s = scripted(vec![
+        Some('a'),
+        Some('a'),
+        None,
+        Some('b'),
+        Some('c'),
+        Some('c'),
+        None,
+        Some('c'),
+    ]);
+    assert_eq!(
+        drain(s.run_length_encode()),
+        Ok(vec![('a', 2), ('b', 1), ('c', 3)])
+    );
+}
+
+#[test]
+fn total_timeout_spins() {
+    let slow = scripted_as::<_, TimeoutError>(vec![Some(1), None, None, Some(2), None, Some(3)]);
+    let mut s = slow.total_timeout_spins(4);
+    let mut seen = Vec::new();
+    let err = loop {
+        s = match s.poll() {
+            Ok(Ok(Some((item, s)))) => {
+                seen.push(item);
+                s
+            }
+            Ok(Ok(None)) => panic!("stream should have timed out"),
+            Ok(Err(e)) => break e,
+            Err(s) => s,
+        };
+    };
+    assert_eq!(err, TimeoutError);
+    assert_eq!(seen, vec![1, 2]);
+
+    let fast = scripted_as::<_, TimeoutError>(vec![Some(1), None, Some(2)]);
+    assert_eq!(drain(fast.total_timeout_spins(4)), Ok(vec![1, 2]));
+}
+
+#[test]
+fn drain_runs_side_effects() {
+    let seen = RefCell::new(Vec::new());
+    let s = scripted(vec![Some(1), None, Some(2), Some(3)]).inspect(|x| seen.borrow_mut().push(*x));
+    assert_eq!(finish(s.drain()), Ok(()));
+    assert_eq!(*seen.borrow(), vec![1, 2, 3]);
+
+    let failing = iter::<_, u32>(vec![Ok(1), Err(2), Ok(3)]).retry_each(0);
+    assert_eq!(finish(failing.drain()), Err(2));
+}
+
+#[test]
+fn collect_bursts() {
+    // A single pending poll is not enough to end a burst.
+    let s = scripted(vec![
+        Some(1),
+        Some(2),
+        None,
+        Some(3),
+        None,
+        None,
+        Some(4),
+        None,
+        None,
+        None,
+        Some(5),
+    ]);
+    assert_eq!(
+        drain(s.collect_bursts(2)),
+        Ok(vec![vec![1, 2, 3], vec![4], vec![5]])
+    );
+}
+
+#[test]
+fn for_each_receiver() {
+    let (tx, rx) = channel();
+    for i in 0..3 {
+        tx.send(i).unwrap();
+    }
+    drop(tx);
+    let mut seen = Vec::new();
+    assert_eq!(rx.for_each(|msg| seen.push(msg)).wait(), Ok(()));
+    assert_eq!(seen, vec![0, 1, 2]);
+
+    let (tx, rx) = channel();
+    let t = thread::spawn(move || {
+        for i in 0..5 {
+            tx.send(i).unwrap();
+        }
+    });
+    let mut seen = Vec::new();
+    assert_eq!(rx.for_each(|msg| seen.push(msg)).wait(), Ok(()));
+    t.join().unwrap();
+    assert_eq!(seen, vec![0, 1, 2, 3, 4]);
+}
+
+#[test]
+fn sorted() {
+    let s = scripted(vec![Some(3), None, Some(1), Some(2)]);
+    assert_eq!(finish(s.sorted()), Ok(vec![1, 2, 3]));
+
+    let failing = iter::<_, u32>(vec![Ok(2), Err(1)]).retry_each(0);
+    assert_eq!(finish(failing.sorted()), Err(1));
+}
+
+#[derive(Debug, PartialEq)]
+struct Player {
+    name: &'static str,
+    score: u32,
+}
+
+#[test]
+fn sorted_by() {
+    let s = scripted(vec![
+        Some(Player {
+            name: "a",
+            score: 2,
+        }),
+        None,
+        Some(Player {
+            name: "b",
+            score: 9,
+        }),
+        Some(Player {
+            name: "c",
+            score: 5,
+        }),
+    ]);
+    let ranked = finish(s.sorted_by(|x, y| y.score.cmp(&x.score))).unwrap();
+    let names: Vec<_> = ranked.iter().map(|p| p.name).collect();
+    assert_eq!(names, vec!["b", "c", "a"]);
+
+    let failing = iter::<_, u32>(vec![Ok(2), Err(1)]).retry_each(0);
+    assert_eq!(finish(failing.sorted_by(|a, b| a.cmp(b))), Err(1));
+}
+
+#[test]
+fn collect_grouped() {
+    let s = scripted(vec![Some((1, "a")), None, Some((2, "b")), Some((1, "c"))]);
+    let groups = finish(s.collect_grouped()).unwrap();
+    assert_eq!(groups.len(), 2);
+    assert_eq!(groups[&1], vec!["a", "c"]);
+    assert_eq!(groups[&2], vec!["b"]);
+}
+
+#[test]
+fn histogram() {
+    let s = scripted(vec![
+        Some("a"),
+        Some("b"),
+        None,
+        Some("a"),
+        Some("c"),
+        Some("a"),
+    ]);
+    let counts = finish(s.histogram()).unwrap();
+    assert_eq!(counts.len(), 3);
+    assert_eq!(counts["a"], 3);
+    assert_eq!(counts["b"], 1);
+    assert_eq!(counts["c"], 1);
+
+    let failing = iter::<_, u32>(vec![Ok(1), Err(2), Ok(1)]).retry_each(0);
+    assert_eq!(finish(failing.histogram()), Err(2));
+}
+
+#[test]
+fn last_n() {
+    let s = scripted(vec![Some(1), Some(2), None, Some(3), Some(4), Some(5)]);
+    assert_eq!(finish(s.last_n(2)), Ok(vec![4, 5]));
+
+    let short = iter::<_, u32>(vec![1]);
+    assert_eq!(finish(short.last_n(3)), Ok(vec![1]));
+}
+
+#[test]
+fn map_while() {
+    let s = scripted(vec![Some("1"), None, Some("2"), Some("x"), Some("3")]);
+    let s = s.map_while(|x| x.parse::<i32>().ok());
+    assert_eq!(drain(s), Ok(vec![1, 2]));
+}
+
+#[test]
+fn coalesce() {
+    // Each item is a value and a running sum, so a merged run still knows
+    // which value it was made of.
+    let runs = [1, 1, 2, 3, 3].iter().map(|&x| Some((x, x)));
+    let s = scripted(runs.collect()).coalesce(|a, b| {
+        if a.0 == b.0 {
+            Ok((a.0, a.1 + b.1))
+        } else {
+            Err((a, b))
+        }
+    });
+    let sums: Vec<_> = drain(s).unwrap().into_iter().map(|(_, sum)| sum).collect();
+    assert_eq!(sums, vec![2, 2, 6]);
+
+    let s = scripted(vec![Some(1), None, Some(1), Some(5)]);
+    let s = s.coalesce(|a, b| if a == b { Ok(a + b) } else { Err((a, b)) });
+    assert_eq!(drain(s), Ok(vec![2, 5]));
+}
+
+#[test]
+fn fill_gaps() {
+    let s = scripted(vec![Some((0, 'a')), None, Some((2, 'c')), Some((5, 'f'))]);
+    assert_eq!(
+        drain(s.fill_gaps('x')),
+        Ok(vec![
+            (0, 'a'),
+            (1, 'x'),
+            (2, 'c'),
+            (3, 'x'),
+            (4, 'x'),
+            (5, 'f')
+        ])
+    );
+
+    // Nothing is made up before the first index.
+    let s = scripted(vec![Some((3, 'd')), Some((4, 'e'))]);
+    assert_eq!(drain(s.fill_gaps('x')), Ok(vec![(3, 'd'), (4, 'e')]));
+}
+
+#[test]
+fn assert_increasing() {
+    let sorted = scripted_as::<_, OrderViolation>(vec![Some(1), None, Some(1), Some(3)]);
+    assert_eq!(drain(sorted.assert_increasing()), Ok(vec![1, 1, 3]));
+
+    let mut s = scripted_as::<_, OrderViolation>(vec![Some(1), Some(3), Some(2), Some(4)])
+        .assert_increasing();
+    let mut seen = Vec::new();
+    let result = loop {
+        s = match s.poll() {
+            Ok(Ok(Some((item, s)))) => {
+                seen.push(item);
+                s
+            }
+            Ok(Ok(None)) => break Ok(()),
+            Ok(Err(e)) => break Err(e),
+            Err(s) => s,
+        };
+    };
+    assert_eq!(result, Err(OrderViolation));
+    assert_eq!(seen, vec![1, 3]);
+}
+
+#[test]
+fn throttle_by() {
+    let mut s = iter::<_, u32>(vec![2, 0, 3, 1]).throttle_by(|x| *x);
+    let mut polls = Vec::new();
+    let mut pending = 0;
+    loop {
+        s = match s.poll() {
+            Ok(Ok(Some((item, s)))) => {
+                polls.push((item, pending));
+                pending = 0;
+                s
+            }
+            Ok(Ok(None)) => break,
+            Ok(Err(e)) => panic!("stream failed: {}", e),
+            Err(s) => {
+                pending += 1;
+                s
+            }
+        };
+    }
+    // Each item is preceded by as many pending polls as the one before asked.
+    assert_eq!(polls, vec![(2, 0), (0, 2), (3, 0), (1, 3)]);
+}
+
+#[test]
+fn idle_timeout_spins() {
+    // Gaps within the budget are fine however long the stream runs.
+    let steady =
+        scripted_as::<_, TimeoutError>(vec![Some(1), None, None, Some(2), None, None, Some(3)]);
+    assert_eq!(drain(steady.idle_timeout_spins(2)), Ok(vec![1, 2, 3]));
+
+    let stalls =
+        scripted_as::<_, TimeoutError>(vec![Some(1), None, Some(2), None, None, None, Some(3)]);
+    let mut s = stalls.idle_timeout_spins(2);
+    let mut seen = Vec::new();
+    let err = loop {
+        s = match s.poll() {
+            Ok(Ok(Some((item, s)))) => {
+                seen.push(item);
+                s
+            }
+            Ok(Ok(None)) => panic!("stream should have timed out"),
+            Ok(Err(e)) => break e,
+            Err(s) => s,
+        };
+    };
+    assert_eq!(err, TimeoutError);
+    assert_eq!(seen, vec![1, 2]);
+}
+
+#[test]
+fn rechunk() {
+    let s = scripted(vec![Some(vec![1, 2, 3]), None, Some(vec![4])]);
+    assert_eq!(drain(s.rechunk(2)), Ok(vec![vec![1, 2], vec![3, 4]]));
+
+    let s = scripted(vec![
+        Some(vec![1, 2, 3, 4, 5]),
+        Some(vec![]),
+        Some(vec![6, 7]),
+    ]);
+    assert_eq!(
+        drain(s.rechunk(3)),
+        Ok(vec![vec![1, 2, 3], vec![4, 5, 6], vec![7]])
+    );
+}
+
+#[test]
+fn tee_into() {
+    let seen = Rc::new(AtomicCell::new(Vec::new()));
+    let mut s = scripted(vec![Some(1), None, Some(2), Some(3)]).tee_into(seen.clone());
+    let mut items = Vec::new();
+    loop {
+        s = match s.poll() {
+            Ok(Ok(Some((item, s)))) => {
+                items.push(item);
+                assert_eq!(*seen.try_borrow().unwrap(), items);
+                s
+            }
+            Ok(Ok(None)) => break,
+            Ok(Err(e)) => panic!("stream failed: {}", e),
+            Err(s) => s,
+        };
+    }
+    assert_eq!(*seen.try_borrow().unwrap(), vec![1, 2, 3]);
+}
+
+#[test]
+fn for_each_concurrent() {
+    let gauge = Rc::new(Cell::new(0));
+    let peak = Cell::new(0);
+    let done = Rc::new(Cell::new(0));
+    let items = scripted(vec![
+        Some(3),
+        Some(1),
+        None,
+        Some(2),
+        Some(0),
+        Some(4),
+        Some(1),
+    ]);
+    let f = items.for_each_concurrent(2, |polls| {
+        gauge.set(gauge.get() + 1);
+        peak.set(peak.get().max(gauge.get()));
+        let done = done.clone();
+        Countdown {
+            polls,
+            value: 0,
+            gauge: gauge.clone(),
+        }
+        .map(move |_| done.set(done.get() + 1))
+    });
+    assert_eq!(finish(f), Ok(()));
+    assert_eq!(done.get(), 6);
+    assert_eq!(gauge.get(), 0);
+    assert_eq!(peak.get(), 2);
+
+    // A failing effect fails the whole run.
+    let failing = iter::<_, u32>(vec![1, 9, 2])
+        .for_each_concurrent(4, |x| if x == 9 { Err(x) } else { Ok(()) }.into_future());
+    assert_eq!(finish(failing), Err(9));
+}
+
+#[test]
+fn backpressure() {
+    let congested = Cell::new(false);
+    let pulled = Cell::new(0);
+    let s = iter::<_, u32>(vec![1, 2, 3])
+        .inspect(|_| pulled.set(pulled.get() + 1))
+        .backpressure(|| congested.get());
+
+    let (first, s) = s.poll().ok().unwrap().unwrap().unwrap();
+    assert_eq!(first, 1);
+
+    // While congested the source isn't touched at all.
+    congested.set(true);
+    let s = s.poll().err().unwrap();
+    let s = s.poll().err().unwrap();
+    assert_eq!(pulled.get(), 1);
+
+    congested.set(false);
+    assert_eq!(drain(s), Ok(vec![2, 3]));
+    assert_eq!(pulled.get(), 3);
+}
+
+#[test]
+fn unzip() {
+    let (left, right) = scripted(vec![Some((1, "a")), None, Some((2, "b"))]).unzip();
+    let (first, left) = left.poll().ok().unwrap().unwrap().unwrap();
+    assert_eq!(first, 1);
+    assert_eq!(drain(right), Ok(vec!["a", "b"]));
+    assert_eq!(drain(left), Ok(vec![2]));
+
+    // The side which pulls the error gets it; the other just ends.
+    let s = iter::<_, u32>(vec![Ok((1, 'x')), Err(5)]).retry_each(0);
+    let (left, right) = s.unzip();
+    assert_eq!(drain(left), Err(5));
+    assert_eq!(drain(right), Ok(vec!['x']));
+}
+
+#[test]
+fn prefix_scan() {
+    let s = scripted(vec![Some(1), None, Some(2), Some(3)]).prefix_scan(0, |acc, x| acc + x);
+    assert_eq!(drain(s), Ok(vec![1, 3, 6]));
+
+    let s = iter::<Vec<i32>, u32>(vec![]).prefix_scan(0, |acc, x| acc + x);
+    assert_eq!(drain(s), Ok(vec![]));
+}
+
+#[test]
+fn take_distinct() {
+    let pulled = Cell::new(0);
+    let s = scripted(vec![Some(1), Some(1), None, Some(2), Some(2), Some(3)])
+        .inspect(|_| pulled.set(pulled.get() + 1))
+        .take_distinct(2);
+    assert_eq!(drain(s), Ok(vec![1, 2]));
+    assert_eq!(pulled.get(), 3);
+}
+
+#[test]
+fn tumbling_reduce() {
+    let s = scripted(vec![Some(1), Some(2), None, Some(3), Some(4), Some(5)]);
+    let s = s.tumbling_reduce(2, |w| w.iter().sum::<i32>());
+    assert_eq!(drain(s), Ok(vec![3, 7, 5]));
+
+    let even = iter::<_, u32>(vec![1, 2, 3, 4]).tumbling_reduce(2, |w| w.len());
+    assert_eq!(drain(even), Ok(vec![2, 2]));
+}
+
+#[test]
+fn gated() {
+    let (tx, rx) = oneshot::<()>();
+    let pulled = Cell::new(0);
+    let gate = rx.map_err(|_| 0u32);
+    let s = iter::<_, u32>(vec![1, 2, 3])
+        .inspect(|_| pulled.set(pulled.get() + 1))
+        .gated(gate);
+    let s = s.poll().err().unwrap();
+    let s = s.poll().err().unwrap();
+    assert_eq!(pulled.get(), 0);
+    tx.complete(());
+    assert_eq!(drain(s), Ok(vec![1, 2, 3]));
+}
+
+#[test]
+fn report_to() {
+    let (tx, rx) = channel();
+    let s = scripted(vec![Some("ab"), None, Some("cde")]).report_to(tx, |x| x.len());
+    assert_eq!(drain(s), Ok(vec!["ab", "cde"]));
+    assert_eq!(
+        receiver(rx).into_blocking_iter().collect::<Vec<_>>(),
+        vec![Ok(2), Ok(3)]
+    );
+
+    // A dropped receiver doesn't disturb the stream.
+    let (tx, rx) = channel();
+    drop(rx);
+    let s = iter::<_, u32>(vec![1, 2]).report_to(tx, |x| *x);
+    assert_eq!(drain(s), Ok(vec![1, 2]));
+}
+
+#[test]
+fn futures_unordered_in_completion_order() {
+    let (tx, rx) = oneshot::<i32>();
+    let (tx2, rx2) = oneshot::<i32>();
+    let set = futures_unordered(vec![rx, rx2]);
+    let set = set.poll().err().unwrap();
+    tx2.complete(2);
+    let (item, mut set) = set.poll().ok().unwrap().ok().unwrap().unwrap();
+    assert_eq!(item, 2);
+
+    // More work can join while the set is running.
+    let (tx3, rx3) = oneshot::<i32>();
+    set.push(rx3);
+    assert_eq!(set.len(), 2);
+    tx3.complete(3);
+    tx.complete(1);
+    assert_eq!(set.collect().wait(), Ok(vec![1, 3]));
+}
+
+#[test]
+fn futures_unordered_polls_only_unparked() {
+    let polls = Rc::new(RefCell::new(vec![0; 3]));
+    let mut completes = Vec::new();
+    let mut set = FuturesUnordered::unparked_only();
+    for i in 0..3 {
+        let (tx, rx) = oneshot::<usize>();
+        let polls = polls.clone();
+        completes.push(tx);
+        set.push(rx.on_poll(move |_| polls.borrow_mut()[i] += 1));
+    }
+    let set = set.poll().err().unwrap();
+    assert_eq!(*polls.borrow(), vec![1, 1, 1]);
+
+    completes.remove(1).complete(1);
+    let (item, set) = set.poll().ok().unwrap().ok().unwrap().unwrap();
+    assert_eq!(item, 1);
+    assert_eq!(*polls.borrow(), vec![1, 2, 1]);
+    drop(completes);
+    assert_eq!(set.collect().wait(), Err(Canceled));
+}
+
+#[test]
+fn futures_unordered_sweeps_sources_that_cannot_unpark() {
+    let (tx, rx) = channel::<i32>();
+    let (tx2, rx2) = channel::<i32>();
+    let set = futures_unordered(vec![rx, rx2]);
+    let sender = thread::spawn(move || {
+        tx2.send(2).unwrap();
+        tx.send(1).unwrap();
+    });
+    let mut got = set.collect().wait().unwrap();
+    sender.join().unwrap();
+    got.sort();
+    assert_eq!(got, vec![1, 2]);
+}
+
+#[test]
+fn futures_unordered_starts_empty() {
+    assert!(FuturesUnordered::<Oneshot<i32>>::new().is_empty());
+}
@@ -0,0 +1,139 @@
+extern crate futures;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::SendError;
+use std::sync::Arc;
+use std::task::{Wake, Waker};
+use std::thread;
+
+use futures::stream::Stream;
+use futures::sync::mpsc::channel;
+use futures::sync::BiLock;
+use futures::task::Task;
+use futures::*;
+
+#[test]
+fn bounded_channel_holds_senders_back() {
+    let (tx, rx) = channel::<i32>(1);
+    let tx = tx.send(1).poll().ok().unwrap().ok().unwrap();
+    // The buffer is full, so the next send waits for the receiver.
+    let sending = tx.send(2).poll().err().unwrap();
+    let (item, rx) = rx.poll().ok().unwrap().ok().unwrap().unwrap();
+    assert_eq!(item, 1);
+    let tx = sending.poll().ok().unwrap().ok().unwrap();
+    drop(tx);
+    assert_eq!(rx.collect().wait(), Ok(vec![2]));
+}
+
+#[derive(Default)]
+struct CountWakes(AtomicUsize);
+
+impl Wake for CountWakes {
+    fn wake(self: Arc<Self>) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn repolled_senders_wait_once() {
+    let (tx, rx) = channel::<i32>(1);
+    let tx = tx.send(1).poll().ok().unwrap().ok().unwrap();
+    let wakes = Arc::new(CountWakes::default());
+    let task = Task::from_waker(Waker::from(wakes.clone()));
+    let mut sending = tx.send(2);
+    for _ in 0..10 {
+        sending = task.enter(|| sending.poll()).err().unwrap();
+    }
+    // Another task for the same waker counts as the same sender.
+    let again = Task::from_waker(Waker::from(wakes.clone()));
+    let sending = again.enter(|| sending.poll()).err().unwrap();
+    let (_, _rx) = rx.poll().ok().unwrap().ok().unwrap().unwrap();
+    assert_eq!(wakes.0.load(Ordering::SeqCst), 1);
+    assert!(sending.poll().is_ok());
+}
+
+#[test]
+fn dropped_receiver_fails_sends() {
+    let (tx, rx) = channel::<i32>(2);
+    drop(rx);
+    match tx.send(7).wait() {
+        Err(SendError(7)) => {}
+        _ => panic!("expected the item back"),
+    }
+}
+
+#[test]
+fn producer_and_consumer_threads() {
+    let (tx, rx) = channel::<usize>(4);
+    let producers: Vec<_> = (0..3)
+        .map(|p| {
+            let tx = tx.clone();
+            thread::spawn(move || {
+                let mut tx = tx;
+                for i in 0..50 {
+                    tx = tx.send(p * 100 + i).wait().ok().unwrap();
+                }
+            })
+        })
+        .collect();
+    drop(tx);
+    let mut got = rx.collect().wait().ok().unwrap();
+    for producer in producers {
+        producer.join().unwrap();
+    }
+    got.sort();
+    let mut expected: Vec<usize> = (0..3)
+        .flat_map(|p| (0..50).map(move |i| p * 100 + i))
+        .collect();
+    expected.sort();
+    assert_eq!(got, expected);
+}
+
+#[test]
+fn bilock_halves_take_turns() {
+    let (a, b) = BiLock::new(vec![1]);
+    let task = Task::new();
+    let mut held = a.poll_lock().unwrap();
+    assert!(task.enter(|| b.poll_lock()).is_none());
+    held.push(2);
+    drop(held);
+    assert!(task.take_unpark());
+    b.poll_lock().unwrap().push(3);
+    assert_eq!(*a.poll_lock().unwrap(), vec![1, 2, 3]);
+    assert_eq!(a.reunite(b).ok(), Some(vec![1, 2, 3]));
+}
+
+#[test]
+fn bilock_only_reunites_its_own_halves() {
+    let (a, _b) = BiLock::new(1);
+    let (_c, d) = BiLock::new(2);
+    assert!(a.reunite(d).is_err());
+}
+
+#[test]
+fn bilock_across_threads() {
+    let (a, b) = BiLock::new(0);
+    let add = |half: BiLock<i32>| {
+        thread::spawn(move || {
+            let task = Task::new();
+            let mut added = 0;
+            while added < 1000 {
+                match task.enter(|| half.poll_lock()) {
+                    Some(mut value) => {
+                        *value += 1;
+                        added += 1;
+                    }
+                    None => {
+                        while !task.take_unpark() {
+                            thread::park();
+                        }
+                    }
+                }
+            }
+            half
+        })
+    };
+    let (a, b) = (add(a), add(b));
+    let (a, b) = (a.join().unwrap(), b.join().unwrap());
+    assert_eq!(a.reunite(b).ok(), Some(2000));
+}
@@ -0,0 +1,62 @@
+extern crate futures;
+
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+
+use futures::task::{self, Task};
+use futures::*;
+
+#[test]
+fn oneshot_unparks_waiting_task() {
+    let (tx, rx) = oneshot::<i32>();
+    let task = Task::new();
+    let rx = task.enter(|| rx.poll()).err().unwrap();
+    assert!(!task.take_unpark());
+
+    let sender = thread::spawn(move || tx.complete(5));
+    while !task.take_unpark() {
+        thread::park();
+    }
+    sender.join().unwrap();
+    assert_eq!(task.enter(|| rx.poll()).ok(), Some(Ok(5)));
+}
+
+#[test]
+fn dropped_sender_unparks() {
+    let (tx, rx) = oneshot::<i32>();
+    let task = Task::new();
+    let rx = task.enter(|| rx.poll()).err().unwrap();
+    drop(tx);
+    assert!(task.take_unpark());
+    assert_eq!(rx.poll().ok(), Some(Err(Canceled)));
+}
+
+#[test]
+fn park_outside_a_task() {
+    // Without a task being driven, `park` still hands out a usable handle.
+    let task = task::park();
+    task.unpark();
+    assert!(task.take_unpark());
+    assert!(!task.take_unpark());
+
+    let outer = Task::new();
+    outer.enter(|| {
+        task::park().unpark();
+    });
+    assert!(outer.take_unpark());
+}
+
+#[test]
+fn enter_restores_the_outer_task_after_a_panic() {
+    let outer = Task::new();
+    let inner = Task::new();
+    outer.enter(|| {
+        let caught = panic::catch_unwind(AssertUnwindSafe(|| {
+            inner.enter(|| panic!("inside"));
+        }));
+        assert!(caught.is_err());
+        task::park().unpark();
+    });
+    assert!(outer.take_unpark());
+    assert!(!inner.take_unpark());
+}
@@ -0,0 +1,113 @@
+extern crate futures;
+
+use std::cell::Cell;
+use std::sync::mpsc::channel;
+use std::time::{Duration, Instant};
+
+use futures::timer::Delay;
+use futures::*;
+
+#[derive(Debug, PartialEq)]
+enum Error {
+    Timeout,
+    Gone,
+}
+
+impl From<TimeoutError> for Error {
+    fn from(_: TimeoutError) -> Error {
+        Error::Timeout
+    }
+}
+
+#[test]
+fn delay_waits_out_its_duration() {
+    let start = Instant::now();
+    let delay = Delay::new(Duration::from_millis(20));
+    let delay = delay.poll().err().unwrap();
+    assert_eq!(delay.wait().ok(), Some(()));
+    assert!(start.elapsed() >= Duration::from_millis(20));
+
+    assert!(Delay::until(start).poll().is_ok());
+}
+
+#[test]
+fn timeout_fires_when_the_future_is_too_slow() {
+    let (_tx, rx) = channel::<i32>();
+    let f = rx
+        .map_err(|_| Error::Gone)
+        .timeout(Duration::from_millis(10));
+    assert_eq!(f.wait(), Err(Error::Timeout));
+
+    let (tx, rx) = channel::<i32>();
+    let f = rx.map_err(|_| Error::Gone).timeout(Duration::from_secs(10));
+    let f = f.poll().err().unwrap();
+    tx.send(3).unwrap();
+    assert_eq!(f.wait(), Ok(3));
+}
+
+#[test]
+fn select_bounds_a_computation() {
+    let (_tx, rx) = channel::<&str>();
+    let slow = rx.map_err(|_| ());
+    let bound = Delay::new(Duration::from_millis(10))
+        .map(|()| "timed out")
+        .map_err(|never| match never {});
+    assert_eq!(slow.select(bound).wait(), Ok("timed out"));
+}
+
+#[test]
+fn retry_policy_pauses_with_backoff() {
+    let start = Instant::now();
+    let calls = Cell::new(0);
+    let policy = RetryPolicy::attempts(3)
+        .delay(Duration::from_millis(5))
+        .backoff(2);
+    let f = retry(policy, || {
+        calls.set(calls.get() + 1);
+        err::<(), u32>(calls.get())
+    });
+    let f = f.poll().err().unwrap();
+    assert_eq!(calls.get(), 1);
+    assert_eq!(f.wait(), Err(3));
+    // 5ms before the second attempt and 10ms before the third.
+    assert!(start.elapsed() >= Duration::from_millis(15));
+}
+
+#[test]
+fn retry_backoff_saturates_at_max_delay() {
+    let start = Instant::now();
+    let calls = Cell::new(0);
+    let policy = RetryPolicy::attempts(5)
+        .delay(Duration::from_millis(1))
+        .backoff(u32::MAX)
+        .max_delay(Duration::from_millis(5));
+    let f = retry(policy, || {
+        calls.set(calls.get() + 1);
+        err::<(), u32>(calls.get())
+    });
+    assert_eq!(f.wait(), Err(5));
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn huge_delays_dont_overflow() {
+    assert!(Delay::new(Duration::MAX).poll().is_err());
+}
+
+#[test]
+fn retry_composes_with_timeout() {
+    let policy = RetryPolicy::attempts(1000).delay(Duration::from_millis(5));
+    let f = retry(policy, || err::<(), Error>(Error::Gone)).timeout(Duration::from_millis(20));
+    assert_eq!(f.wait(), Err(Error::Timeout));
+
+    let f = loop_fn(0, |n| {
+        retry(2, move || {
+            ok::<_, Error>(if n < 3 {
+                Loop::Continue(n + 1)
+            } else {
+                Loop::Break(n)
+            })
+        })
+    });
+    assert_eq!(f.timeout(Duration::from_secs(10)).wait(), Ok(3));
+}